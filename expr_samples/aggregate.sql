@@ -0,0 +1,4 @@
+SELECT dpt, COUNT(*) AS employees, MIN(ide), MAX(nom)
+FROM project_spec/samples/employes.csv
+WHERE dpt > 2
+GROUP BY dpt;
@@ -0,0 +1,4 @@
+SELECT titre
+FROM project_spec/samples/projets.csv
+JOIN project_spec/samples/employes.csv ON ide = responsable
+WHERE ide = 38;
@@ -0,0 +1,6 @@
+SELECT titre, COUNT(*) AS members
+FROM project_spec/samples/projets.csv
+JOIN (SELECT idp AS project, ide FROM project_spec/samples/membres.csv) ON idp = project
+GROUP BY titre
+ORDER BY members DESC, titre
+LIMIT 4;
@@ -1,6 +1,7 @@
 use crate::types::*;
 use crate::optimize::*;
 use crate::eval::*;
+use crate::error::*;
 
 use std::fs::File;
 use std::fs;
@@ -21,25 +22,35 @@ struct Benchmark {
     tests: Vec<Test>
 }
 
-fn get_benchmark_from(path: String) -> Benchmark {
-    let file = File::open(path).unwrap();
+fn get_benchmark_from(path: String) -> Result<Benchmark> {
+    let file = File::open(&path).map_err(|e| EngineError::io(&path, e))?;
+    let deserializer = &mut serde_json::Deserializer::from_reader(BufReader::new(file));
 
-    serde_json::from_reader(BufReader::new(file)).unwrap()
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let location = format!("{} {}", path, e.path());
+        EngineError::parse(&location, e.into_inner())
+    })
 }
 
-fn opti_from_string(opti: String) -> Box<dyn Optimizer> {
-    match &opti[..] {
+fn opti_from_string(opti: String) -> Result<Box<dyn Optimizer>> {
+    Ok(match &opti[..] {
         "UCE" => Box::new(UnfoldComplexExpressionsOptimizer{}),
         "DLC" => Box::new(DetectLoadColumnsOptimizer{}),
         "PDS" => Box::new(PushDownSelectionsOptimizer{}),
         "APE" => Box::new(ApplyProjectionsEarlyOptimizer{}),
         "FCE" => Box::new(FoldComplexExpressionsOptimizer{}),
-        _ => panic!(format!("unknown optimization: {}", opti))
-    }
+        "FSL" => Box::new(FuseSortLimitOptimizer{}),
+        "RRD" => Box::new(RemoveRedundantDistinctOptimizer{}),
+        "CJS" => Box::new(ChooseJoinSidesOptimizer{}),
+        "ROJ" => Box::new(ReorderJoinsOptimizer{}),
+        "SCO" => Box::new(SimplifyConditionsOptimizer{}),
+        "IPR" => Box::new(InferPredicatesOptimizer{}),
+        _ => return Err(EngineError::Unsupported(format!("unknown optimization {}", opti)))
+    })
 }
 
-fn run_benchmark_on(path: String, n: Option<u128>) {
-    let benchmark = get_benchmark_from(path.clone());
+fn run_benchmark_on(path: String, n: Option<u128>) -> Result<()> {
+    let benchmark = get_benchmark_from(path.clone())?;
 
     let expression = Box::new(benchmark.input);
     let tests = benchmark.tests;
@@ -49,16 +60,16 @@ fn run_benchmark_on(path: String, n: Option<u128>) {
     println!("### Running benchmark {} ###\n", path);
 
     for test in tests {
-        let optims = test.optims.into_iter().map(|opti| opti_from_string(opti)).collect();
+        let optims = test.optims.into_iter().map(opti_from_string).collect::<Result<Vec<_>>>()?;
         let optimizer = ChainOptimizer{optimizers: optims};
 
-        let expr = optimizer.optimize(expression.clone());
+        let expr = optimizer.optimize(expression.clone())?;
 
         let mut total_time = std::time::Duration::new(0, 0);
 
         for _ in 0..nb_it {
             let time_before = Instant::now();
-            eval(expr.clone());
+            eval(*expr.clone())?;
             let time_elapsed = time_before.elapsed();
 
             total_time += time_elapsed;
@@ -66,26 +77,27 @@ fn run_benchmark_on(path: String, n: Option<u128>) {
         
         println!("{} took {:.2?} on average", test.name, total_time/(nb_it as u32));
     }
+
+    Ok(())
 }
 
-pub fn run_benchmark() {
+pub fn run_benchmark() -> Result<()> {
+    let directory = "expr_samples/benchmarks";
+
     // Get a vector of all filenaames inside "expr_samples/benchmarks"
-    let mut entries : Vec<_> = fs::read_dir("expr_samples/benchmarks").
-        unwrap()
-        .map(|res| {
-            res.map(|e| e.path())
-            .unwrap()
-            .to_str()
-            .map(|p| String::from(p))
-            .unwrap()
-        })
-        .collect();
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(directory).map_err(|e| EngineError::io(directory, e))? {
+        let path = entry.map_err(|e| EngineError::io(directory, e))?.path();
+        entries.push(path.to_string_lossy().into_owned());
+    }
 
     // Sort them
     entries.sort();
 
     // Run benchmarking on each of them
     for entry in entries {
-        run_benchmark_on(entry, Some(100));
+        run_benchmark_on(entry, Some(100))?;
     }
+
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,362 @@
+use crate::types::*;
+use crate::error::*;
+use crate::stats::*;
+use crate::eval::{compare_values, total_order};
+use chrono::Datelike;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/**
+ * The estimated result of an expression, and the work needed to compute it.
+ */
+#[derive(Debug, Clone)]
+pub struct Estimate {
+    pub rows: f64,
+    pub columns: HashMap<String, ColumnEstimate>,
+    pub cost: f64 // roughly, the number of entries handled by the operators of the expression, children included
+}
+
+#[derive(Debug, Clone)]
+pub struct ColumnEstimate {
+    pub distinct: f64,
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    pub null_fraction: f64
+}
+
+// Sélectivité des comparaisons dont on ne sait rien
+const DEFAULT_SELECTIVITY: f64 = 1.0 / 3.0;
+// Insérer une entrée dans une table de hachage coûte plus cher que d'en chercher une
+const HASH_BUILD_FACTOR: f64 = 2.0;
+
+/**
+ * Estimate the number of entries of an expression, the statistics of its columns and its cost,
+ * from the statistics of the files it reads.
+ */
+pub fn estimate(expression: &Expression) -> Result<Estimate> {
+    Ok(match expression {
+        Expression::Load(file, _) => load(file)?,
+        Expression::Empty(columns) => Estimate {
+            rows: 0.0,
+            columns: columns.iter().map(|name| (name.clone(), ColumnEstimate { distinct: 0.0, min: None, max: None, null_fraction: 0.0 })).collect(),
+            cost: 0.0
+        },
+        Expression::ReadSelectProjectRename(file, condition, old_columns, new_columns) => {
+            let file = load(file)?;
+            let rows = file.rows * selectivity(condition, &file.columns);
+            let columns = rename(project(file.columns, old_columns), old_columns, new_columns);
+
+            Estimate { rows, columns: capped(columns, rows), cost: file.cost }
+        },
+        // Une sélection sur un produit sera évaluée comme une jointure
+        Expression::Select(expression_from, condition) => match &**expression_from {
+            Expression::Product(expr1, expr2) => join(expr1, expr2, condition)?,
+            _ => {
+                let from = estimate(expression_from)?;
+                let rows = from.rows * selectivity(condition, &from.columns);
+
+                Estimate { rows, columns: capped(from.columns, rows), cost: from.cost + from.rows }
+            }
+        },
+        Expression::Project(expression_from, columns) => {
+            let from = estimate(expression_from)?;
+
+            Estimate { rows: from.rows, columns: project(from.columns, columns), cost: from.cost + from.rows }
+        },
+        Expression::Rename(expression_from, old_columns, new_columns) => {
+            let from = estimate(expression_from)?;
+
+            Estimate { columns: rename(from.columns, old_columns, new_columns), ..from }
+        },
+        Expression::Product(expr1, expr2) => {
+            let (estimate1, estimate2) = (estimate(expr1)?, estimate(expr2)?);
+            let rows = estimate1.rows * estimate2.rows;
+            let cost = estimate1.cost + estimate2.cost + rows;
+            let mut columns = estimate1.columns;
+            columns.extend(estimate2.columns);
+
+            Estimate { rows, columns, cost }
+        },
+        Expression::JoinProjectRename(expr1, expr2, condition, old_columns, new_columns) => {
+            let joined = join(expr1, expr2, condition)?;
+
+            Estimate { columns: rename(project(joined.columns, old_columns), old_columns, new_columns), ..joined }
+        },
+        Expression::Except(expr1, expr2) => {
+            let (estimate1, estimate2) = (estimate(expr1)?, estimate(expr2)?);
+            let cost = estimate1.cost + estimate2.cost + HASH_BUILD_FACTOR * estimate2.rows + estimate1.rows;
+
+            Estimate { cost, ..estimate1 }
+        },
+        Expression::Union(expr1, expr2, _) => {
+            let (estimate1, estimate2) = (estimate(expr1)?, estimate(expr2)?);
+            let (rows1, rows2) = (estimate1.rows, estimate2.rows);
+            let columns = estimate1.columns.into_iter().map(|(name, column1)| {
+                let column = match estimate2.columns.get(&name) {
+                    Some(column2) => union(&column1, column2, rows1, rows2),
+                    None => column1
+                };
+                (name, column)
+            }).collect();
+
+            Estimate { rows: rows1 + rows2, columns, cost: estimate1.cost + estimate2.cost }
+        },
+        Expression::Distinct(expression_from) => {
+            let from = estimate(expression_from)?;
+            let rows = from.rows.min(distinct_combinations(from.columns.values()));
+
+            Estimate { rows, columns: from.columns, cost: from.cost + from.rows }
+        },
+        Expression::Aggregate(expression_from, group_by, aggregates) => {
+            let from = estimate(expression_from)?;
+            let rows = if group_by.is_empty() {
+                1.0
+            } else {
+                from.rows.min(distinct_combinations(group_by.iter().filter_map(|column| from.columns.get(column))))
+            };
+
+            let mut columns = project(from.columns, group_by);
+            for (_, _, name) in aggregates {
+                columns.insert(name.clone(), ColumnEstimate { distinct: rows, min: None, max: None, null_fraction: 0.0 });
+            }
+
+            Estimate { rows, columns, cost: from.cost + from.rows }
+        },
+        Expression::Sort(expression_from, _) => {
+            let from = estimate(expression_from)?;
+
+            Estimate { cost: from.cost + from.rows * from.rows.max(2.0).log2(), ..from }
+        },
+        Expression::Limit(expression_from, limit, offset) => {
+            let from = estimate(expression_from)?;
+            let rows = (from.rows - *offset as f64).max(0.0).min(*limit as f64);
+
+            Estimate { rows, columns: capped(from.columns, rows), cost: from.cost }
+        },
+        Expression::TopN(expression_from, _, limit, offset) => {
+            let from = estimate(expression_from)?;
+            let rows = (from.rows - *offset as f64).max(0.0).min(*limit as f64);
+            let cost = from.cost + from.rows * ((limit + offset) as f64).max(2.0).log2();
+
+            Estimate { rows, columns: capped(from.columns, rows), cost }
+        }
+    })
+}
+
+/**
+ * Among equivalent plans, the one with the lowest estimated cost; the first one on ties.
+ */
+pub fn cheapest(candidates: Vec<Expression>) -> Result<Box<Expression>> {
+    let mut best: Option<(f64, Expression)> = None;
+
+    for candidate in candidates {
+        let cost = estimate(&candidate)?.cost;
+        if best.as_ref().is_none_or(|(best_cost, _)| cost < *best_cost) {
+            best = Some((cost, candidate));
+        }
+    }
+
+    best.map(|(_, expression)| Box::new(expression)).ok_or_else(|| EngineError::Unsupported(String::from("no plan to choose from")))
+}
+
+fn load(file: &CsvFile) -> Result<Estimate> {
+    let stats = table_stats(file)?;
+    let rows = stats.rows as f64;
+    let columns = stats.columns.iter().map(|(name, column)| (name.clone(), ColumnEstimate {
+        distinct: column.distinct as f64,
+        min: column.min.clone(),
+        max: column.max.clone(),
+        null_fraction: if stats.rows == 0 { 0.0 } else { column.nulls as f64 / rows }
+    })).collect();
+
+    Ok(Estimate { rows, columns, cost: rows })
+}
+
+fn join(expr1: &Expression, expr2: &Expression, condition: &Condition) -> Result<Estimate> {
+    let (estimate1, estimate2) = (estimate(expr1)?, estimate(expr2)?);
+    let join_cost = join_cost(condition, &estimate1, &estimate2);
+    let mut columns = estimate1.columns;
+    columns.extend(estimate2.columns);
+    let rows = estimate1.rows * estimate2.rows * selectivity(condition, &columns);
+
+    Ok(Estimate { rows, columns: capped(columns, rows), cost: estimate1.cost + estimate2.cost + join_cost + rows })
+}
+
+/**
+ * The cost of matching the entries of both sides of a join, according to the algorithm its condition allows.
+ */
+fn join_cost(condition: &Condition, estimate1: &Estimate, estimate2: &Estimate) -> f64 {
+    let (equalities, ranges) = conditions_between(condition, estimate1, estimate2);
+    let (rows1, rows2) = (estimate1.rows, estimate2.rows);
+
+    if equalities > 0 && ranges == 0 {
+        // Le côté gauche est rangé dans la table de hachage, le droit la parcourt
+        HASH_BUILD_FACTOR * rows1 + rows2
+    } else if ranges > 0 {
+        rows1 * rows1.max(2.0).log2() + rows2 * rows2.max(2.0).log2()
+    } else {
+        rows1 * rows2
+    }
+}
+
+/**
+ * Count the equalities and the other comparisons between a column of each side in the conjuncts of a condition.
+ */
+fn conditions_between(condition: &Condition, estimate1: &Estimate, estimate2: &Estimate) -> (usize, usize) {
+    let between = |v1: &Value, v2: &Value| match (v1, v2) {
+        (Value::Column(c1), Value::Column(c2)) =>
+            (estimate1.columns.contains_key(c1) && estimate2.columns.contains_key(c2)) || (estimate2.columns.contains_key(c1) && estimate1.columns.contains_key(c2)),
+        _ => false
+    };
+
+    match condition {
+        Condition::And(c1, c2) => {
+            let (equalities1, ranges1) = conditions_between(c1, estimate1, estimate2);
+            let (equalities2, ranges2) = conditions_between(c2, estimate1, estimate2);
+            (equalities1 + equalities2, ranges1 + ranges2)
+        },
+        Condition::Equal(v1, v2) if between(v1, v2) => (1, 0),
+        Condition::Less(v1, v2) | Condition::LessEq(v1, v2) | Condition::More(v1, v2) | Condition::MoreEq(v1, v2) if between(v1, v2) => (0, 1),
+        _ => (0, 0)
+    }
+}
+
+/**
+ * The estimated fraction of entries satisfying a condition.
+ */
+pub fn selectivity(condition: &Condition, columns: &HashMap<String, ColumnEstimate>) -> f64 {
+    match condition {
+        Condition::True => 1.0,
+        Condition::False => 0.0,
+        Condition::Not(c) => 1.0 - selectivity(c, columns),
+        Condition::And(c1, c2) => selectivity(c1, columns) * selectivity(c2, columns),
+        Condition::Or(c1, c2) => {
+            let (s1, s2) = (selectivity(c1, columns), selectivity(c2, columns));
+            s1 + s2 - s1 * s2
+        },
+        Condition::Equal(v1, v2) => equality(v1, v2, columns),
+        Condition::NotEqual(v1, v2) => 1.0 - equality(v1, v2, columns),
+        Condition::Less(v1, v2) | Condition::LessEq(v1, v2) => below(v1, v2, columns),
+        Condition::More(v1, v2) | Condition::MoreEq(v1, v2) => below(v2, v1, columns),
+        Condition::IsNull(v) => match operand(v, columns) {
+            Operand::Column(column) => column.null_fraction,
+            Operand::Constant(constant) => if let Value::Null = constant { 1.0 } else { 0.0 },
+            Operand::Unknown => DEFAULT_SELECTIVITY
+        }
+    }
+}
+
+enum Operand<'a> {
+    Column(&'a ColumnEstimate),
+    Constant(&'a Value),
+    Unknown
+}
+
+fn operand<'a>(value: &'a Value, columns: &'a HashMap<String, ColumnEstimate>) -> Operand<'a> {
+    match value {
+        Value::Column(name) => columns.get(name).map(Operand::Column).unwrap_or(Operand::Unknown),
+        constant => Operand::Constant(constant)
+    }
+}
+
+fn equality(v1: &Value, v2: &Value, columns: &HashMap<String, ColumnEstimate>) -> f64 {
+    match (operand(v1, columns), operand(v2, columns)) {
+        (Operand::Column(c1), Operand::Column(c2)) =>
+            (1.0 - c1.null_fraction) * (1.0 - c2.null_fraction) / c1.distinct.max(c2.distinct).max(1.0),
+        (Operand::Column(column), Operand::Constant(constant)) | (Operand::Constant(constant), Operand::Column(column)) => {
+            if outside(column, constant) {
+                0.0
+            } else {
+                (1.0 - column.null_fraction) / column.distinct.max(1.0)
+            }
+        },
+        (Operand::Constant(Value::Null), _) | (_, Operand::Constant(Value::Null)) => 0.0,
+        (Operand::Constant(c1), Operand::Constant(c2)) => if c1 == c2 { 1.0 } else { 0.0 },
+        _ => DEFAULT_SELECTIVITY
+    }
+}
+
+/**
+ * The estimated fraction of entries for which v1 is below v2, interpolating between the bounds of numeric and date columns.
+ */
+fn below(v1: &Value, v2: &Value, columns: &HashMap<String, ColumnEstimate>) -> f64 {
+    match (operand(v1, columns), operand(v2, columns)) {
+        (Operand::Column(column), Operand::Constant(k)) =>
+            fraction_below(column, k).map(|fraction| fraction * (1.0 - column.null_fraction)).unwrap_or(DEFAULT_SELECTIVITY),
+        (Operand::Constant(k), Operand::Column(column)) =>
+            fraction_below(column, k).map(|fraction| (1.0 - fraction) * (1.0 - column.null_fraction)).unwrap_or(DEFAULT_SELECTIVITY),
+        _ => DEFAULT_SELECTIVITY
+    }
+}
+
+/**
+ * The position of a value on a line, for the values that can be interpolated: numbers, and dates as days.
+ */
+fn position(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(i) => Some(*i as f64),
+        Value::Float(x) => Some(*x),
+        Value::Date(date) => Some(date.num_days_from_ce() as f64),
+        _ => None
+    }
+}
+
+/**
+ * The fraction of the values of a column below k, when k and its bounds can be interpolated.
+ */
+fn fraction_below(column: &ColumnEstimate, k: &Value) -> Option<f64> {
+    // Les dates ne sont pas comparables aux nombres
+    compare_values(k, column.min.as_ref()?)?;
+    compare_values(k, column.max.as_ref()?)?;
+    let (k, min, max) = (position(k)?, position(column.min.as_ref()?)?, position(column.max.as_ref()?)?);
+
+    if max > min {
+        Some(((k - min) / (max - min)).clamp(0.0, 1.0))
+    } else {
+        Some(if k > min { 1.0 } else { 0.0 })
+    }
+}
+
+fn outside(column: &ColumnEstimate, constant: &Value) -> bool {
+    let below_min = column.min.as_ref().is_some_and(|min| total_order(constant, min) == Ordering::Less);
+    let above_max = column.max.as_ref().is_some_and(|max| total_order(constant, max) == Ordering::Greater);
+
+    below_min || above_max
+}
+
+fn project(columns: HashMap<String, ColumnEstimate>, kept: &[String]) -> HashMap<String, ColumnEstimate> {
+    columns.into_iter().filter(|(name, _)| kept.contains(name)).collect()
+}
+
+fn rename(columns: HashMap<String, ColumnEstimate>, old_columns: &[String], new_columns: &[String]) -> HashMap<String, ColumnEstimate> {
+    columns.into_iter().map(|(name, column)| match old_columns.iter().position(|old| *old == name) {
+        Some(i) => (new_columns[i].clone(), column),
+        None => (name, column)
+    }).collect()
+}
+
+/**
+ * A column cannot have more distinct values than there are entries.
+ */
+fn capped(columns: HashMap<String, ColumnEstimate>, rows: f64) -> HashMap<String, ColumnEstimate> {
+    columns.into_iter().map(|(name, column)| (name, ColumnEstimate { distinct: column.distinct.min(rows), ..column })).collect()
+}
+
+fn distinct_combinations<'a>(columns: impl Iterator<Item = &'a ColumnEstimate>) -> f64 {
+    columns.map(|column| column.distinct.max(1.0)).product()
+}
+
+fn union(column1: &ColumnEstimate, column2: &ColumnEstimate, rows1: f64, rows2: f64) -> ColumnEstimate {
+    let bound = |v1: &Option<Value>, v2: &Option<Value>, wanted: Ordering| match (v1, v2) {
+        (Some(v1), Some(v2)) => Some(if total_order(v2, v1) == wanted { v2.clone() } else { v1.clone() }),
+        _ => None
+    };
+    let rows = (rows1 + rows2).max(1.0);
+
+    ColumnEstimate {
+        distinct: column1.distinct + column2.distinct,
+        min: bound(&column1.min, &column2.min, Ordering::Less),
+        max: bound(&column1.max, &column2.max, Ordering::Greater),
+        null_fraction: (column1.null_fraction * rows1 + column2.null_fraction * rows2) / rows
+    }
+}
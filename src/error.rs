@@ -0,0 +1,69 @@
+use std::fmt;
+
+/**
+ * Everything that can go wrong while parsing, optimizing or evaluating a query.
+ */
+#[derive(Debug)]
+pub enum EngineError {
+    Io { path: String, message: String }, // a file that cannot be read or written
+    Parse { location: String, message: String }, // location is a JSON path, or a position in a SQL query
+    UnknownColumn { column: String, context: String },
+    SchemaMismatch(String),
+    Type(String),
+    Unsupported(String), // an expression that a pass does not know how to handle
+    Invalid(Vec<EngineError>) // all the problems found while validating a query
+}
+
+pub type Result<T> = std::result::Result<T, EngineError>;
+
+impl EngineError {
+    pub fn io(path: &str, error: impl fmt::Display) -> EngineError {
+        EngineError::Io { path: String::from(path), message: error.to_string() }
+    }
+
+    pub fn parse(location: &str, message: impl fmt::Display) -> EngineError {
+        EngineError::Parse { location: String::from(location), message: message.to_string() }
+    }
+
+    pub fn unknown_column(column: &str, context: &str) -> EngineError {
+        EngineError::UnknownColumn { column: String::from(column), context: String::from(context) }
+    }
+
+    /**
+     * The code the program exits with when it stops because of this error.
+     */
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            EngineError::Io { .. } => 2,
+            EngineError::Parse { .. } => 3,
+            EngineError::UnknownColumn { .. } => 4,
+            EngineError::SchemaMismatch(_) => 5,
+            EngineError::Type(_) => 6,
+            EngineError::Unsupported(_) => 7,
+            // On garde le code du premier problème rencontré
+            EngineError::Invalid(errors) => errors.first().map(|e| e.exit_code()).unwrap_or(1)
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineError::Io { path, message } => write!(f, "cannot access {}: {}", path, message),
+            EngineError::Parse { location, message } => write!(f, "parse error at {}: {}", location, message),
+            EngineError::UnknownColumn { column, context } => write!(f, "unknown column \"{}\" in {}", column, context),
+            EngineError::SchemaMismatch(message) => write!(f, "schema mismatch: {}", message),
+            EngineError::Type(message) => write!(f, "type error: {}", message),
+            EngineError::Unsupported(message) => write!(f, "unsupported expression: {}", message),
+            EngineError::Invalid(errors) => {
+                write!(f, "invalid query, {} problem(s) found", errors.len())?;
+                for error in errors {
+                    write!(f, "; {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
@@ -1,237 +1,888 @@
 use crate::types::*;
+use crate::error::*;
+use crate::spill::*;
+use crate::simplify::simplify;
+use crate::input;
+use chrono::NaiveDate;
 use csv::Reader;
-use std::collections::{HashMap,HashSet};
+use std::collections::{HashMap,HashSet,BinaryHeap};
+use std::cmp::Reverse;
+use std::cmp::Ordering;
+use std::cell::RefCell;
+use std::fs::File;
+use std::time::{Duration, Instant};
 
+/**
+ * A lazy stream of entries: each operator pulls the entries it needs from its children, one at a time.
+ */
+pub type Rows = Box<dyn Iterator<Item = Result<Entry>>>;
 
-pub fn eval(expression: Box<Expression>) -> Table {
-    match *expression {
-        Expression::Select(expression_from, condition) => select(expression_from, condition),
-        Expression::Project(expression_from, columns) => project(expression_from, columns),
-        Expression::Product(expr1, expr2) => product(expr1, expr2),
-        Expression::Except(expr1, expr2) => minus(expr1, expr2),
-        Expression::Union(expr1, expr2) => union(expr1, expr2),
-        Expression::Rename(expression, old_columns, new_columns) => renaming(expression, old_columns, new_columns),
-        Expression::ReadSelectProjectRename(filename, condition, old_attrs, new_attrs) => read_select_project_rename(filename, condition, old_attrs, new_attrs),
-        Expression::JoinProjectRename(expr1, expr2, condition, old_attrs, new_attrs) => join_project_rename(expr1, expr2, condition, old_attrs, new_attrs),
-        Expression::Load(filename, _) => read(filename),
+/**
+ * The columns of an expression, along with the stream of its entries.
+ */
+pub type Stream = (HashMap<String, usize>, Rows);
+
+/**
+ * Number of entries produced by an expression, and the time spent pulling them, children included.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct NodeStats {
+    pub rows: usize,
+    pub elapsed: Duration
+}
+
+thread_local! {
+    // Quand on analyse une requête, on y range les statistiques de chaque noeud compilé, dans l'ordre préfixe
+    static PROFILE: RefCell<Option<Vec<NodeStats>>> = const { RefCell::new(None) };
+}
+
+/**
+ * Evaluate an expression, also returning the statistics of each of its nodes, in prefix order.
+ */
+pub fn eval_analyzed(expression: Expression) -> Result<(Table, Vec<NodeStats>)> {
+    PROFILE.with(|profile| *profile.borrow_mut() = Some(Vec::new()));
+    let table = eval(expression);
+    let stats = PROFILE.with(|profile| profile.borrow_mut().take()).unwrap_or_default();
+
+    Ok((table?, stats))
+}
+
+/**
+ * Evaluate an expression, pulling all its entries into a table.
+ */
+pub fn eval(expression: Expression) -> Result<Table> {
+    let (column_names, rows) = compile(expression)?;
+
+    Ok((column_names, rows.collect::<Result<Vec<_>>>()?))
+}
+
+/**
+ * Compile an expression into a pipeline of operators.
+ *
+ * Nothing is read before the first entry is pulled. Selections, projections, renamings, unions, limits and
+ * the probe side of joins stream their entries; only the build side of joins and blocking operators
+ * (sort, aggregate, top-n) keep their input in memory.
+ */
+pub fn compile(expression: Expression) -> Result<Stream> {
+    // On réserve la place du noeud avant de compiler ses enfants, pour garder l'ordre préfixe
+    let position = PROFILE.with(|profile| profile.borrow_mut().as_mut().map(|stats| {
+        stats.push(NodeStats { rows: 0, elapsed: Duration::ZERO });
+        stats.len() - 1
+    }));
+
+    let (column_names, rows) = compile_node(expression)?;
+    match position {
+        None => Ok((column_names, rows)),
+        Some(position) => Ok((column_names, Box::new(Profiled { rows, position })))
     }
 }
 
-fn select(expression: Box<Expression>, condition: Box<Condition>) -> Table {
-    let (column_names, entries) = eval(expression);
-    let new_entries: Vec<Entry> = entries.into_iter().filter(
-        |entry| eval_condition(entry, &column_names, &condition)
-    ).collect();
+fn compile_node(expression: Expression) -> Result<Stream> {
+    match expression {
+        Expression::Select(expression_from, condition) => select(*expression_from, condition),
+        Expression::Project(expression_from, columns) => project(*expression_from, columns),
+        Expression::Product(expr1, expr2) => product(*expr1, *expr2),
+        Expression::Except(expr1, expr2) => minus(*expr1, *expr2),
+        Expression::Union(expr1, expr2, matching) => union(*expr1, *expr2, matching),
+        Expression::Rename(expression, old_columns, new_columns) => renaming(*expression, old_columns, new_columns),
+        Expression::ReadSelectProjectRename(file, condition, old_attrs, new_attrs) => read_select_project_rename(file, condition, old_attrs, new_attrs),
+        Expression::JoinProjectRename(expr1, expr2, condition, old_attrs, new_attrs) => join_project_rename(*expr1, *expr2, condition, old_attrs, new_attrs),
+        Expression::Aggregate(expression, group_by, aggregates) => aggregate(*expression, group_by, aggregates),
+        Expression::Sort(expression, keys) => sort(*expression, keys),
+        Expression::Limit(expression, limit, offset) => limit_offset(*expression, limit, offset),
+        Expression::TopN(expression, keys, limit, offset) => top_n(*expression, keys, limit, offset),
+        Expression::Distinct(expression) => distinct(*expression),
+        Expression::Empty(columns) => Ok((columns.into_iter().enumerate().map(|(i, column)| (column, i)).collect(), Box::new(std::iter::empty()))),
+        Expression::Load(file, _) => read(file),
+    }
+}
 
-    (column_names, new_entries)
+/**
+ * Counts the entries pulled through a node, and the time spent doing so.
+ */
+struct Profiled {
+    rows: Rows,
+    position: usize
 }
 
-fn swaps_for_projection(column_names: &HashMap<String, usize>, columns: &Vec<String>) -> (Vec<(usize, usize)>, HashMap<String, usize>) {
+impl Iterator for Profiled {
+    type Item = Result<Entry>;
 
-    let mut final_columns: HashMap<String, usize> = HashMap::new();
+    fn next(&mut self) -> Option<Result<Entry>> {
+        let start = Instant::now();
+        let row = self.rows.next();
+        let elapsed = start.elapsed();
 
-    // On regarde dans les premières positions celles qui peuvent être utilisées
-    let mut can_be_used = vec![true; columns.len()];
-    for column in columns.iter() {
-        let index = *column_names.get(column).unwrap();
-        if index < columns.len() {
-            can_be_used[index] = false;
-            final_columns.insert(column.clone(), index);
-        }
+        PROFILE.with(|profile| if let Some(stats) = profile.borrow_mut().as_mut() {
+            stats[self.position].elapsed += elapsed;
+            if let Some(Ok(_)) = row {
+                stats[self.position].rows += 1;
+            }
+        });
+
+        row
     }
+}
 
-    let mut swaps = Vec::new();
+/**
+ * Entries computed all at once, when the first one is pulled.
+ */
+fn blocking(compute: impl FnOnce() -> Result<Vec<Entry>> + 'static) -> Rows {
+    Box::new(std::iter::once_with(compute).flat_map(|result| {
+        let (entries, error) = match result {
+            Ok(entries) => (entries, None),
+            Err(e) => (Vec::new(), Some(e))
+        };
 
-    // on va associer tout ça comme il faut
-    let mut i = 0;
-    for column in columns.iter() {
-        let index = *column_names.get(column).unwrap();
-        if index < columns.len() {
-            continue; // on est déjà bon, rien à faire
-        }
+        entries.into_iter().map(Ok).chain(error.map(Err))
+    }))
+}
+
+/**
+ * Entries of a stream only built when the first one is pulled.
+ */
+fn deferred(compute: impl FnOnce() -> Result<Rows> + 'static) -> Rows {
+    Box::new(std::iter::once_with(compute).flat_map(|result| match result {
+        Ok(rows) => rows,
+        Err(e) => Box::new(std::iter::once(Err(e)))
+    }))
+}
 
-        // sinon, on cherche une nouvelle position
-        while !can_be_used[i] {
-            i += 1;
+/**
+ * Streams the probe entries through probe_row, once the build side has been materialized.
+ * The build side is only computed when the first entry is pulled.
+ */
+struct BuildProbe<B, F> {
+    build: Option<Box<dyn FnOnce() -> Result<B>>>,
+    built: Option<B>,
+    probe: Rows,
+    probe_row: F,
+    pending: std::vec::IntoIter<Entry>
+}
+
+impl<B, F: FnMut(&B, Entry) -> Result<Vec<Entry>>> Iterator for BuildProbe<B, F> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Result<Entry>> {
+        if let Some(build) = self.build.take() {
+            match build() {
+                Ok(built) => self.built = Some(built),
+                Err(e) => return Some(Err(e))
+            }
         }
+        // Après une erreur de construction, il n'y a plus rien à produire
+        let built = self.built.as_ref()?;
 
-        swaps.push((index, i));
-        final_columns.insert(column.clone(), i);
-        i += 1;
+        loop {
+            if let Some(entry) = self.pending.next() {
+                return Some(Ok(entry));
+            }
+
+            match self.probe.next()? {
+                Ok(entry) => match (self.probe_row)(built, entry) {
+                    Ok(entries) => self.pending = entries.into_iter(),
+                    Err(e) => return Some(Err(e))
+                },
+                Err(e) => return Some(Err(e))
+            }
+        }
     }
+}
 
-    (swaps, final_columns)
+fn build_and_probe<B: 'static>(build: impl FnOnce() -> Result<B> + 'static, probe: Rows, probe_row: impl FnMut(&B, Entry) -> Result<Vec<Entry>> + 'static) -> Rows {
+    Box::new(BuildProbe { build: Some(Box::new(build)), built: None, probe, probe_row, pending: Vec::new().into_iter() })
 }
 
-fn project(expression: Box<Expression>, columns: Vec<String>) -> Table {
-    let (column_names, mut entries) = eval(expression);
+fn select(expression: Expression, condition: Box<Condition>) -> Result<Stream> {
+    let (column_names, rows) = compile(expression)?;
+    let rows = filter_rows(rows, column_names.clone(), condition);
 
-    let (swaps, final_columns) = swaps_for_projection(&column_names, &columns);
+    Ok((column_names, rows))
+}
 
-    for entry in &mut entries {
-        for (i, j) in &swaps {
-            entry.swap(*i, *j);
+/**
+ * Keep the entries satisfying the condition.
+ */
+fn filter_rows(rows: Rows, column_names: HashMap<String, usize>, condition: Box<Condition>) -> Rows {
+    Box::new(rows.filter_map(move |row| {
+        match row.and_then(|entry| Ok((eval_condition(&entry, &column_names, &condition)?, entry))) {
+            // Une condition inconnue (comparant NULL) ne retient pas l'entrée
+            Ok((Some(true), entry)) => Some(Ok(entry)),
+            Ok((_, _)) => None,
+            Err(e) => Some(Err(e))
         }
-        entry.truncate(columns.len())
-    }
+    }))
+}
 
-    (final_columns, entries)
+/**
+ * Find the position of a column, context describing where it is used in case of error.
+ */
+fn column_index(column_names: &HashMap<String, usize>, column: &str, context: &str) -> Result<usize> {
+    column_names.get(column).copied().ok_or_else(|| EngineError::unknown_column(column, context))
 }
 
-fn product(expression1: Box<Expression>, expression2: Box<Expression>) -> Table {
-    let (column_names1, entries1) = eval(expression1);
-    let (column_names2, entries2) = eval(expression2);
+/**
+ * Positions in the entries of the projected columns, and the columns of the projection, in the given order.
+ */
+fn projection_indexes(column_names: &HashMap<String, usize>, columns: &[String]) -> Result<(Vec<usize>, HashMap<String, usize>)> {
+    let indexes = columns.iter().map(|column| column_index(column_names, column, "projection")).collect::<Result<Vec<_>>>()?;
+    let final_columns = columns.iter().enumerate().map(|(i, column)| (column.clone(), i)).collect();
 
-    let mut final_entries: Vec<Entry> = Vec::new();
+    Ok((indexes, final_columns))
+}
 
-    for entry1 in entries1 {
-        for entry2 in &entries2 {
-            let mut entry = entry1.clone();
-            entry.append(&mut entry2.clone());
+fn project(expression: Expression, columns: Vec<String>) -> Result<Stream> {
+    let (column_names, rows) = compile(expression)?;
 
-            final_entries.push(entry);
-        }
+    let (indexes, final_columns) = projection_indexes(&column_names, &columns)?;
+
+    Ok((final_columns, project_rows(rows, indexes)))
+}
+
+/**
+ * Keep the values at the given positions in each entry, in that order.
+ */
+fn project_rows(rows: Rows, indexes: Vec<usize>) -> Rows {
+    // Quand les colonnes gardées sont déjà les premières, il suffit de couper les entrées
+    if indexes.iter().enumerate().all(|(i, index)| i == *index) {
+        let len = indexes.len();
+        return Box::new(rows.map(move |row| row.map(|mut entry| {
+            entry.truncate(len);
+            entry
+        })));
     }
 
+    // Sinon, on déplace les valeurs plutôt que de les copier, sauf si une colonne est gardée plusieurs fois
+    let unique = indexes.iter().collect::<HashSet<_>>().len() == indexes.len();
+    Box::new(rows.map(move |row| row.map(|mut entry| {
+        indexes.iter().map(|index| if unique { std::mem::replace(&mut entry[*index], Value::Int(0)) } else { entry[*index].clone() }).collect()
+    })))
+}
+
+/**
+ * Columns of the concatenation of entries of both sides.
+ */
+fn concatenated_columns(column_names1: &HashMap<String, usize>, column_names2: &HashMap<String, usize>) -> HashMap<String, usize> {
     let mut final_columns = HashMap::new();
     for (key, value) in column_names2 {
-        final_columns.insert(key, column_names1.len()+value);
+        final_columns.insert(key.clone(), column_names1.len()+value);
     }
     for (key, value) in column_names1 {
-        final_columns.insert(key, value);
+        final_columns.insert(key.clone(), *value);
     }
 
-    (final_columns, final_entries)
+    final_columns
 }
 
-fn renaming(expression: Box<Expression>, old_columns: Vec<String>, new_columns: Vec<String>) -> Table {
-    let (mut column_names, entries) = eval(expression);
-    rename_columns(&mut column_names, old_columns, new_columns);
+fn product(expression1: Expression, expression2: Expression) -> Result<Stream> {
+    let (column_names1, rows1) = compile(expression1)?;
+    let (column_names2, rows2) = compile(expression2)?;
+    let final_columns = concatenated_columns(&column_names1, &column_names2);
+
+    Ok((final_columns, nested_loop(rows1, rows2)))
+}
 
-    (column_names, entries)
+fn concatenate(entry1: &Entry, entry2: &Entry) -> Entry {
+    let mut entry = entry1.clone();
+    entry.extend(entry2.iter().cloned());
+
+    entry
+}
+
+/**
+ * Product of two streams, each entry of the left side being followed by an entry of the right side.
+ *
+ * The right side is kept in memory when it fits in the memory limit, and the left side is streamed.
+ * Otherwise, the right side is written in a temporary file, which is read again for each block
+ * of left entries fitting in memory (block nested loop).
+ */
+fn nested_loop(mut left: Rows, mut right: Rows) -> Rows {
+    deferred(move || {
+        let (entries2, exhausted) = fill(&mut right)?;
+        if exhausted {
+            return Ok(build_and_probe(
+                move || Ok(entries2),
+                left,
+                |entries2, entry1| Ok(entries2.iter().map(|entry2| concatenate(&entry1, entry2)).collect())
+            ));
+        }
+
+        let mut spilled = SpillFile::from_rows(entries2.into_iter().map(Ok).chain(right))?;
+        let blocks = std::iter::from_fn(move || match fill(&mut left) {
+            Ok((block, _)) if block.is_empty() => None,
+            block => Some(block)
+        });
+
+        Ok(Box::new(blocks.flat_map(move |block| {
+            let rows2 = spilled.rows();
+            deferred(move || {
+                let (block, rows2) = (block?.0, rows2?);
+
+                Ok(Box::new(rows2.flat_map(move |row2| match row2 {
+                    Ok(entry2) => block.iter().map(|entry1| Ok(concatenate(entry1, &entry2))).collect(),
+                    Err(e) => vec![Err(e)]
+                })))
+            })
+        })))
+    })
 }
 
-fn rename_columns(column_names: &mut HashMap<String, usize>, old_columns: Vec<String>, new_columns: Vec<String>) {
-    for (i, new_column) in new_columns.into_iter().enumerate() {
-        let index = column_names.remove(&old_columns[i]).unwrap();
+/**
+ * How the probe entries of a hash join are matched against the build entries.
+ */
+#[derive(Clone, Copy)]
+enum JoinKind {
+    Inner, // each build entry with the same key, followed by the probe entry
+    Anti // the probe entries whose key is not on the build side
+}
+
+const PARTITIONS: usize = 16;
+// Au-delà, une partition trop grosse (beaucoup d'entrées avec la même clé) est gardée en mémoire
+const MAX_PARTITION_DEPTH: usize = 3;
+
+fn key_of(entry: &Entry, key: &[usize]) -> Vec<Value> {
+    key.iter().map(|index| entry[*index].clone()).collect()
+}
+
+/**
+ * Hash join of two streams, on the columns at build_key and probe_key.
+ *
+ * The build side is kept in buckets when it fits in the memory limit, and the probe side is streamed.
+ * Otherwise, both sides are partitioned in temporary files by the hash of their key (grace hash join),
+ * then each pair of partitions is joined in turn.
+ */
+fn hash_join(mut build: Rows, probe: Rows, build_key: Vec<usize>, probe_key: Vec<usize>, kind: JoinKind, depth: usize) -> Rows {
+    deferred(move || {
+        let (entries, exhausted) = fill(&mut build)?;
+
+        if exhausted || depth >= MAX_PARTITION_DEPTH {
+            let mut buckets: HashMap<Vec<Value>, Vec<Entry>> = HashMap::new();
+            for row in entries.into_iter().map(Ok).chain(build) {
+                let entry = row?;
+                let key = key_of(&entry, &build_key);
+                if let JoinKind::Inner = kind {
+                    if !key.contains(&Value::Null) {
+                        buckets.entry(key).or_default().push(entry);
+                    }
+                } else {
+                    buckets.entry(key).or_default();
+                }
+            }
+
+            return Ok(build_and_probe(move || Ok(buckets), probe, move |buckets, entry2| {
+                // NULL n'est égal à rien dans une jointure, mais minus compare les entrées entières et les retire
+                let matching = buckets.get(&key_of(&entry2, &probe_key));
+
+                Ok(match kind {
+                    JoinKind::Inner => matching.map(|entries| entries.iter().map(|entry1| concatenate(entry1, &entry2)).collect()).unwrap_or_default(),
+                    JoinKind::Anti if matching.is_some() => Vec::new(),
+                    JoinKind::Anti => vec![entry2]
+                })
+            }));
+        }
+
+        // Les entrées de même clé tombent dans la même partition des deux côtés
+        let build_partitions = partition(entries.into_iter().map(Ok).chain(build), &build_key, depth, PARTITIONS)?;
+        let probe_partitions = partition(probe, &probe_key, depth, PARTITIONS)?;
+
+        Ok(Box::new(build_partitions.into_iter().zip(probe_partitions).flat_map(move |(build_partition, probe_partition)| {
+            let (build_key, probe_key) = (build_key.clone(), probe_key.clone());
+            deferred(move || Ok(hash_join(build_partition.into_rows()?, probe_partition.into_rows()?, build_key, probe_key, kind, depth + 1)))
+        })))
+    })
+}
+
+fn renaming(expression: Expression, old_columns: Vec<String>, new_columns: Vec<String>) -> Result<Stream> {
+    let (mut column_names, rows) = compile(expression)?;
+    rename_columns(&mut column_names, old_columns, new_columns)?;
+
+    Ok((column_names, rows))
+}
+
+fn rename_columns(column_names: &mut HashMap<String, usize>, old_columns: Vec<String>, new_columns: Vec<String>) -> Result<()> {
+    if old_columns.len() != new_columns.len() {
+        return Err(EngineError::SchemaMismatch(format!("renaming {} columns into {} names", old_columns.len(), new_columns.len())));
+    }
+
+    // On retire tout avant de réinsérer, pour permettre d'échanger des noms
+    let mut indexes = Vec::new();
+    for old_column in &old_columns {
+        indexes.push(column_names.remove(old_column).ok_or_else(|| EngineError::unknown_column(old_column, "renaming"))?);
+    }
+    for (new_column, index) in new_columns.into_iter().zip(indexes) {
         column_names.insert(new_column, index);
     }
+
+    Ok(())
 }
 
-fn minus(expression1: Box<Expression>, expression2: Box<Expression>) -> Table {
-    let (column_names1, entries1) = eval(expression1);
-    let (_column_names2, entries2) = eval(expression2);
+/**
+ * Check that both tables have the same set of columns, naming the mismatching ones otherwise.
+ */
+fn check_compatible_schemas(operation: &str, column_names1: &HashMap<String, usize>, column_names2: &HashMap<String, usize>) -> Result<()> {
+    let mut only_left = column_names1.keys().filter(|column| !column_names2.contains_key(*column)).cloned().collect::<Vec<_>>();
+    let mut only_right = column_names2.keys().filter(|column| !column_names1.contains_key(*column)).cloned().collect::<Vec<_>>();
 
-    let new_entries = entries1.into_iter().filter(
-        |entry1| entries2.iter().all(|entry2| !(*entry1 == *entry2))
-    ).collect();
+    if !only_left.is_empty() || !only_right.is_empty() {
+        only_left.sort();
+        only_right.sort();
+        return Err(EngineError::SchemaMismatch(format!("{}: columns {:?} only appear on the left and columns {:?} only on the right", operation, only_left, only_right)));
+    }
+
+    Ok(())
+}
+
+/**
+ * Compute, for each position of the first table, the position of the column with the same name in the second table.
+ */
+fn alignment(column_names1: &HashMap<String, usize>, column_names2: &HashMap<String, usize>) -> Vec<usize> {
+    let mut positions = vec![0; column_names1.len()];
+    for (column, index) in column_names1 {
+        positions[*index] = column_names2[column];
+    }
 
-    (column_names1, new_entries)
+    positions
 }
 
-fn union(expression1: Box<Expression>, expression2: Box<Expression>) -> Table {
-    let (column_names1, mut entries1) = eval(expression1);
-    let (mut column_names2, entries2) = eval(expression2);
+fn minus(expression1: Expression, expression2: Expression) -> Result<Stream> {
+    let (column_names1, rows1) = compile(expression1)?;
+    let (column_names2, rows2) = compile(expression2)?;
+    check_compatible_schemas("minus", &column_names1, &column_names2)?;
+
+    // Les entrées de droite sont remises dans l'ordre des colonnes de gauche, et servent de clé entière
+    let positions = alignment(&column_names1, &column_names2);
+    let rows2 = Box::new(rows2.map(move |row| row.map(
+        |entry2| positions.iter().map(|position| entry2[*position].clone()).collect::<Entry>()
+    )));
+    let key = (0..column_names1.len()).collect::<Vec<_>>();
+    let rows = hash_join(rows2, rows1, key.clone(), key, JoinKind::Anti, 0);
+
+    Ok((column_names1, rows))
+}
 
-    let mut columns = vec!["".to_string(); column_names2.len()];
-    for (column, index) in &column_names2 {
-        columns[*index] = column.clone();
+fn union(expression1: Expression, expression2: Expression, matching: ColumnMatching) -> Result<Stream> {
+    let (column_names1, rows1) = compile(expression1)?;
+    let (column_names2, rows2) = compile(expression2)?;
+
+    let rows2: Rows = match matching {
+        ColumnMatching::ByName => {
+            check_compatible_schemas("union", &column_names1, &column_names2)?;
+
+            let positions = alignment(&column_names1, &column_names2);
+            Box::new(rows2.map(move |row| row.map(
+                |entry2| positions.iter().map(|position| entry2[*position].clone()).collect::<Entry>()
+            )))
+        },
+        ColumnMatching::ByPosition => {
+            if column_names1.len() != column_names2.len() {
+                return Err(EngineError::SchemaMismatch(format!("union by position: the left side has {} columns but the right side has {}", column_names1.len(), column_names2.len())));
+            }
+
+            rows2
+        }
+    };
+
+    Ok((column_names1, Box::new(rows1.chain(rows2))))
+}
+
+/**
+ * Running state of an aggregate function over a group.
+ */
+enum Accumulator {
+    Count(i64),
+    Sum(Value), // NULL until a value is added, then an integer, or a float once a float was added
+    Min(Option<Value>),
+    Max(Option<Value>),
+    Avg(Value, i64) // sum, count
+}
+
+impl Accumulator {
+    fn new(function: AggregateFunction) -> Accumulator {
+        match function {
+            AggregateFunction::Count => Accumulator::Count(0),
+            AggregateFunction::Sum => Accumulator::Sum(Value::Null),
+            AggregateFunction::Min => Accumulator::Min(None),
+            AggregateFunction::Max => Accumulator::Max(None),
+            AggregateFunction::Avg => Accumulator::Avg(Value::Null, 0)
+        }
     }
 
-    let mut swaps = Vec::new();
-    for (column, index) in &column_names1 {
-        let actual_index_mut = column_names2.get_mut(column).unwrap();
-        let actual_index = actual_index_mut.clone();
-        if *index == actual_index {
-            continue;
+    fn update(&mut self, value: Option<&Value>) -> Result<()> {
+        // Comme en SQL, les NULL sont ignorés, sauf par count(*)
+        if let Some(Value::Null) = value {
+            return Ok(());
         }
 
-        swaps.push((*index, actual_index));
+        match self {
+            Accumulator::Count(count) => *count += 1,
+            Accumulator::Sum(sum) => *sum = add_numbers(sum, value)?,
+            Accumulator::Avg(sum, count) => {
+                *sum = add_numbers(sum, value)?;
+                *count += 1;
+            },
+            Accumulator::Min(current) => keep_extremum(current, value, Ordering::Less)?,
+            Accumulator::Max(current) => keep_extremum(current, value, Ordering::Greater)?
+        }
 
-        // On update les positions
-        *actual_index_mut = *index;
-        column_names2.insert(columns[*index].clone(), actual_index);
+        Ok(())
     }
 
-    entries1.append(&mut entries2.into_iter().map(|mut entry| {
-        for (i, j) in &swaps {
-            entry.swap(*i, *j);
+    fn finish(self) -> Value {
+        match self {
+            Accumulator::Count(count) => Value::Int(count),
+            Accumulator::Sum(sum) => sum,
+            // Comme en SQL standard, la moyenne d'entiers n'est pas arrondie
+            Accumulator::Avg(Value::Int(sum), count) => Value::Float(sum as f64 / count as f64),
+            Accumulator::Avg(Value::Float(sum), count) => Value::Float(sum / count as f64),
+            Accumulator::Avg(sum, _) => sum,
+            Accumulator::Min(value) | Accumulator::Max(value) => value.unwrap_or(Value::Null)
         }
-        entry
-    }).collect());
+    }
+}
+
+fn keep_extremum(current: &mut Option<Value>, value: Option<&Value>, wanted: Ordering) -> Result<()> {
+    let value = value.ok_or_else(|| EngineError::Type(String::from("min and max need a column, not *")))?;
+    let replace = match current {
+        None => true,
+        Some(v) => compare_values(value, v) == Some(wanted)
+    };
 
-    (column_names1, entries1)
+    if replace {
+        *current = Some(value.clone());
+    }
+
+    Ok(())
+}
+
+/**
+ * Add a value to a running sum, NULL before the first value: integers stay integers unless they overflow, floats make the sum a float.
+ */
+fn add_numbers(sum: &Value, value: Option<&Value>) -> Result<Value> {
+    match (sum, value) {
+        (Value::Null, Some(v @ Value::Int(_))) | (Value::Null, Some(v @ Value::Float(_))) => Ok(v.clone()),
+        (Value::Int(i), Some(Value::Int(j))) => Ok(i.checked_add(*j).map(Value::Int).unwrap_or(Value::Float(*i as f64 + *j as f64))),
+        (Value::Int(i), Some(Value::Float(y))) => Ok(Value::Float(*i as f64 + y)),
+        (Value::Float(x), Some(Value::Int(j))) => Ok(Value::Float(x + *j as f64)),
+        (Value::Float(x), Some(Value::Float(y))) => Ok(Value::Float(x + y)),
+        (_, Some(v)) => Err(EngineError::Type(format!("sum and avg can only be computed on numbers, found {:?}", v))),
+        (_, None) => Err(EngineError::Type(String::from("sum and avg need a column, not *")))
+    }
 }
 
-fn read(filename: String) -> Table {
-    let mut rdr = Reader::from_path(filename).unwrap();
-    let mut column_names = HashMap::new();
+fn aggregate(expression: Expression, group_by: Vec<String>, aggregates: Vec<(AggregateFunction, String, String)>) -> Result<Stream> {
+    let (column_names, rows) = compile(expression)?;
 
-    for (i, header) in rdr.headers().unwrap().into_iter().enumerate() {
-        column_names.insert(String::from(header), i);
+    let group_indexes = group_by.iter().map(|column| column_index(&column_names, column, "group by")).collect::<Result<Vec<_>>>()?;
+    // "*" (only meaningful for count) does not refer to any column
+    let aggregate_indexes = aggregates.iter().map(|(_, column, _)|
+        if column == "*" { Ok(None) } else { column_index(&column_names, column, "aggregate").map(Some) }
+    ).collect::<Result<Vec<_>>>()?;
+    let functions = aggregates.iter().map(|(function, _, _)| *function).collect::<Vec<_>>();
+
+    let mut final_columns = HashMap::new();
+    for (i, column) in group_by.into_iter().chain(aggregates.into_iter().map(|(_, _, name)| name)).enumerate() {
+        final_columns.insert(column, i);
     }
 
-    let entries: Vec<Entry> = rdr.records().map(
-        |record| record.unwrap().into_iter().map(
-            |value| {
-                match value.parse::<i64>() {
-                    Ok(i) => Value::Int(i),
-                    Err(_) => Value::Str(String::from(value))
+    let rows = blocking(move || {
+        // On garde l'ordre d'apparition des groupes
+        let mut groups: HashMap<Vec<Value>, usize> = HashMap::new();
+        let mut accumulators: Vec<(Vec<Value>, Vec<Accumulator>)> = Vec::new();
+
+        for row in rows {
+            let entry = row?;
+            let key = group_indexes.iter().map(|index| entry[*index].clone()).collect::<Vec<_>>();
+
+            let group = match groups.get(&key) {
+                Some(group) => *group,
+                None => {
+                    groups.insert(key.clone(), accumulators.len());
+                    accumulators.push((key, functions.iter().map(|function| Accumulator::new(*function)).collect()));
+                    accumulators.len() - 1
                 }
+            };
+
+            for (accumulator, index) in accumulators[group].1.iter_mut().zip(&aggregate_indexes) {
+                accumulator.update(index.map(|index| &entry[index]))?;
             }
-        ).collect()
-    ).collect();
+        }
+
+        // Sans groupement, une relation vide donne quand même une entrée : count vaut 0, les autres NULL
+        if group_indexes.is_empty() && accumulators.is_empty() {
+            accumulators.push((Vec::new(), functions.iter().map(|function| Accumulator::new(*function)).collect()));
+        }
 
-    (column_names, entries)
+        Ok(accumulators.into_iter().map(|(mut key, accumulators)| {
+            key.extend(accumulators.into_iter().map(|accumulator| accumulator.finish()));
+            key
+        }).collect())
+    });
+
+    Ok((final_columns, rows))
 }
 
-fn read_select_project_rename(filename: String, condition: Box<Condition>, old_attrs: Vec<String>, new_attrs: Vec<String>) -> Table {
-    let mut rdr = Reader::from_path(filename).unwrap();
-    let mut column_names = HashMap::new();
+/**
+ * A value along with the order it should be sorted in.
+ */
+#[derive(PartialEq, Eq)]
+struct SortKey(Value, SortOrder);
 
-    for (i, header) in rdr.headers().unwrap().into_iter().enumerate() {
-        column_names.insert(String::from(header), i);
+impl Ord for SortKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let ordering = total_order(&self.0, &other.0);
+        match self.1 {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse()
+        }
+    }
+}
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
+}
 
-    let (swaps, mut final_columns) = swaps_for_projection(&column_names, &old_attrs);
-    let entries: Vec<Entry> = rdr.records().map(
-        |record|
-            record.unwrap().into_iter()
-            .map(
-                |value| {
-                    match value.parse::<i64>() {
-                        Ok(i) => Value::Int(i),
-                        Err(_) => Value::Str(String::from(value))
-                    }
-                }
-            )
-            .collect()
-    )
-    // chaining map, then filter is optimized by rust
-    .filter(|entry| eval_condition(entry, &column_names, &condition))
-    .map(|mut record| {
-        for (i, j) in &swaps {
-            record.swap(*i, *j);
+/**
+ * An entry of the top-n heap, ordered by its sort keys then its position in the input (to keep the sort stable).
+ * When merging sorted runs, the position is the one of the run.
+ */
+struct HeapEntry {
+    keys: Vec<SortKey>,
+    position: usize,
+    entry: Entry
+}
+
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for HeapEntry {}
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.keys.cmp(&other.keys).then(self.position.cmp(&other.position))
+    }
+}
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn sort_indexes(column_names: &HashMap<String, usize>, keys: &[(String, SortOrder)]) -> Result<Vec<(usize, SortOrder)>> {
+    keys.iter().map(|(column, order)| Ok((column_index(column_names, column, "sort")?, *order))).collect()
+}
+
+fn sort_keys(entry: &Entry, indexes: &[(usize, SortOrder)]) -> Vec<SortKey> {
+    indexes.iter().map(|(index, order)| SortKey(entry[*index].clone(), *order)).collect()
+}
+
+fn sort(expression: Expression, keys: Vec<(String, SortOrder)>) -> Result<Stream> {
+    let (column_names, rows) = compile(expression)?;
+    let indexes = sort_indexes(&column_names, &keys)?;
+
+    Ok((column_names, sorted_rows(rows, indexes)))
+}
+
+/**
+ * Sort a stream, in memory when it fits in the memory limit.
+ * Otherwise, sorted runs fitting in memory are written to temporary files, then merged (external sort).
+ */
+fn sorted_rows(mut rows: Rows, indexes: Vec<(usize, SortOrder)>) -> Rows {
+    deferred(move || {
+        let mut runs = Vec::new();
+        loop {
+            let (mut entries, exhausted) = fill(&mut rows)?;
+
+            // Le tri est stable : les entrées égales gardent leur ordre
+            entries.sort_by_cached_key(|entry| sort_keys(entry, &indexes));
+            if exhausted && runs.is_empty() {
+                return Ok(Box::new(entries.into_iter().map(Ok)) as Rows);
+            }
+
+            runs.push(SpillFile::from_rows(entries.into_iter().map(Ok))?);
+            if exhausted {
+                break;
+            }
         }
-        record.truncate(old_attrs.len());
 
-        record
+        merge_runs(runs, indexes)
     })
-    .collect();
+}
+
+/**
+ * Merge sorted runs, ties going to the earliest run to keep the sort stable.
+ */
+struct MergeRuns {
+    runs: Vec<Rows>,
+    heap: BinaryHeap<Reverse<HeapEntry>>,
+    indexes: Vec<(usize, SortOrder)>
+}
+
+fn merge_runs(runs: Vec<SpillFile>, indexes: Vec<(usize, SortOrder)>) -> Result<Rows> {
+    let runs = runs.into_iter().map(|run| run.into_rows()).collect::<Result<Vec<_>>>()?;
+    let mut merge = MergeRuns { heap: BinaryHeap::with_capacity(runs.len()), runs, indexes };
+    for position in 0..merge.runs.len() {
+        merge.pull(position)?;
+    }
+
+    Ok(Box::new(merge))
+}
+
+impl MergeRuns {
+    fn pull(&mut self, position: usize) -> Result<()> {
+        if let Some(row) = self.runs[position].next() {
+            let entry = row?;
+            self.heap.push(Reverse(HeapEntry { keys: sort_keys(&entry, &self.indexes), position, entry }));
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for MergeRuns {
+    type Item = Result<Entry>;
 
-    rename_columns(&mut final_columns, old_attrs, new_attrs);
+    fn next(&mut self) -> Option<Result<Entry>> {
+        let Reverse(smallest) = self.heap.pop()?;
+
+        Some(self.pull(smallest.position).map(|_| smallest.entry))
+    }
+}
+
+fn limit_offset(expression: Expression, limit: usize, offset: usize) -> Result<Stream> {
+    let (column_names, rows) = compile(expression)?;
+
+    // On arrête de tirer des entrées une fois la limite atteinte ; les erreurs ne comptent pas dans l'offset
+    let mut position = 0;
+    let rows = rows.filter(move |row| {
+        if row.is_ok() {
+            position += 1;
+        }
+        row.is_err() || position > offset
+    }).take(limit);
 
-    (final_columns, entries)
+    Ok((column_names, Box::new(rows)))
 }
 
-fn join_project_rename(expr1: Box<Expression>, expr2: Box<Expression>, condition: Box<Condition>, old_attrs: Vec<String>, new_attrs: Vec<String>) -> Table {
-    let (column_names1, entries1) = eval(expr1);
-    let (column_names2, entries2) = eval(expr2);
+/**
+ * Sort then limit, only keeping the first limit+offset entries in a bounded heap.
+ */
+fn top_n(expression: Expression, keys: Vec<(String, SortOrder)>, limit: usize, offset: usize) -> Result<Stream> {
+    let (column_names, rows) = compile(expression)?;
+    let indexes = sort_indexes(&column_names, &keys)?;
+    let bound = limit.saturating_add(offset);
+
+    let rows = blocking(move || {
+        // Le haut du tas est la plus grande des entrées gardées, qu'on remplace dès qu'on trouve mieux
+        let mut heap = BinaryHeap::with_capacity(bound.min(1024) + 1);
+        for (position, row) in rows.enumerate() {
+            let entry = row?;
+            let candidate = HeapEntry { keys: sort_keys(&entry, &indexes), position, entry };
+
+            if heap.len() < bound {
+                heap.push(candidate);
+            } else if let Some(mut top) = heap.peek_mut() {
+                if candidate < *top {
+                    *top = candidate;
+                }
+            }
+        }
+
+        Ok(heap.into_sorted_vec().into_iter().skip(offset).map(|heap_entry| heap_entry.entry).collect())
+    });
+
+    Ok((column_names, rows))
+}
 
-    // On se repose sur un hash join pour accélérer les cross product
+fn distinct(expression: Expression) -> Result<Stream> {
+    let (column_names, rows) = compile(expression)?;
+
+    // On garde la première occurrence de chaque entrée, dans l'ordre
+    let mut seen = HashSet::new();
+    let rows = rows.filter(move |row| match row {
+        Ok(entry) => seen.insert(entry.clone()),
+        Err(_) => true
+    });
+
+    Ok((column_names, Box::new(rows)))
+}
+
+/**
+ * Open a CSV file, returning its reader and the positions of its columns.
+ */
+fn open_csv(file: &CsvFile) -> Result<(Reader<File>, HashMap<String, usize>)> {
+    let (rdr, columns) = input::open_csv(file)?;
+    let column_names = columns.into_iter().enumerate().map(|(i, column)| (column, i)).collect();
+
+    Ok((rdr, column_names))
+}
+
+/**
+ * Stream the remaining records of a CSV file as entries.
+ */
+fn read_entries(file: CsvFile, rdr: Reader<File>) -> Rows {
+    Box::new(rdr.into_records().map(move |record| {
+        let record = record.map_err(|e| EngineError::io(&file.filename, e))?;
+
+        Ok(record.into_iter().map(|field| parse_field(field, &file.dialect.nulls)).collect())
+    }))
+}
+
+/**
+ * The value of a CSV field: NULL when it is one of nulls, an integer, a float, a boolean (true or false, whatever the case)
+ * or an ISO-8601 date (e.g. 2021-03-14) when it can be read as one, a string otherwise.
+ */
+pub fn parse_field(field: &str, nulls: &[String]) -> Value {
+    if nulls.iter().any(|null| null == field) {
+        return Value::Null;
+    }
+    if let Ok(i) = field.parse::<i64>() {
+        return Value::Int(i);
+    }
+    // "inf" et "NaN" sont lus comme des flottants par Rust, mais restent des chaînes ici
+    if let Ok(x) = field.parse::<f64>() {
+        if x.is_finite() {
+            return Value::Float(x);
+        }
+    }
+    if field.eq_ignore_ascii_case("true") || field.eq_ignore_ascii_case("false") {
+        return Value::Bool(field.eq_ignore_ascii_case("true"));
+    }
+
+    match NaiveDate::parse_from_str(field, "%Y-%m-%d") {
+        Ok(date) => Value::Date(date),
+        Err(_) => Value::Str(String::from(field))
+    }
+}
+
+fn read(file: CsvFile) -> Result<Stream> {
+    let (rdr, column_names) = open_csv(&file)?;
+
+    Ok((column_names, read_entries(file, rdr)))
+}
+
+fn read_select_project_rename(file: CsvFile, condition: Box<Condition>, old_attrs: Vec<String>, new_attrs: Vec<String>) -> Result<Stream> {
+    let (rdr, column_names) = open_csv(&file)?;
+
+    let (indexes, mut final_columns) = projection_indexes(&column_names, &old_attrs)?;
+    rename_columns(&mut final_columns, old_attrs, new_attrs)?;
+
+    let rows = filter_rows(read_entries(file, rdr), column_names, condition);
+
+    Ok((final_columns, project_rows(rows, indexes)))
+}
+
+fn join_project_rename(expr1: Expression, expr2: Expression, condition: Box<Condition>, old_attrs: Vec<String>, new_attrs: Vec<String>) -> Result<Stream> {
+    let (column_names1, rows1) = compile(expr1)?;
+    let (column_names2, rows2) = compile(expr2)?;
+
+    // On sépare les égalités et les comparaisons entre les deux côtés, qui permettent d'éviter un produit
+    let whole_condition = condition.clone();
     let mut unsupported_conditions = Box::new(Condition::True);
     let mut conditions_to_treat = vec![condition];
     let mut bucket1 = HashSet::new();
+    let mut ranges = Vec::new();
 
     while let Some(condition) = conditions_to_treat.pop() {
         match *condition {
@@ -248,107 +899,366 @@ fn join_project_rename(expr1: Box<Expression>, expr2: Box<Expression>, condition
                 conditions_to_treat.push(c1);
                 conditions_to_treat.push(c2);
             },
-            _ => unsupported_conditions = Box::new(Condition::And(unsupported_conditions, condition))
+            // not (a != b) est une égalité, qui peut donc profiter du hash join
+            Condition::Not(c) if matches!(*c, Condition::NotEqual(_, _)) => {
+                if let Condition::NotEqual(v1, v2) = *c {
+                    conditions_to_treat.push(Box::new(Condition::Equal(v1, v2)));
+                }
+            },
+            // Les autres comparaisons (<, <=, >, >=, !=) sont vérifiées après coup, même celles qui guident un sort-merge join
+            _ => {
+                if let Some(range) = range_between(&condition, &column_names1, &column_names2) {
+                    ranges.push(range);
+                }
+                unsupported_conditions = Box::new(Condition::And(unsupported_conditions, condition))
+            }
         }
     }
 
-    let indexes = bucket1.into_iter().map(|(field1, field2)|
-        (column_names1.get(&field1).unwrap(), column_names2.get(&field2).unwrap())
-    ).collect::<Vec<_>>();
+    let mut bucket1 = bucket1.into_iter().collect::<Vec<_>>();
+    bucket1.sort();
+    let (indexes1, indexes2): (Vec<usize>, Vec<usize>) = bucket1.into_iter().map(|(field1, field2)|
+        (column_names1[&field1], column_names2[&field2])
+    ).unzip();
 
-    let mut buckets = HashMap::new();
+    let final_columns = concatenated_columns(&column_names1, &column_names2);
+    let (indexes, mut projected_columns) = projection_indexes(&final_columns, &old_attrs)?;
+    rename_columns(&mut projected_columns, old_attrs, new_attrs)?;
 
-    for entry1 in entries1 {
-        let mut repr = Vec::new();
-        for (id, _) in &indexes {
-            repr.push(entry1[**id].clone());
-        }
+    let join_condition = JoinCondition { keys1: indexes1, keys2: indexes2, ranges, residual: simplify(*unsupported_conditions), condition: whole_condition };
+    let rows = join(rows1, rows2, join_condition, final_columns, column_names2.len());
 
-        let mut bucket = buckets.get_mut(&repr);
-        if bucket.is_none() {
-            buckets.insert(repr.clone(), Vec::new());
-            bucket = buckets.get_mut(&repr);
+    Ok((projected_columns, project_rows(rows, indexes)))
+}
+
+/**
+ * A comparison between a column of each side of a join: value1 op value2.
+ */
+#[derive(Debug, Clone, Copy)]
+enum RangeComparison {
+    Less,
+    LessEq,
+    More,
+    MoreEq
+}
+
+impl RangeComparison {
+    fn flipped(self) -> RangeComparison {
+        match self {
+            RangeComparison::Less => RangeComparison::More,
+            RangeComparison::LessEq => RangeComparison::MoreEq,
+            RangeComparison::More => RangeComparison::Less,
+            RangeComparison::MoreEq => RangeComparison::LessEq
         }
-        let bucket = bucket.unwrap();
-        
-        bucket.push(entry1);
     }
+}
 
-    let mut final_entries: Vec<Entry> = Vec::new();
+/**
+ * The positions of the columns compared by a condition, when it compares a column of each side.
+ */
+fn range_between(condition: &Condition, column_names1: &HashMap<String, usize>, column_names2: &HashMap<String, usize>) -> Option<(usize, RangeComparison, usize)> {
+    let (column1, comparison, column2) = match condition {
+        Condition::Less(Value::Column(c1), Value::Column(c2)) => (c1, RangeComparison::Less, c2),
+        Condition::LessEq(Value::Column(c1), Value::Column(c2)) => (c1, RangeComparison::LessEq, c2),
+        Condition::More(Value::Column(c1), Value::Column(c2)) => (c1, RangeComparison::More, c2),
+        Condition::MoreEq(Value::Column(c1), Value::Column(c2)) => (c1, RangeComparison::MoreEq, c2),
+        _ => return None
+    };
 
-    for entry2 in &entries2 {
-        let mut repr = Vec::new();
-        for (_, id) in &indexes {
-            repr.push(entry2[**id].clone());
-        }
+    match (column_names1.get(column1), column_names2.get(column2), column_names2.get(column1), column_names1.get(column2)) {
+        (Some(index1), Some(index2), _, _) => Some((*index1, comparison, *index2)),
+        (_, _, Some(index2), Some(index1)) => Some((*index1, comparison.flipped(), *index2)),
+        _ => None
+    }
+}
+
+/**
+ * The condition of a join, split according to what each algorithm can use.
+ */
+struct JoinCondition {
+    keys1: Vec<usize>, // equalities between the columns at keys1 on the left and keys2 on the right
+    keys2: Vec<usize>,
+    ranges: Vec<(usize, RangeComparison, usize)>,
+    residual: Box<Condition>, // what is left to check once the keys are matched, ranges included
+    condition: Box<Condition>
+}
+
+/**
+ * The algorithms a join can be evaluated with.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinAlgorithm {
+    Hash,
+    SortMerge,
+    NestedLoop
+}
+
+// En dessous, comparer chaque paire d'entrées coûte moins que de hacher ou de trier
+const NESTED_LOOP_MAX: usize = 16;
+
+/**
+ * Choose how to evaluate a join, from the number of equalities and of range comparisons between both sides,
+ * and the number of entries of its smallest side when it is known.
+ *
+ * Tiny sides and joins without any equality nor range comparison use a nested loop; range comparisons,
+ * with or without equalities, use a sort-merge join; equalities alone use a hash join.
+ */
+pub fn choose_join_algorithm(equalities: usize, ranges: usize, smallest_side: Option<usize>) -> JoinAlgorithm {
+    match smallest_side {
+        Some(size) if size <= NESTED_LOOP_MAX => JoinAlgorithm::NestedLoop,
+        _ if ranges > 0 => JoinAlgorithm::SortMerge,
+        _ if equalities > 0 => JoinAlgorithm::Hash,
+        _ => JoinAlgorithm::NestedLoop
+    }
+}
+
+/**
+ * The number of entries of a stream when there are at most max of them, along with the stream.
+ */
+fn peek_size(mut rows: Rows, max: usize) -> Result<(Option<usize>, Rows)> {
+    let head = rows.by_ref().take(max + 1).collect::<Result<Vec<_>>>()?;
+    let size = if head.len() <= max { Some(head.len()) } else { None };
+
+    Ok((size, Box::new(head.into_iter().map(Ok).chain(rows))))
+}
+
+/**
+ * Join two streams, each entry of the left side being followed by an entry of the right side.
+ * The algorithm is chosen when the first entry is pulled, once the size of the smallest side is known if it is tiny.
+ */
+fn join(rows1: Rows, rows2: Rows, join_condition: JoinCondition, column_names: HashMap<String, usize>, len2: usize) -> Rows {
+    deferred(move || {
+        let (size1, rows1) = peek_size(rows1, NESTED_LOOP_MAX)?;
+        let (size2, rows2) = peek_size(rows2, NESTED_LOOP_MAX)?;
+        let smallest_side = match (size1, size2) {
+            (Some(size1), Some(size2)) => Some(size1.min(size2)),
+            (size1, size2) => size1.or(size2)
+        };
+        let JoinCondition { keys1, keys2, ranges, residual, condition } = join_condition;
+
+        let (rows, condition) = match choose_join_algorithm(keys1.len(), ranges.len(), smallest_side) {
+            // On garde en mémoire le plus petit côté, quitte à remettre les colonnes dans l'ordre ensuite
+            JoinAlgorithm::NestedLoop if size1.is_some() && size1 == smallest_side => {
+                let rows: Rows = Box::new(nested_loop(rows2, rows1).map(move |row| row.map(|mut entry| {
+                    entry.rotate_left(len2);
+                    entry
+                })));
+                (rows, condition)
+            },
+            JoinAlgorithm::NestedLoop => (nested_loop(rows1, rows2), condition),
+            JoinAlgorithm::Hash => (hash_join(rows1, rows2, keys1, keys2, JoinKind::Inner, 0), residual),
+            JoinAlgorithm::SortMerge => (sort_merge_join(rows1, rows2, keys1, keys2, ranges[0]), residual)
+        };
+
+        Ok(filter_rows(rows, column_names, condition))
+    })
+}
+
+fn compare_keys(key1: &[Value], key2: &[Value]) -> Ordering {
+    key1.iter().zip(key2).map(|(v1, v2)| total_order(v1, v2)).find(|ordering| *ordering != Ordering::Equal).unwrap_or(Ordering::Equal)
+}
+
+/**
+ * Sort-merge join: both sides are sorted on their keys then on the compared columns, and the right side
+ * is read one group of entries with the same key at a time. Within a group, the entries matching the range
+ * comparison are found by binary search.
+ */
+struct SortMerge {
+    left: Rows,
+    right: std::iter::Fuse<Rows>,
+    keys1: Vec<usize>,
+    keys2: Vec<usize>,
+    range: (usize, RangeComparison, usize),
+    group: Vec<Entry>,
+    group_key: Option<Vec<Value>>,
+    lookahead: Option<Entry>, // the first entry of the next group
+    exhausted: bool,
+    pending: std::vec::IntoIter<Entry>
+}
+
+fn sort_merge_join(rows1: Rows, rows2: Rows, keys1: Vec<usize>, keys2: Vec<usize>, range: (usize, RangeComparison, usize)) -> Rows {
+    let order1 = keys1.iter().chain(std::iter::once(&range.0)).map(|index| (*index, SortOrder::Asc)).collect();
+    let order2 = keys2.iter().chain(std::iter::once(&range.2)).map(|index| (*index, SortOrder::Asc)).collect();
+
+    Box::new(SortMerge {
+        left: sorted_rows(rows1, order1),
+        right: sorted_rows(rows2, order2).fuse(),
+        keys1,
+        keys2,
+        range,
+        group: Vec::new(),
+        group_key: None,
+        lookahead: None,
+        exhausted: false,
+        pending: Vec::new().into_iter()
+    })
+}
 
-        // On fait le produit avec les éléments du bucket qui correspond
-        let bucket = buckets.get(&repr);
-        if let Some(entries) = bucket {
-            for entry in entries {
-                let mut entry = entry.clone();
-                entry.append(&mut entry2.clone());
+impl SortMerge {
+    /**
+     * Move to the first group of the right side whose key is not smaller than key.
+     */
+    fn advance_to(&mut self, key: &[Value]) -> Result<()> {
+        while !self.exhausted && self.group_key.as_ref().is_none_or(|group_key| compare_keys(group_key, key) == Ordering::Less) {
+            self.group.clear();
+            self.group_key = None;
 
-                final_entries.push(entry);
+            let first = match self.lookahead.take() {
+                Some(entry) => entry,
+                None => match self.right.next() {
+                    Some(row) => row?,
+                    None => {
+                        self.exhausted = true;
+                        break;
+                    }
+                }
+            };
+            let group_key = key_of(&first, &self.keys2);
+            self.group.push(first);
+
+            // Le groupe s'arrête à la première entrée de clé différente, gardée pour le groupe suivant
+            for row in self.right.by_ref() {
+                let entry = row?;
+                if key_of(&entry, &self.keys2) == group_key {
+                    self.group.push(entry);
+                } else {
+                    self.lookahead = Some(entry);
+                    break;
+                }
             }
+            self.group_key = Some(group_key);
         }
+
+        Ok(())
     }
 
-    let mut final_columns = HashMap::new();
-    for (key, value) in column_names2 {
-        final_columns.insert(key, column_names1.len()+value);
+    /**
+     * The entries of the current group satisfying the range comparison with a left entry.
+     * The group being sorted on the compared column, they are contiguous.
+     */
+    fn matching(&self, entry1: &Entry) -> &[Entry] {
+        let (index1, comparison, index2) = self.range;
+        let value1 = &entry1[index1];
+        let position = |wanted: fn(Ordering) -> bool| self.group.partition_point(|entry2| wanted(total_order(&entry2[index2], value1)));
+
+        match comparison {
+            RangeComparison::Less => &self.group[position(|ordering| ordering != Ordering::Greater)..],
+            RangeComparison::LessEq => &self.group[position(|ordering| ordering == Ordering::Less)..],
+            RangeComparison::More => &self.group[..position(|ordering| ordering == Ordering::Less)],
+            RangeComparison::MoreEq => &self.group[..position(|ordering| ordering != Ordering::Greater)]
+        }
     }
-    for (key, value) in column_names1 {
-        final_columns.insert(key, value);
+}
+
+impl Iterator for SortMerge {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Result<Entry>> {
+        loop {
+            if let Some(entry) = self.pending.next() {
+                return Some(Ok(entry));
+            }
+
+            let entry1 = match self.left.next()? {
+                Ok(entry1) => entry1,
+                Err(e) => return Some(Err(e))
+            };
+            let key = key_of(&entry1, &self.keys1);
+            if key.contains(&Value::Null) {
+                continue;
+            }
+            if let Err(e) = self.advance_to(&key) {
+                return Some(Err(e));
+            }
+
+            if self.group_key.as_ref() == Some(&key) {
+                self.pending = self.matching(&entry1).iter().map(|entry2| concatenate(&entry1, entry2)).collect::<Vec<_>>().into_iter();
+            }
+        }
     }
+}
 
-    let (swaps, mut swapped_columns) = swaps_for_projection(&final_columns, &old_attrs);
-    let final_entries = final_entries.into_iter()
-        .filter(|entry| eval_condition(entry, &final_columns, &unsupported_conditions))
-        .map(|mut record| {
-            for (i, j) in &swaps {
-                record.swap(*i, *j);
+/**
+ * Evaluate a condition on an entry, in three-valued logic: None when it is unknown, because of a comparison with NULL.
+ * Not of an unknown condition is unknown; And (resp. Or) is false (resp. true) as soon as one side is, unknown otherwise.
+ */
+fn eval_condition(entry: &Entry, column_names: &HashMap<String, usize>, condition: &Condition) -> Result<Option<bool>> {
+    Ok(match condition {
+        Condition::True => Some(true),
+        Condition::False => Some(false),
+        Condition::Not(c) => eval_condition(entry, column_names, c)?.map(|holds| !holds),
+        Condition::And(c1, c2) => match eval_condition(entry, column_names, c1)? {
+            Some(false) => Some(false),
+            holds1 => match eval_condition(entry, column_names, c2)? {
+                Some(false) => Some(false),
+                holds2 => holds1.and(holds2)
+            }
+        },
+        Condition::Or(c1, c2) => match eval_condition(entry, column_names, c1)? {
+            Some(true) => Some(true),
+            holds1 => match eval_condition(entry, column_names, c2)? {
+                Some(true) => Some(true),
+                holds2 => holds1.and(holds2)
             }
-            record.truncate(old_attrs.len());
+        },
+        Condition::Equal(v1, v2) => compare_in_entry(entry, column_names, v1, v2)?.map(|ordering| ordering == Some(Ordering::Equal)),
+        Condition::NotEqual(v1, v2) => compare_in_entry(entry, column_names, v1, v2)?.map(|ordering| ordering != Some(Ordering::Equal)),
+        Condition::Less(v1, v2) => compare_in_entry(entry, column_names, v1, v2)?.map(|ordering| ordering == Some(Ordering::Less)),
+        Condition::LessEq(v1, v2) => compare_in_entry(entry, column_names, v1, v2)?.map(|ordering| matches!(ordering, Some(Ordering::Less) | Some(Ordering::Equal))),
+        Condition::More(v1, v2) => compare_in_entry(entry, column_names, v1, v2)?.map(|ordering| ordering == Some(Ordering::Greater)),
+        Condition::MoreEq(v1, v2) => compare_in_entry(entry, column_names, v1, v2)?.map(|ordering| matches!(ordering, Some(Ordering::Greater) | Some(Ordering::Equal))),
+        Condition::IsNull(v) => Some(matches!(get_value(entry, column_names, v)?, Value::Null))
+    })
+}
 
-            record
-        })
-        .collect();
+/**
+ * Compare two values of an entry, None meaning that one of them is NULL.
+ */
+fn compare_in_entry(entry: &Entry, column_names: &HashMap<String, usize>, v1: &Value, v2: &Value) -> Result<Option<Option<Ordering>>> {
+    match (get_value(entry, column_names, v1)?, get_value(entry, column_names, v2)?) {
+        (Value::Null, _) | (_, Value::Null) => Ok(None),
+        (v1, v2) => Ok(Some(compare_values(&v1, &v2)))
+    }
+}
 
-    rename_columns(&mut swapped_columns, old_attrs, new_attrs);
+/**
+ * Compare two resolved values: numbers numerically (integers with floats too), booleans with false before true,
+ * dates chronologically and strings lexicographically. Values of different types are not comparable.
+ */
+pub fn compare_values(v1: &Value, v2: &Value) -> Option<Ordering> {
+    match (v1, v2) {
+        (Value::Int(i), Value::Int(j)) => Some(i.cmp(j)),
+        (Value::Float(x), Value::Float(y)) => x.partial_cmp(y),
+        (Value::Int(i), Value::Float(y)) => compare_int_float(*i, *y),
+        (Value::Float(x), Value::Int(j)) => compare_int_float(*j, *x).map(Ordering::reverse),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Date(d), Value::Date(e)) => Some(d.cmp(e)),
+        (Value::Str(s), Value::Str(t)) => Some(s.cmp(t)),
+        _ => None
+    }
+}
 
-    (swapped_columns, final_entries)
+/**
+ * A total order on values, used for sorting: values that are not comparable are ordered by type.
+ */
+pub fn total_order(v1: &Value, v2: &Value) -> Ordering {
+    compare_values(v1, v2).unwrap_or_else(|| type_rank(v1).cmp(&type_rank(v2)))
 }
 
-fn eval_condition(entry: &Entry, column_names: &HashMap<String, usize>, condition: &Box<Condition>) -> bool {
-    match &**condition {
-        Condition::True => true,
-        Condition::False => false,
-        Condition::Not(c) => !eval_condition(entry, column_names, &c),
-        Condition::And(c1, c2) => eval_condition(entry, column_names, &c1) && eval_condition(entry, column_names, &c2),
-        Condition::Or(c1, c2) => eval_condition(entry, column_names, &c1) || eval_condition(entry, column_names, &c2),
-        Condition::Equal(v1, v2) => 
-            match (get_value(entry, column_names, v1), get_value(entry, column_names, v2)) {
-                (Value::Int(i), Value::Int(j)) => i == j,
-                (Value::Str(i), Value::Str(j)) => i == j,
-                _ => false
-            },
-        Condition::Less(v1, v2) => 
-            match (get_value(entry, column_names, v1), get_value(entry, column_names, v2)) {
-                (Value::Int(i), Value::Int(j)) => i < j,
-                _ => false
-            },
-        Condition::More(v1, v2) => 
-            match (get_value(entry, column_names, v1), get_value(entry, column_names, v2)) {
-                (Value::Int(i), Value::Int(j)) => i > j,
-                _ => false
-            }
+fn type_rank(value: &Value) -> u8 {
+    match value {
+        Value::Int(_) | Value::Float(_) => 0,
+        Value::Bool(_) => 1,
+        Value::Date(_) => 2,
+        Value::Str(_) => 3,
+        Value::Null => 4, // NULL vient après les autres valeurs, comme dans PostgreSQL
+        Value::Column(_) => 5
     }
 }
 
-fn get_value(entry: &Entry, column_names: &HashMap<String, usize>, value: &Value) -> Value {
+fn get_value(entry: &Entry, column_names: &HashMap<String, usize>, value: &Value) -> Result<Value> {
     match value {
-        Value::Column(s) => entry[*column_names.get(s).unwrap()].clone(),
-        v => v.clone()
+        Value::Column(s) => Ok(entry[column_index(column_names, s, "condition")?].clone()),
+        v => Ok(v.clone())
     }
 }
\ No newline at end of file
@@ -0,0 +1,174 @@
+use crate::types::*;
+use crate::eval::NodeStats;
+
+/**
+ * Format an expression as an indented tree of operators, one per line.
+ */
+pub fn format_plan(expression: &Expression) -> String {
+    let mut output = String::new();
+    write_node(expression, 0, None, &mut 0, &mut output);
+
+    output
+}
+
+/**
+ * Format an expression like format_plan, annotating each operator with the statistics of its evaluation.
+ * The statistics are given in prefix order, as returned by eval_analyzed.
+ */
+pub fn format_analyzed_plan(expression: &Expression, stats: &[NodeStats]) -> String {
+    let mut output = String::new();
+    write_node(expression, 0, Some(stats), &mut 0, &mut output);
+
+    output
+}
+
+fn write_node(expression: &Expression, depth: usize, stats: Option<&[NodeStats]>, position: &mut usize, output: &mut String) {
+    output.push_str(&"  ".repeat(depth));
+    output.push_str(&node_label(expression));
+
+    if let Some(stats) = stats {
+        // Un noeud absent des statistiques n'a pas été évalué
+        match stats.get(*position) {
+            Some(node_stats) => output.push_str(&format!("  (rows: {}, time: {:.2?})", node_stats.rows, node_stats.elapsed)),
+            None => output.push_str("  (not evaluated)")
+        }
+    }
+    output.push('\n');
+    *position += 1;
+
+    for child in children(expression) {
+        write_node(child, depth + 1, stats, position, output);
+    }
+}
+
+/**
+ * The sub-expressions of an expression, in the order they are evaluated.
+ */
+pub fn children(expression: &Expression) -> Vec<&Expression> {
+    match expression {
+        Expression::Select(expression_from, _) | Expression::Project(expression_from, _) | Expression::Rename(expression_from, _, _) |
+            Expression::Aggregate(expression_from, _, _) | Expression::Sort(expression_from, _) | Expression::Limit(expression_from, _, _) |
+            Expression::TopN(expression_from, _, _, _) | Expression::Distinct(expression_from) => vec![expression_from],
+        Expression::Product(expr1, expr2) | Expression::Except(expr1, expr2) | Expression::Union(expr1, expr2, _) |
+            Expression::JoinProjectRename(expr1, expr2, _, _, _) => vec![expr1, expr2],
+        Expression::Load(_, _) | Expression::ReadSelectProjectRename(_, _, _, _) | Expression::Empty(_) => Vec::new()
+    }
+}
+
+/**
+ * A one-line description of an operator, without its children.
+ */
+pub fn node_label(expression: &Expression) -> String {
+    match expression {
+        Expression::Select(_, condition) => format!("Select {}", condition_to_string(condition)),
+        Expression::Project(_, columns) => format!("Project [{}]", columns.join(", ")),
+        Expression::Rename(_, old_columns, new_columns) => format!("Rename [{}]", renaming_to_string(old_columns, new_columns)),
+        Expression::Except(_, _) => String::from("Minus"),
+        Expression::Union(_, _, ColumnMatching::ByName) => String::from("Union by name"),
+        Expression::Union(_, _, ColumnMatching::ByPosition) => String::from("Union by position"),
+        Expression::Product(_, _) => String::from("Product"),
+        Expression::ReadSelectProjectRename(filename, condition, old_columns, new_columns) =>
+            format!("ReadSelectProjectRename {} where {} [{}]", filename, condition_to_string(condition), renaming_to_string(old_columns, new_columns)),
+        Expression::JoinProjectRename(_, _, condition, old_columns, new_columns) =>
+            format!("JoinProjectRename on {} [{}]", condition_to_string(condition), renaming_to_string(old_columns, new_columns)),
+        Expression::Aggregate(_, group_by, aggregates) => format!("Aggregate group by [{}] [{}]",
+            group_by.join(", "),
+            aggregates.iter().map(|(function, column, name)| format!("{}({}) as {}", format!("{:?}", function).to_lowercase(), column, name)).collect::<Vec<_>>().join(", ")
+        ),
+        Expression::Sort(_, keys) => format!("Sort [{}]", keys_to_string(keys)),
+        Expression::Limit(_, limit, offset) => format!("Limit {} offset {}", limit, offset),
+        Expression::TopN(_, keys, limit, offset) => format!("TopN [{}] limit {} offset {}", keys_to_string(keys), limit, offset),
+        Expression::Distinct(_) => String::from("Distinct"),
+        Expression::Empty(columns) => format!("Empty [{}]", columns.join(", ")),
+        Expression::Load(filename, None) => format!("Load {}", filename),
+        Expression::Load(filename, Some(columns)) => {
+            let mut columns = columns.iter().cloned().collect::<Vec<_>>();
+            columns.sort();
+
+            format!("Load {} [{}]", filename, columns.join(", "))
+        }
+    }
+}
+
+fn renaming_to_string(old_columns: &[String], new_columns: &[String]) -> String {
+    old_columns.iter().zip(new_columns)
+        .map(|(old, new)| if old == new { old.clone() } else { format!("{} -> {}", old, new) })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn keys_to_string(keys: &[(String, SortOrder)]) -> String {
+    keys.iter().map(|(column, order)| format!("{} {}", column, format!("{:?}", order).to_lowercase())).collect::<Vec<_>>().join(", ")
+}
+
+/**
+ * Write a condition in infix notation, e.g. "(idp < 5 AND NOT titre = 'Ursa')".
+ */
+pub fn condition_to_string(condition: &Condition) -> String {
+    match condition {
+        Condition::True => String::from("TRUE"),
+        Condition::False => String::from("FALSE"),
+        Condition::Not(c) => match &**c {
+            Condition::IsNull(v) => format!("{} IS NOT NULL", value_to_string(v)),
+            c => format!("NOT {}", condition_to_string(c))
+        },
+        Condition::And(c1, c2) => format!("({} AND {})", condition_to_string(c1), condition_to_string(c2)),
+        Condition::Or(c1, c2) => format!("({} OR {})", condition_to_string(c1), condition_to_string(c2)),
+        Condition::Equal(v1, v2) => format!("{} = {}", value_to_string(v1), value_to_string(v2)),
+        Condition::NotEqual(v1, v2) => format!("{} != {}", value_to_string(v1), value_to_string(v2)),
+        Condition::Less(v1, v2) => format!("{} < {}", value_to_string(v1), value_to_string(v2)),
+        Condition::LessEq(v1, v2) => format!("{} <= {}", value_to_string(v1), value_to_string(v2)),
+        Condition::More(v1, v2) => format!("{} > {}", value_to_string(v1), value_to_string(v2)),
+        Condition::MoreEq(v1, v2) => format!("{} >= {}", value_to_string(v1), value_to_string(v2)),
+        Condition::IsNull(v) => format!("{} IS NULL", value_to_string(v))
+    }
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::Int(i) => i.to_string(),
+        Value::Float(x) => float_to_string(*x),
+        Value::Bool(b) => String::from(if *b { "TRUE" } else { "FALSE" }),
+        Value::Date(d) => format!("DATE '{}'", d.format("%Y-%m-%d")),
+        Value::Str(s) => format!("'{}'", s.replace('\'', "''")),
+        Value::Null => String::from("NULL"),
+        Value::Column(column) => column.clone()
+    }
+}
+
+/**
+ * Render several plans side by side in a single DOT graph, each one in a box labelled with its name,
+ * e.g. the plan after each optimization.
+ */
+pub fn plans_to_dot(plans: &[(String, Box<Expression>)]) -> String {
+    let mut output = String::from("digraph plans {\n  node [shape=box, fontname=\"monospace\"];\n");
+
+    for (i, (name, expression)) in plans.iter().enumerate() {
+        output.push_str(&format!("  subgraph cluster_{} {{\n  label=\"{}\";\n", i, escape_dot(name)));
+        write_dot_node(expression, &format!("p{}_", i), &mut 0, &mut output);
+        output.push_str("  }\n");
+    }
+    output.push_str("}\n");
+
+    output
+}
+
+/**
+ * Write the node of an expression and its subtree, returning the identifier of the node.
+ */
+fn write_dot_node(expression: &Expression, prefix: &str, counter: &mut usize, output: &mut String) -> String {
+    let id = format!("{}{}", prefix, counter);
+    *counter += 1;
+    output.push_str(&format!("  {} [label=\"{}\"];\n", id, escape_dot(&node_label(expression))));
+
+    for child in children(expression) {
+        let child_id = write_dot_node(child, prefix, counter, output);
+        output.push_str(&format!("  {} -> {};\n", id, child_id));
+    }
+
+    id
+}
+
+fn escape_dot(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
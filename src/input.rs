@@ -0,0 +1,80 @@
+use crate::types::*;
+use crate::error::*;
+use csv::{Reader, ReaderBuilder, Trim};
+use std::fmt;
+use std::fs::File;
+
+/**
+ * Open a CSV file according to its dialect, returning its reader, positioned on the first entry, and the names of its columns.
+ *
+ * Without a header nor explicit names, the columns are named column1, column2... A UTF-8 byte order mark is skipped.
+ */
+pub fn open_csv(file: &CsvFile) -> Result<(Reader<File>, Vec<String>)> {
+    let dialect = &file.dialect;
+    let mut rdr = ReaderBuilder::new()
+        .delimiter(dialect.delimiter)
+        .quote(dialect.quote)
+        .has_headers(dialect.has_header)
+        .comment(dialect.comment)
+        .trim(if dialect.trim { Trim::All } else { Trim::None })
+        .from_path(&file.filename)
+        .map_err(|e| EngineError::io(&file.filename, e))?;
+
+    // Sans en-tête, on obtient la première entrée, sans qu'elle soit consommée
+    let first = rdr.headers().map_err(|e| EngineError::io(&file.filename, e))?;
+    let columns = match &dialect.columns {
+        Some(columns) if !first.is_empty() && columns.len() != first.len() =>
+            return Err(EngineError::SchemaMismatch(format!("{}: {} column names given for {} columns", file.filename, columns.len(), first.len()))),
+        Some(columns) => columns.clone(),
+        None if dialect.has_header => first.iter().map(String::from).collect(),
+        None => (1..=first.len()).map(|i| format!("column{}", i)).collect()
+    };
+
+    Ok((rdr, columns))
+}
+
+/**
+ * Read the column names of a CSV file, in order, without reading its entries.
+ */
+pub fn read_headers(file: &CsvFile) -> Result<Vec<String>> {
+    open_csv(file).map(|(_, columns)| columns)
+}
+
+/**
+ * The name of the file, followed by the options of its dialect that are not the default ones.
+ */
+impl fmt::Display for CsvFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dialect = &self.dialect;
+        let default = InputDialect::default();
+        let mut options = Vec::new();
+
+        if dialect.delimiter != default.delimiter {
+            options.push(format!("delimiter {:?}", dialect.delimiter as char));
+        }
+        if dialect.quote != default.quote {
+            options.push(format!("quote {:?}", dialect.quote as char));
+        }
+        if !dialect.has_header {
+            options.push(String::from("no header"));
+        }
+        if let Some(columns) = &dialect.columns {
+            options.push(format!("columns {}", columns.join(", ")));
+        }
+        if let Some(comment) = dialect.comment {
+            options.push(format!("comment {:?}", comment as char));
+        }
+        if dialect.trim {
+            options.push(String::from("trimmed"));
+        }
+        if dialect.nulls != default.nulls {
+            options.push(format!("nulls {:?}", dialect.nulls));
+        }
+
+        if options.is_empty() {
+            write!(f, "{}", self.filename)
+        } else {
+            write!(f, "{} ({})", self.filename, options.join(", "))
+        }
+    }
+}
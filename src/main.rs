@@ -1,64 +1,244 @@
 mod types;
+mod error;
 mod eval;
 mod output;
+mod input;
 mod optimize;
 mod parser;
+mod sql;
 mod benchmark;
+mod validate;
+mod explain;
+mod spill;
+mod stats;
+mod cost;
+mod simplify;
 
-#[cfg(test)]
-mod tests {
-    pub mod eval_test;
-    pub mod optimize_test;
-}
-
+use crate::types::*;
 use crate::eval::*;
 use crate::output::*;
 use crate::optimize::*;
 use crate::parser::*;
 use crate::benchmark::*;
+use crate::error::*;
+use crate::validate::*;
+use crate::explain::*;
+use crate::spill::*;
+use crate::cost::*;
 
 use clap::{Arg, App};
 
 fn main() {
+    // Une erreur est affichée sur une seule ligne, le code de sortie dépend de son type
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+fn run() -> Result<()> {
     // Parse command-line arguments and options
     let args = App::new("Linear Algebra Engine on CSV files")
         .version("1.0")
         .author("Guilhem Niot <guilhem.niot@ens-lyon.fr>; Yann Aguettaz <yann.aguettaz@ens-lyon.fr>")
-        .about("Takes JSON-formatted querries and runs them on CSV tables.\n Read the docs in the mardown files.")
+        .about("Takes JSON or SQL querries and runs them on CSV tables.\n Read the docs in the mardown files.")
         .arg(Arg::new("source_file")
             .index(1))
         .arg(Arg::new("output_file")
             .index(2))
+        .arg(Arg::new("language")
+            .short('l')
+            .long("language")
+            .takes_value(true)
+            .possible_values(["json", "sql"])
+            .help("Language of the query, guessed from the file extension by default"))
         .arg(Arg::new("benchmark")
             .short('b')
             .long("benchmark"))
+        .arg(Arg::new("explain")
+            .long("explain")
+            .help("Print the plan before and after each optimization, without evaluating it"))
+        .arg(Arg::new("dot")
+            .long("dot")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Write the plan after each optimization as a DOT graph, side by side"))
+        .arg(Arg::new("to_json")
+            .long("to-json")
+            .help("Output the optimized query in the JSON grammar instead of evaluating it"))
+        .arg(Arg::new("explain_analyze")
+            .long("explain-analyze")
+            .help("Like --explain, then run the query and annotate the plan with row counts and times"))
+        .arg(Arg::new("memory_limit")
+            .short('m')
+            .long("memory-limit")
+            .takes_value(true)
+            .value_name("SIZE")
+            .help("Memory each operator may use before spilling to temporary files, e.g. 512K, 64M or 2G"))
+        .arg(Arg::new("format")
+            .short('f')
+            .long("format")
+            .takes_value(true)
+            .possible_values(["table", "csv", "tsv", "json", "jsonl", "markdown"])
+            .help("Format of the result, guessed from the extension of the output file by default"))
+        .arg(Arg::new("columns")
+            .long("columns")
+            .takes_value(true)
+            .value_name("COLUMNS")
+            .help("Comma-separated columns to output, in this order, instead of those of the query"))
+        .arg(Arg::new("delimiter")
+            .short('d')
+            .long("delimiter")
+            .takes_value(true)
+            .value_name("CHAR")
+            .help("Field delimiter of the output CSV file, a single character or \"tab\""))
+        .arg(Arg::new("quote_style")
+            .long("quote-style")
+            .takes_value(true)
+            .possible_values(["necessary", "always", "non-numeric", "never"])
+            .help("Which fields of the output CSV file are quoted"))
+        .arg(Arg::new("line_terminator")
+            .long("line-terminator")
+            .takes_value(true)
+            .possible_values(["crlf", "lf"])
+            .help("Line terminator of the output CSV file"))
+        .arg(Arg::new("no_header")
+            .long("no-header")
+            .help("Do not write the column names on the first line of the output CSV file"))
         .get_matches();
 
     // If benchmarkn, run it. Else parse input and evaluate
     if args.is_present("benchmark") {
-        run_benchmark();
+        run_benchmark()
     } else {
-        let source_file = args.value_of("source_file").map(|str| String::from(str));
-        let output_file = args.value_of("output_file").map(|str| String::from(str));
+        let source_file = args.value_of("source_file").map(String::from);
+        let output_file = args.value_of("output_file").map(String::from);
+
+        let language = match args.value_of("language") {
+            Some("sql") => QueryLanguage::Sql,
+            Some(_) => QueryLanguage::Json,
+            None => QueryLanguage::from_path(&source_file)
+        };
+
+        if let Some(size) = args.value_of("memory_limit") {
+            set_memory_limit(Some(parse_memory_limit(size)?));
+        }
+
+        let format = match args.value_of("format") {
+            Some(name) => OutputFormat::from_name(name)?,
+            None => OutputFormat::from_path(&output_file)
+        };
+        let mut dialect = CsvDialect::default();
+        if let Some(delimiter) = args.value_of("delimiter") {
+            dialect.delimiter = parse_delimiter(delimiter)?;
+        }
+        if let Some(quote_style) = args.value_of("quote_style") {
+            dialect.quote_style = parse_quote_style(quote_style)?;
+        }
+        if let Some(terminator) = args.value_of("line_terminator") {
+            dialect.terminator = parse_terminator(terminator)?;
+        }
+        dialect.header = !args.is_present("no_header");
+
+        // Get expression from json or sql
+        let expr = Box::new(get_expression_from(source_file, language)?);
+
+        // The requested columns are projected on, so that they are checked with the others
+        let expr = match args.value_of("columns") {
+            Some(columns) => Box::new(Expression::Project(expr, columns.split(',').map(|column| String::from(column.trim())).collect())),
+            None => expr
+        };
 
-        // Get expression from json
-        let expr = Box::new(get_expression_from(source_file));
+        // Check the columns before reading any data
+        validate(&expr)?;
+        let columns = columns_of(&expr)?;
 
         // Optimization phase
         let optimizer = ChainOptimizer{optimizers: vec![
             Box::new(UnfoldComplexExpressionsOptimizer{}),
             Box::new(DetectLoadColumnsOptimizer{}),
+            Box::new(InferPredicatesOptimizer{}),
+            Box::new(SimplifyConditionsOptimizer{}),
+            Box::new(ReorderJoinsOptimizer{}),
+            Box::new(RemoveRedundantDistinctOptimizer{}),
             Box::new(PushDownSelectionsOptimizer{}),
             Box::new(ApplyProjectionsEarlyOptimizer{}),
-            Box::new(FoldComplexExpressionsOptimizer{})
+            Box::new(FoldComplexExpressionsOptimizer{}),
+            Box::new(ChooseJoinSidesOptimizer{}),
+            Box::new(FuseSortLimitOptimizer{})
         ]};
-        let expr = optimizer.optimize(expr);
+        let explain = args.is_present("explain") || args.is_present("explain_analyze");
+        let dot_file = args.value_of("dot");
+        if explain {
+            println!("Initial plan:\n{}", format_plan(&expr));
+        }
+        let mut plans = Vec::new();
+        if dot_file.is_some() {
+            plans.push((String::from("Initial"), expr.clone()));
+        }
+
+        let expr = optimizer.optimize_observed(expr, |name, expression| {
+            if explain {
+                println!("After {}:\n{}", name, format_plan(expression));
+            }
+            if dot_file.is_some() {
+                plans.push((String::from(name), Box::new(expression.clone())));
+            }
+        })?;
+
+        // The optimizations may change the order of the columns, which are output in the order of the query
+        let expr = if columns_of(&expr).ok().as_ref() == Some(&columns) { expr } else { Box::new(Expression::Project(expr, columns)) };
+        if explain {
+            let estimated = estimate(&expr)?;
+            println!("Estimated rows: {:.0}, cost: {:.0}\n", estimated.rows, estimated.cost);
+        }
+
+        if let Some(filename) = dot_file {
+            std::fs::write(filename, plans_to_dot(&plans)).map_err(|e| EngineError::io(filename, e))?;
+        }
+
+        // The optimized query can be saved instead of being evaluated
+        if args.is_present("to_json") {
+            let json = expression_to_json(&expr)?;
+            return match output_file {
+                Some(filename) => std::fs::write(&filename, json + "\n").map_err(|e| EngineError::io(&filename, e)),
+                None => {
+                    println!("{}", json);
+                    Ok(())
+                }
+            };
+        }
 
-        // Eval and print/write result
-        let table = eval(expr);
+        // In explain mode, the result is not output
+        if args.is_present("explain_analyze") {
+            let (_, stats) = eval_analyzed(*expr.clone())?;
+            println!("Analyzed plan:\n{}", format_analyzed_plan(&expr, &stats));
+            return Ok(());
+        } else if explain {
+            return Ok(());
+        }
+
+        // Eval and print/write result, the entries being written as they are computed
+        let stream = compile(*expr)?;
         match output_file {
-            Some(filename) => write_table(table, filename),
-            None => print_table(table)
+            Some(filename) => write_table(stream, filename, format, &dialect),
+            None => print_table(stream, format, &dialect)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    pub mod eval_test;
+    pub mod optimize_test;
+    pub mod sql_test;
+    pub mod validate_test;
+    pub mod explain_test;
+    pub mod spill_test;
+    pub mod cost_test;
+    pub mod simplify_test;
+    pub mod output_test;
+    pub mod input_test;
+    pub mod temporary_file;
+    pub mod helpers;
+}
@@ -1,42 +1,67 @@
 use crate::types::*;
-use csv::Reader;
+use crate::error::*;
+use crate::cost::*;
+use crate::simplify::simplify;
+use crate::validate::columns_of;
+use crate::input::read_headers;
 use std::collections::HashSet;
 use std::collections::HashMap;
+use std::collections::BTreeSet;
 
 pub trait Optimizer {
-    fn optimize(&self, expression: Box<Expression>) -> Box<Expression>;
+    fn name(&self) -> &'static str;
+    fn optimize(&self, expression: Box<Expression>) -> Result<Box<Expression>>;
 }
 
 pub struct ChainOptimizer { pub optimizers: Vec<Box<dyn Optimizer>> }
 impl Optimizer for ChainOptimizer {
-    fn optimize(&self, expression: Box<Expression>) -> Box<Expression> {
+    fn name(&self) -> &'static str {
+        "Chain"
+    }
+
+    fn optimize(&self, expression: Box<Expression>) -> Result<Box<Expression>> {
+        self.optimize_observed(expression, |_, _| ())
+    }
+}
+
+impl ChainOptimizer {
+    /**
+     * Run the optimizers one after the other, calling observe with the name of each one and the expression it produced.
+     */
+    pub fn optimize_observed(&self, expression: Box<Expression>, mut observe: impl FnMut(&str, &Expression)) -> Result<Box<Expression>> {
         let mut final_expression = expression;
 
         for optimizer in &self.optimizers {
-            final_expression = optimizer.optimize(final_expression);
+            final_expression = optimizer.optimize(final_expression)?;
+            observe(optimizer.name(), &final_expression);
         }
 
-        final_expression
+        Ok(final_expression)
     }
 }
 
 /**
  * Call the optimizer on the children of this expression.
  */
-fn visit_children(optimizer: &dyn Optimizer, expression: Box<Expression>) -> Box<Expression> {
-    Box::new(
-        match *expression {
-            Expression::Select(expression_from, condition) => Expression::Select(optimizer.optimize(expression_from), condition),
-            Expression::Project(expression_from, columns) => Expression::Project(optimizer.optimize(expression_from), columns),
-            Expression::Product(expr1, expr2) => Expression::Product(optimizer.optimize(expr1), optimizer.optimize(expr2)),
-            Expression::Except(expr1, expr2) => Expression::Except(optimizer.optimize(expr1), optimizer.optimize(expr2)),
-            Expression::Union(expr1, expr2) => Expression::Union(optimizer.optimize(expr1), optimizer.optimize(expr2)),
-            Expression::Rename(expression, old_columns, new_columns) => Expression::Rename(optimizer.optimize(expression), old_columns, new_columns),
-            Expression::Load(_, _) => *expression,
-            _ => panic!("Unsupported expression, please run UnfoldComplexExpression")
+fn visit_children(optimizer: &dyn Optimizer, expression: Expression) -> Result<Box<Expression>> {
+    Ok(Box::new(
+        match expression {
+            Expression::Select(expression_from, condition) => Expression::Select(optimizer.optimize(expression_from)?, condition),
+            Expression::Project(expression_from, columns) => Expression::Project(optimizer.optimize(expression_from)?, columns),
+            Expression::Product(expr1, expr2) => Expression::Product(optimizer.optimize(expr1)?, optimizer.optimize(expr2)?),
+            Expression::Except(expr1, expr2) => Expression::Except(optimizer.optimize(expr1)?, optimizer.optimize(expr2)?),
+            Expression::Union(expr1, expr2, matching) => Expression::Union(optimizer.optimize(expr1)?, optimizer.optimize(expr2)?, matching),
+            Expression::Rename(expression, old_columns, new_columns) => Expression::Rename(optimizer.optimize(expression)?, old_columns, new_columns),
+            Expression::Aggregate(expression, group_by, aggregates) => Expression::Aggregate(optimizer.optimize(expression)?, group_by, aggregates),
+            Expression::Sort(expression, keys) => Expression::Sort(optimizer.optimize(expression)?, keys),
+            Expression::Limit(expression, limit, offset) => Expression::Limit(optimizer.optimize(expression)?, limit, offset),
+            Expression::TopN(expression, keys, limit, offset) => Expression::TopN(optimizer.optimize(expression)?, keys, limit, offset),
+            Expression::Distinct(expression) => Expression::Distinct(optimizer.optimize(expression)?),
+            Expression::JoinProjectRename(expr1, expr2, condition, old_attrs, new_attrs) =>
+                Expression::JoinProjectRename(optimizer.optimize(expr1)?, optimizer.optimize(expr2)?, condition, old_attrs, new_attrs),
+            expression @ (Expression::Load(_, _) | Expression::ReadSelectProjectRename(_, _, _, _) | Expression::Empty(_)) => expression
         }
-    )
-
+    ))
 }
 
 /**
@@ -44,42 +69,43 @@ fn visit_children(optimizer: &dyn Optimizer, expression: Box<Expression>) -> Box
  */
 pub struct DetectLoadColumnsOptimizer { }
 impl Optimizer for DetectLoadColumnsOptimizer {
-    fn optimize(&self, expression: Box<Expression>) -> Box<Expression> {
-        if let Expression::Load(filename, None) = *expression { // load expression with no columns detected
-            let mut rdr = Reader::from_path(&filename).unwrap();
-            let mut columns = HashSet::new();
-        
-            for header in rdr.headers().unwrap().into_iter() {
-                columns.insert(String::from(header));
-            }
+    fn name(&self) -> &'static str {
+        "DetectLoadColumns"
+    }
+
+    fn optimize(&self, expression: Box<Expression>) -> Result<Box<Expression>> {
+        if let Expression::Load(file, None) = *expression { // load expression with no columns detected
+            let columns = read_headers(&file)?.into_iter().collect();
 
-            Box::new(Expression::Load(filename, Some(columns)))
+            Ok(Box::new(Expression::Load(file, Some(columns))))
         } else {
-            visit_children(self, expression)
+            visit_children(self, *expression)
         }
     }
 }
 /**
  * Compute the columns exposed by a given expression.
+ *
+ * DetectLoadColumnsOptimizer must be executed before, so that the columns of loaded files are known.
  */
-fn get_exposed_columns(expression: &Box<Expression>) -> HashSet<String> {
-    match &**expression {
+fn get_exposed_columns(expression: &Expression) -> Result<HashSet<String>> {
+    Ok(match expression {
         // Si on n'a pas besoin de tous les fields après, on regarde si on a besoin de nouveau fields pour la condition
-        Expression::Select(expression_from, _) => get_exposed_columns(&expression_from),
+        Expression::Select(expression_from, _) => get_exposed_columns(expression_from)?,
         Expression::Project(_, columns) => columns.iter().cloned().collect(),
         Expression::Product(expr1, expr2) => {
             // Pour les product, on dit qu'on "utilise" un sur ensemble de fields, et on corrige les problèmes dans les load et rename
-            let mut fields1 = get_exposed_columns(&expr1);
-            let fields2 = get_exposed_columns(&expr2);
+            let mut fields1 = get_exposed_columns(expr1)?;
+            let fields2 = get_exposed_columns(expr2)?;
 
             fields1.extend(fields2);
 
             fields1
         },
-        Expression::Except(expr1, _) => get_exposed_columns(&expr1),
-        Expression::Union(expr1, _) => get_exposed_columns(&expr1),
+        Expression::Except(expr1, _) => get_exposed_columns(expr1)?,
+        Expression::Union(expr1, _, _) => get_exposed_columns(expr1)?,
         Expression::Rename(expression, old_columns, new_columns) => {
-            let mut fields = get_exposed_columns(&expression);
+            let mut fields = get_exposed_columns(expression)?;
 
             for i in 0..old_columns.len() {
                 fields.remove(&old_columns[i]);
@@ -88,30 +114,32 @@ fn get_exposed_columns(expression: &Box<Expression>) -> HashSet<String> {
 
             fields
         },
-        Expression::Load(_, columns) => columns.as_ref().unwrap().iter().cloned().collect(),
-        Expression::ReadSelectProjectRename(_, _, _, columns) => columns.into_iter().cloned().collect(),
-        Expression::JoinProjectRename(_, _, _, _, columns) => columns.into_iter().cloned().collect(),
-    }
+        Expression::Aggregate(_, group_by, aggregates) => group_by.iter().cloned().chain(aggregates.iter().map(|(_, _, name)| name.clone())).collect(),
+        Expression::Sort(expression, _) | Expression::Limit(expression, _, _) | Expression::TopN(expression, _, _, _) |
+            Expression::Distinct(expression) => get_exposed_columns(expression)?,
+        Expression::Empty(columns) => columns.iter().cloned().collect(),
+        Expression::Load(_, Some(columns)) => columns.iter().cloned().collect(),
+        Expression::Load(file, None) => return Err(EngineError::Unsupported(format!("columns of {} are unknown, please run DetectLoadColumns", file))),
+        Expression::ReadSelectProjectRename(_, _, _, columns) => columns.iter().cloned().collect(),
+        Expression::JoinProjectRename(_, _, _, _, columns) => columns.iter().cloned().collect(),
+    })
 }
 
-fn columns_used_in_condition(condition: &Box<Condition>, fields: &mut HashSet<String>) {
-    match condition.as_ref() {
+pub fn columns_used_in_condition(condition: &Condition, fields: &mut HashSet<String>) {
+    match condition {
         Condition::True | Condition::False => (),
         Condition::Not(c) => columns_used_in_condition(c, fields),
         Condition::And(c1, c2) | Condition::Or(c1, c2) => {
             columns_used_in_condition(c1, fields);
             columns_used_in_condition(c2, fields);
         },
-        Condition::Equal(v1, v2) | Condition::Less(v1, v2) | Condition::More(v1, v2) => {
-            match v1 {
-                Value::Column(s) => { fields.insert(s.clone()); () },
-                _ => ()
-            }
-            match v2 {
-                Value::Column(s) => { fields.insert(s.clone()); () },
-                _ => ()
-            }
-        }
+        Condition::Equal(v1, v2) | Condition::NotEqual(v1, v2) |
+            Condition::Less(v1, v2) | Condition::LessEq(v1, v2) |
+            Condition::More(v1, v2) | Condition::MoreEq(v1, v2) => {
+            if let Value::Column(s) = v1 { fields.insert(s.clone());  }
+            if let Value::Column(s) = v2 { fields.insert(s.clone());  }
+        },
+        Condition::IsNull(v) => if let Value::Column(s) = v { fields.insert(s.clone()); }
     }
 }
 
@@ -128,16 +156,20 @@ fn rename_value(value: Value, rename_map: &HashMap<String, String>) -> Value {
     }
 }
 
-fn rename_in_condition(condition: Box<Condition>, rename_map: &HashMap<String, String>) -> Box<Condition> {
-    match *condition {
+fn rename_in_condition(condition: Condition, rename_map: &HashMap<String, String>) -> Box<Condition> {
+    match condition {
         Condition::True => Box::new(Condition::True),
         Condition::False => Box::new(Condition::False),
-        Condition::Not(c) => Box::new(Condition::Not(rename_in_condition(c, rename_map))),
-        Condition::And(c1, c2) => Box::new(Condition::And(rename_in_condition(c1, rename_map), rename_in_condition(c2, rename_map))),
-        Condition::Or(c1, c2) => Box::new(Condition::Or(rename_in_condition(c1, rename_map), rename_in_condition(c2, rename_map))),
+        Condition::Not(c) => Box::new(Condition::Not(rename_in_condition(*c, rename_map))),
+        Condition::And(c1, c2) => Box::new(Condition::And(rename_in_condition(*c1, rename_map), rename_in_condition(*c2, rename_map))),
+        Condition::Or(c1, c2) => Box::new(Condition::Or(rename_in_condition(*c1, rename_map), rename_in_condition(*c2, rename_map))),
         Condition::Equal(v1, v2) => Box::new(Condition::Equal(rename_value(v1, rename_map), rename_value(v2, rename_map))),
+        Condition::NotEqual(v1, v2) => Box::new(Condition::NotEqual(rename_value(v1, rename_map), rename_value(v2, rename_map))),
         Condition::Less(v1, v2) => Box::new(Condition::Less(rename_value(v1, rename_map), rename_value(v2, rename_map))),
+        Condition::LessEq(v1, v2) => Box::new(Condition::LessEq(rename_value(v1, rename_map), rename_value(v2, rename_map))),
         Condition::More(v1, v2) => Box::new(Condition::More(rename_value(v1, rename_map), rename_value(v2, rename_map))),
+        Condition::MoreEq(v1, v2) => Box::new(Condition::MoreEq(rename_value(v1, rename_map), rename_value(v2, rename_map))),
+        Condition::IsNull(v) => Box::new(Condition::IsNull(rename_value(v, rename_map))),
     }
 }
 
@@ -147,8 +179,8 @@ fn rename_in_condition(condition: Box<Condition>, rename_map: &HashMap<String, S
  * Takes as input the expression to transform and the fields that must be kept (None if all of them).
  * Returns the updated expressions.
  */
-fn apply_projections_early(expression: Box<Expression>, fields: Option<HashSet<String>>) -> Box<Expression> {
-    match *expression {
+fn apply_projections_early(expression: Box<Expression>, fields: Option<HashSet<String>>) -> Result<Box<Expression>> {
+    Ok(match *expression {
         // Si on n'a pas besoin de tous les fields après, on regarde si on a besoin de nouveau fields pour la condition
         Expression::Select(expression_from, condition) if fields.is_some() => {
             let mut fields_set = fields.unwrap();
@@ -164,10 +196,12 @@ fn apply_projections_early(expression: Box<Expression>, fields: Option<HashSet<S
                 }
             }
 
-            let project_on = fields_set.iter().cloned().collect::<Vec<String>>();
+            // Les fields demandés peuvent être un sur ensemble (cf. product), on ne garde que ceux exposés
+            let exposed = get_exposed_columns(&expression_from)?;
+            let project_on = fields_set.iter().filter(|field| exposed.contains(*field)).cloned().collect::<Vec<String>>();
             fields_set.extend(used_in_condition);
 
-            let expression = Box::new(Expression::Select(apply_projections_early(expression_from, Some(fields_set)), condition));
+            let expression = Box::new(Expression::Select(apply_projections_early(expression_from, Some(fields_set))?, condition));
 
             if projection_required {
                 Box::new(Expression::Project(expression, project_on))
@@ -175,72 +209,127 @@ fn apply_projections_early(expression: Box<Expression>, fields: Option<HashSet<S
                 expression
             }
         },
-        Expression::Select(expression_from, condition) => Box::new(Expression::Select(apply_projections_early(expression_from, fields), condition)),
+        Expression::Select(expression_from, condition) => Box::new(Expression::Select(apply_projections_early(expression_from, fields)?, condition)),
         Expression::Project(expression_from, columns) => 
         {
             // Quand on a un project, les éléments utilisés correspondent exactement aux éléments du project
-            let fields = if fields.is_none() {
-                let mut fields = HashSet::new();
-                for column in columns {
-                    fields.insert(column);
-                }
-
-                fields
-            } else {
-                fields.unwrap()
+            let fields = match fields {
+                Some(fields) => fields,
+                None => columns.into_iter().collect()
             };
 
             // On remonte les project, donc rien à faire ici
-            apply_projections_early(expression_from, Some(fields))
+            apply_projections_early(expression_from, Some(fields))?
         },
         Expression::Product(expr1, expr2) => {
             // Pour les product, on dit qu'on "utilise" un sur ensemble de fields, et on corrige les problèmes dans les load et rename
             let fields2 = fields.clone();
-            let final_expr1 = apply_projections_early(expr1, fields);
-            let final_expr2 = apply_projections_early(expr2, fields2);
+            let final_expr1 = apply_projections_early(expr1, fields)?;
+            let final_expr2 = apply_projections_early(expr2, fields2)?;
 
             Box::new(Expression::Product(final_expr1, final_expr2))
         },
-        Expression::Except(expr1, expr2) => {
+        Expression::Union(expr1, expr2, ColumnMatching::ByName) => {
             let fields2 = fields.clone();
 
-            Box::new(Expression::Except(apply_projections_early(expr1, fields), apply_projections_early(expr2, fields2)))
+            Box::new(Expression::Union(apply_projections_early(expr1, fields)?, apply_projections_early(expr2, fields2)?, ColumnMatching::ByName))
         },
-        Expression::Union(expr1, expr2) => {
-            let fields2 = fields.clone();
+        // Projeter les deux côtés changerait le résultat d'un minus, ou les positions d'une union par position :
+        // on garde donc toutes les colonnes en dessous, et on projette au dessus
+        Expression::Except(_, _) | Expression::Union(_, _, ColumnMatching::ByPosition) => {
+            let exposed = get_exposed_columns(&expression)?;
+            let new_expr = match *expression {
+                Expression::Except(expr1, expr2) => Expression::Except(apply_projections_early(expr1, None)?, apply_projections_early(expr2, None)?),
+                Expression::Union(expr1, expr2, matching) => Expression::Union(apply_projections_early(expr1, None)?, apply_projections_early(expr2, None)?, matching),
+                _ => unreachable!()
+            };
 
-            Box::new(Expression::Union(apply_projections_early(expr1, fields), apply_projections_early(expr2, fields2)))
+            match fields {
+                Some(fields_set) if !exposed.iter().all(|column| fields_set.contains(column)) =>
+                    Box::new(Expression::Project(Box::new(new_expr), exposed.into_iter().filter(|column| fields_set.contains(column)).collect())),
+                _ => Box::new(new_expr)
+            }
         },
         Expression::Rename(expression, old_columns, new_columns) if fields.is_some() => {
             let mut fields_set = fields.unwrap();
 
-            let (old_columns, new_columns) : (Vec<_>, Vec<_>) = old_columns.into_iter().zip(new_columns.into_iter()).filter(
-                |(_, new)| fields_set.contains(new)
+            // Un product demande à ses deux côtés un sur ensemble des colonnes : si l'ancien nom en fait partie,
+            // on garde le renommage, sans quoi ce côté exposerait une colonne de l'autre
+            let (old_columns, new_columns) : (Vec<_>, Vec<_>) = old_columns.into_iter().zip(new_columns).filter(
+                |(old, new)| fields_set.contains(new) || fields_set.contains(old)
             ).unzip();
 
-            for i in 0..old_columns.len() {
-                fields_set.remove(&new_columns[i]);
-                fields_set.insert(old_columns[i].clone());
+            // Tous les retraits avant les ajouts, pour les renommages qui échangent des noms
+            for new_column in &new_columns {
+                fields_set.remove(new_column);
             }
+            fields_set.extend(old_columns.iter().cloned());
+
+            Box::new(Expression::Rename(apply_projections_early(expression, Some(fields_set))?, old_columns, new_columns))
+        },
+        Expression::Rename(expression, old_columns, new_columns) => Box::new(Expression::Rename(apply_projections_early(expression, fields)?, old_columns, new_columns)),
+        Expression::Aggregate(expression, group_by, aggregates) => {
+            // Les aggrégats dont le résultat n'est pas utilisé peuvent être supprimés
+            let aggregates = match fields {
+                Some(fields_set) => aggregates.into_iter().filter(|(_, _, name)| fields_set.contains(name)).collect(),
+                None => aggregates
+            };
+
+            // En dessous, on a besoin des colonnes de groupement et des colonnes aggrégées
+            let mut needed = group_by.iter().cloned().collect::<HashSet<_>>();
+            needed.extend(aggregates.iter().filter(|(_, column, _)| column != "*").map(|(_, column, _)| column.clone()));
 
-            Box::new(Expression::Rename(apply_projections_early(expression, Some(fields_set)), old_columns, new_columns))
+            Box::new(Expression::Aggregate(apply_projections_early(expression, Some(needed))?, group_by, aggregates))
         },
-        Expression::Rename(expression, old_columns, new_columns) => Box::new(Expression::Rename(apply_projections_early(expression, fields), old_columns, new_columns)),
-        Expression::Load(_, ref columns) if fields.is_some() => {
+        Expression::Sort(expression_from, keys) if fields.is_some() => {
+            // Comme pour les sélections, on a besoin des colonnes de tri, quitte à les retirer après
+            let mut fields_set = fields.unwrap();
+
+            let exposed = get_exposed_columns(&expression_from)?;
+            let project_on = fields_set.iter().filter(|field| exposed.contains(*field)).cloned().collect::<Vec<String>>();
+            let projection_required = keys.iter().any(|(column, _)| !fields_set.contains(column));
+            fields_set.extend(keys.iter().map(|(column, _)| column.clone()));
+
+            let expression = Box::new(Expression::Sort(apply_projections_early(expression_from, Some(fields_set))?, keys));
+
+            if projection_required {
+                Box::new(Expression::Project(expression, project_on))
+            } else {
+                expression
+            }
+        },
+        Expression::Sort(expression, keys) => Box::new(Expression::Sort(apply_projections_early(expression, fields)?, keys)),
+        Expression::Limit(expression, limit, offset) => Box::new(Expression::Limit(apply_projections_early(expression, fields)?, limit, offset)),
+        Expression::Distinct(expression) => {
+            // Projeter avant d'éliminer les doublons changerait le résultat, on garde donc toutes les colonnes
+            let exposed = get_exposed_columns(&expression)?;
+            let new_expr = Box::new(Expression::Distinct(apply_projections_early(expression, None)?));
+
+            match fields {
+                Some(fields_set) if !exposed.iter().all(|column| fields_set.contains(column)) =>
+                    Box::new(Expression::Project(new_expr, exposed.into_iter().filter(|column| fields_set.contains(column)).collect())),
+                _ => new_expr
+            }
+        },
+        // DetectLoadColumnsOptimizer must be executed before, sans quoi on ne projette pas
+        Expression::Load(_, Some(ref columns)) if fields.is_some() => {
             let fields_set = fields.unwrap();
-            // DetectLoadColumnsOptimizer must be executed before
-            let project_on = columns.as_ref().unwrap().into_iter().filter(|column| fields_set.contains(*column)).cloned().collect::<Vec<_>>();
+            let project_on = columns.iter().filter(|column| fields_set.contains(*column)).cloned().collect::<Vec<_>>();
 
             // On ajoute une projection que si cela limite réellement les champs dispo
-            if project_on.len() != columns.as_ref().unwrap().len() {
+            if project_on.len() != columns.len() {
                 Box::new(Expression::Project(expression, project_on))
             } else {
                 expression
             }
         },
         Expression::Load(_, _) => expression,
-        _ => panic!("Unsupported expression, please run UnfoldComplexExpression")
-    }
+        Expression::Empty(columns) => match fields {
+            Some(fields_set) => Box::new(Expression::Empty(columns.into_iter().filter(|column| fields_set.contains(column)).collect())),
+            None => Box::new(Expression::Empty(columns))
+        },
+        _ => return Err(EngineError::Unsupported(String::from("ApplyProjectionsEarly needs unfolded expressions, please run UnfoldComplexExpressions before")))
+    })
 }
 
 /**
@@ -248,7 +337,11 @@ fn apply_projections_early(expression: Box<Expression>, fields: Option<HashSet<S
  */
 pub struct ApplyProjectionsEarlyOptimizer { }
 impl Optimizer for ApplyProjectionsEarlyOptimizer {
-    fn optimize(&self, expression: Box<Expression>) -> Box<Expression> {
+    fn name(&self) -> &'static str {
+        "ApplyProjectionsEarly"
+    }
+
+    fn optimize(&self, expression: Box<Expression>) -> Result<Box<Expression>> {
         apply_projections_early(expression, None)
     }
 }
@@ -256,40 +349,51 @@ impl Optimizer for ApplyProjectionsEarlyOptimizer {
 /**
  * Try to push down selections and merge selections.
  */
-fn push_down_selections(mut expression: Box<Expression>, mut selections: Vec<(Box<Condition>, HashSet<String>)>) -> Box<Expression> {
-    match *expression {
+fn push_down_selections(expression: Box<Expression>, mut selections: Vec<(Box<Condition>, HashSet<String>)>) -> Result<Box<Expression>> {
+    Ok(match *expression {
         // Si on n'a pas besoin de tous les fields après, on regarde si on a besoin de nouveau fields pour la condition
         Expression::Select(expression_from, condition) => {
-            let mut fields = HashSet::new();
-            columns_used_in_condition(&condition, &mut fields);
-            selections.push((condition, fields));
+            // Chaque membre d'une conjonction descend de son côté
+            let mut conjuncts = Vec::new();
+            split_conjuncts(*condition, &mut conjuncts);
+            for condition in conjuncts {
+                let mut fields = HashSet::new();
+                columns_used_in_condition(&condition, &mut fields);
+                selections.push((Box::new(condition), fields));
+            }
 
-            push_down_selections(expression_from, selections)
+            push_down_selections(expression_from, selections)?
         },
-        Expression::Project(expression_from, columns) => Box::new(Expression::Project(push_down_selections(expression_from, selections), columns)),
+        Expression::Project(expression_from, columns) => Box::new(Expression::Project(push_down_selections(expression_from, selections)?, columns)),
         Expression::Product(expr1, expr2) => {
-            let fields1 = get_exposed_columns(&expr1);
+            let fields1 = get_exposed_columns(&expr1)?;
 
             // on voit si on peut remonter certaines conditions
             let (selections1, selections): (Vec<_>, Vec<_>) = selections.into_iter().partition(|(_, fields)| fields.iter().all(|field| fields1.contains(field)));
             let (selections2, selections): (Vec<_>, Vec<_>) = selections.into_iter().partition(|(_, fields)| fields.iter().all(|field| !fields1.contains(field)));
 
-            let mut new_expr = Box::new(Expression::Product(push_down_selections(expr1, selections1), push_down_selections(expr2, selections2)));
-
-            for (condition, _) in selections {
-                new_expr = Box::new(Expression::Select(new_expr, condition));
-            }
+            let new_expr = Box::new(Expression::Product(push_down_selections(expr1, selections1)?, push_down_selections(expr2, selections2)?));
 
-            new_expr
+            reapply_selections(new_expr, selections)
         }, 
         Expression::Except(expr1, expr2) => Box::new(Expression::Except(
-            push_down_selections(expr1, selections.clone()),
-            push_down_selections(expr2, selections)
+            push_down_selections(expr1, selections.clone())?,
+            push_down_selections(expr2, selections)?
         )),
-        Expression::Union(expr1, expr2) => Box::new(Expression::Union(
-            push_down_selections(expr1, selections.clone()),
-            push_down_selections(expr2, selections)
+        Expression::Union(expr1, expr2, ColumnMatching::ByName) => Box::new(Expression::Union(
+            push_down_selections(expr1, selections.clone())?,
+            push_down_selections(expr2, selections)?,
+            ColumnMatching::ByName
         )),
+        Expression::Union(expr1, expr2, ColumnMatching::ByPosition) => {
+            // Les colonnes de droite ont d'autres noms, on garde les sélections au dessus
+            let new_expr = Box::new(Expression::Union(
+                push_down_selections(expr1, Vec::new())?,
+                push_down_selections(expr2, Vec::new())?,
+                ColumnMatching::ByPosition
+            ));
+            reapply_selections(new_expr, selections)
+        },
         Expression::Rename(expression, old_columns, new_columns) => {
             let mut rename_map = HashMap::new();
             for i in 0..old_columns.len() {
@@ -297,7 +401,7 @@ fn push_down_selections(mut expression: Box<Expression>, mut selections: Vec<(Bo
             }
 
             let updated_selections = selections.into_iter().map(|(condition, fields)| {
-                (rename_in_condition(condition, &rename_map), fields.into_iter().map(|field| {
+                (rename_in_condition(*condition, &rename_map), fields.into_iter().map(|field| {
                     match rename_map.get(&field) {
                         None => field,
                         Some(new_name) => new_name.clone(),
@@ -305,60 +409,89 @@ fn push_down_selections(mut expression: Box<Expression>, mut selections: Vec<(Bo
                 }).collect())
             }).collect();
 
-            Box::new(Expression::Rename(push_down_selections(expression, updated_selections), old_columns, new_columns))
+            Box::new(Expression::Rename(push_down_selections(expression, updated_selections)?, old_columns, new_columns))
         },
-        Expression::Load(_, _) => {
-            // Reapply selections
-            for (condition, _) in selections {
-                expression = Box::new(Expression::Select(expression, condition));
-            }
+        Expression::Aggregate(expression, group_by, aggregates) => {
+            // Seules les sélections portant sur les colonnes de groupement peuvent passer sous l'aggrégation ; une condition
+            // sans colonne (False...) reste au dessus, sans quoi une aggrégation sans groupement perdrait son entrée
+            let (below, above): (Vec<_>, Vec<_>) = selections.into_iter().partition(|(_, fields)| !fields.is_empty() && fields.iter().all(|field| group_by.contains(field)));
 
-            expression
+            let new_expr = Box::new(Expression::Aggregate(push_down_selections(expression, below)?, group_by, aggregates));
+            reapply_selections(new_expr, above)
         },
-        _ => panic!("Unsupported expression, please run UnfoldComplexExpression")
-    }
+        // Filtrer puis trier (ou dédoublonner) revient à trier puis filtrer
+        Expression::Sort(expression, keys) => Box::new(Expression::Sort(push_down_selections(expression, selections)?, keys)),
+        Expression::Distinct(expression) => Box::new(Expression::Distinct(push_down_selections(expression, selections)?)),
+        Expression::Limit(expression, limit, offset) => {
+            // Une sélection ne peut pas passer sous un limit, qui dépend du nombre de lignes
+            let new_expr = Box::new(Expression::Limit(push_down_selections(expression, Vec::new())?, limit, offset));
+            reapply_selections(new_expr, selections)
+        },
+        Expression::Load(_, _) => reapply_selections(expression, selections),
+        // Filtrer une relation vide ne change rien
+        Expression::Empty(_) => expression,
+        _ => return Err(EngineError::Unsupported(String::from("PushDownSelections needs unfolded expressions, please run UnfoldComplexExpressions before")))
+    })
+}
+
+/**
+ * Put back the selections that cannot go further down, as a single one so that it can be folded.
+ */
+fn reapply_selections(expression: Box<Expression>, selections: Vec<(Box<Condition>, HashSet<String>)>) -> Box<Expression> {
+    select_all(expression, selections.into_iter().map(|(condition, _)| *condition).collect())
 }
 
 pub struct PushDownSelectionsOptimizer { }
 impl Optimizer for PushDownSelectionsOptimizer {
-    fn optimize(&self, expression: Box<Expression>) -> Box<Expression> {
+    fn name(&self) -> &'static str {
+        "PushDownSelections"
+    }
+
+    fn optimize(&self, expression: Box<Expression>) -> Result<Box<Expression>> {
         push_down_selections(expression, Vec::new())
     }
 }
 
 pub struct UnfoldComplexExpressionsOptimizer { }
 impl Optimizer for UnfoldComplexExpressionsOptimizer {
-    fn optimize(&self, expression: Box<Expression>) -> Box<Expression> {
+    fn name(&self) -> &'static str {
+        "UnfoldComplexExpressions"
+    }
 
-        match *expression {
-            Expression::ReadSelectProjectRename(filename, condition, old_attrs, new_attrs) => {
+    fn optimize(&self, expression: Box<Expression>) -> Result<Box<Expression>> {
+
+        Ok(match *expression {
+            Expression::ReadSelectProjectRename(file, condition, old_attrs, new_attrs) => {
                 Box::new(Expression::Rename(
                     Box::new(Expression::Project(
                         Box::new(Expression::Select(
-                            Box::new(Expression::Load(filename, None)),
+                            Box::new(Expression::Load(file, None)),
                             condition
                         )),
-                        old_attrs.iter().cloned().collect()
+                        old_attrs.to_vec()
                     )),
                     old_attrs,
                     new_attrs
                 ))
             },
+            Expression::TopN(expression, keys, limit, offset) => {
+                Box::new(Expression::Limit(Box::new(Expression::Sort(self.optimize(expression)?, keys)), limit, offset))
+            },
             Expression::JoinProjectRename(expr1, expr2, condition, old_attrs, new_attrs) => {
                 Box::new(Expression::Rename(
                     Box::new(Expression::Project(
                         Box::new(Expression::Select(
-                            Box::new(Expression::Product(self.optimize(expr1), self.optimize(expr2))),
+                            Box::new(Expression::Product(self.optimize(expr1)?, self.optimize(expr2)?)),
                             condition
                         )),
-                        old_attrs.iter().cloned().collect()
+                        old_attrs.to_vec()
                     )),
                     old_attrs,
                     new_attrs
                 ))
             },
-            _ => visit_children(self, expression)
-        }
+            _ => visit_children(self, *expression)?
+        })
     }
 }
 
@@ -373,7 +506,11 @@ impl Optimizer for UnfoldComplexExpressionsOptimizer {
  */
 pub struct FoldComplexExpressionsOptimizer { }
 impl Optimizer for FoldComplexExpressionsOptimizer {
-    fn optimize(&self, mut expression: Box<Expression>) -> Box<Expression> {
+    fn name(&self) -> &'static str {
+        "FoldComplexExpressions"
+    }
+
+    fn optimize(&self, mut expression: Box<Expression>) -> Result<Box<Expression>> {
 
         let mut project_on = None;
         let mut rename = None;
@@ -401,9 +538,8 @@ impl Optimizer for FoldComplexExpressionsOptimizer {
         }
 
         // on fold que si on a trouvé une sélection
-        match *expression {
-            Expression::Load(filename, fields) if selection.is_some() => {
-                let mut fields = fields.unwrap();
+        Ok(match *expression {
+            Expression::Load(file, Some(mut fields)) if selection.is_some() => {
                 let condition = selection.unwrap();
 
                 if let Some(project_on_fields) = project_on {
@@ -419,17 +555,17 @@ impl Optimizer for FoldComplexExpressionsOptimizer {
                     new_attrs.push(still_there);
                 }
 
-                Box::new(Expression::ReadSelectProjectRename(filename, condition, old_attrs, new_attrs))
+                Box::new(Expression::ReadSelectProjectRename(file, condition, old_attrs, new_attrs))
             },
             Expression::Product(expr1, expr2) if selection.is_some() => {
-                let expr1 = self.optimize(expr1);
-                let expr2 = self.optimize(expr2);
+                let expr1 = self.optimize(expr1)?;
+                let expr2 = self.optimize(expr2)?;
                 
                 let mut fields = if let Some(project_on_fields) = project_on {
                     project_on_fields.into_iter().collect::<HashSet<_>>()
                 } else {
-                    let mut fields1 = get_exposed_columns(&expr1);
-                    let fields2 = get_exposed_columns(&expr2);
+                    let mut fields1 = get_exposed_columns(&expr1)?;
+                    let fields2 = get_exposed_columns(&expr2)?;
 
                     fields1.extend(fields2);
                     fields1
@@ -449,21 +585,426 @@ impl Optimizer for FoldComplexExpressionsOptimizer {
                 Box::new(Expression::JoinProjectRename(expr1, expr2, condition, old_attrs, new_attrs))
             },
             _ => { // Sinon, on abort et on visite les enfants
-                expression = visit_children(self, expression);
+                expression = visit_children(self, *expression)?;
 
-                if !selection.is_none() {
-                    expression = Box::new(Expression::Select(expression, selection.unwrap()));
+                if let Some(condition) = selection {
+                    expression = Box::new(Expression::Select(expression, condition));
                 }
-                if !project_on.is_none() {
-                    expression = Box::new(Expression::Project(expression, project_on.unwrap()));
+                if let Some(columns) = project_on {
+                    expression = Box::new(Expression::Project(expression, columns));
                 }
-                if !rename.is_none() {
-                    let (old, new) = rename.unwrap();
+                if let Some((old, new)) = rename {
                     expression = Box::new(Expression::Rename(expression, old, new));
                 }
 
                 expression
             }
+        })
+    }
+}
+
+/**
+ * Infer the conditions implied by equalities between columns: from a = b and a = 38, b = 38 is added,
+ * so that PushDownSelections can filter both sides of the join instead of one.
+ *
+ * The conditions of the selections above and between nested products are considered together,
+ * and the inferred ones are added to the topmost selection.
+ */
+pub struct InferPredicatesOptimizer { }
+impl Optimizer for InferPredicatesOptimizer {
+    fn name(&self) -> &'static str {
+        "InferPredicates"
+    }
+
+    fn optimize(&self, expression: Box<Expression>) -> Result<Box<Expression>> {
+        match *expression {
+            Expression::Select(expression_from, condition) => {
+                let mut conditions = Vec::new();
+                split_conjuncts((*condition).clone(), &mut conditions);
+                let expression_from = self.collect_conditions(*expression_from, &mut conditions)?;
+
+                let condition = implied_conditions(&conditions).into_iter().fold(condition, |c1, c2| Box::new(Condition::And(c1, Box::new(c2))));
+                Ok(Box::new(Expression::Select(expression_from, condition)))
+            },
+            _ => visit_children(self, *expression)
+        }
+    }
+}
+
+impl InferPredicatesOptimizer {
+    /**
+     * Add the conjuncts of the selections between nested products to conditions, optimizing the relations below them.
+     */
+    fn collect_conditions(&self, expression: Expression, conditions: &mut Vec<Condition>) -> Result<Box<Expression>> {
+        Ok(match expression {
+            Expression::Select(expression_from, condition) => {
+                split_conjuncts((*condition).clone(), conditions);
+                Box::new(Expression::Select(self.collect_conditions(*expression_from, conditions)?, condition))
+            },
+            Expression::Product(expr1, expr2) => Box::new(Expression::Product(self.collect_conditions(*expr1, conditions)?, self.collect_conditions(*expr2, conditions)?)),
+            expression => self.optimize(Box::new(expression))?
+        })
+    }
+}
+
+/**
+ * The conditions implied by the given conjuncts that are not among them.
+ *
+ * Columns equal to each other have the same values, so a conjunct using a single column also holds
+ * for the columns equal to it.
+ */
+fn implied_conditions(conditions: &[Condition]) -> Vec<Condition> {
+    // Classes d'équivalence des colonnes, triées pour que les conditions déduites ne dépendent pas d'un hachage
+    let mut classes: Vec<BTreeSet<String>> = Vec::new();
+    for condition in conditions {
+        if let Condition::Equal(Value::Column(c1), Value::Column(c2)) = condition {
+            let (linked, mut others): (Vec<_>, Vec<_>) = classes.into_iter().partition(|class| class.contains(c1) || class.contains(c2));
+            let mut class = linked.into_iter().flatten().collect::<BTreeSet<_>>();
+            class.insert(c1.clone());
+            class.insert(c2.clone());
+            others.push(class);
+            classes = others;
+        }
+    }
+
+    let mut implied = Vec::new();
+    for condition in conditions {
+        let mut fields = HashSet::new();
+        columns_used_in_condition(condition, &mut fields);
+        let column = match fields.into_iter().collect::<Vec<_>>()[..] {
+            [ref column] => column.clone(),
+            _ => continue
+        };
+
+        for class in classes.iter().filter(|class| class.contains(&column)) {
+            for other in class.iter().filter(|other| **other != column) {
+                let rename_map = HashMap::from([(column.clone(), other.clone())]);
+                let inferred = *rename_in_condition(condition.clone(), &rename_map);
+
+                if !conditions.contains(&inferred) && !implied.contains(&inferred) {
+                    implied.push(inferred);
+                }
+            }
+        }
+    }
+
+    implied
+}
+
+/**
+ * Simplify the conditions of selections and joins, e.g. folding comparisons between constants.
+ *
+ * A selection whose condition always holds is removed. One whose condition never holds is replaced by an
+ * empty relation, so that its input is never read, and so are the operators above it that keep it empty.
+ */
+pub struct SimplifyConditionsOptimizer { }
+impl Optimizer for SimplifyConditionsOptimizer {
+    fn name(&self) -> &'static str {
+        "SimplifyConditions"
+    }
+
+    fn optimize(&self, expression: Box<Expression>) -> Result<Box<Expression>> {
+        let expression = match *expression {
+            Expression::Select(expression_from, condition) => {
+                let expression_from = self.optimize(expression_from)?;
+
+                match *simplify(*condition) {
+                    Condition::True => return Ok(expression_from),
+                    condition => Box::new(Expression::Select(expression_from, Box::new(condition)))
+                }
+            },
+            Expression::ReadSelectProjectRename(file, condition, old_attrs, new_attrs) =>
+                Box::new(Expression::ReadSelectProjectRename(file, simplify(*condition), old_attrs, new_attrs)),
+            Expression::JoinProjectRename(expr1, expr2, condition, old_attrs, new_attrs) =>
+                Box::new(Expression::JoinProjectRename(self.optimize(expr1)?, self.optimize(expr2)?, simplify(*condition), old_attrs, new_attrs)),
+            _ => visit_children(self, *expression)?
+        };
+
+        if produces_nothing(&expression) {
+            return Ok(Box::new(Expression::Empty(columns_of(&expression)?)));
+        }
+
+        // Un côté vide ne retire ni n'ajoute rien
+        Ok(match *expression {
+            Expression::Except(expr1, expr2) if is_empty(&expr2) => expr1,
+            Expression::Union(expr1, expr2, _) if is_empty(&expr2) => expr1,
+            Expression::Union(expr1, expr2, ColumnMatching::ByName) if is_empty(&expr1) => Box::new(Expression::Project(expr2, columns_of(&expr1)?)),
+            expression => Box::new(expression)
+        })
+    }
+}
+
+fn is_empty(expression: &Expression) -> bool {
+    matches!(expression, Expression::Empty(_))
+}
+
+/**
+ * Whether an expression has no entries, knowing which of its children are empty.
+ */
+fn produces_nothing(expression: &Expression) -> bool {
+    match expression {
+        Expression::Select(expression_from, condition) => is_empty(expression_from) || **condition == Condition::False,
+        Expression::ReadSelectProjectRename(_, condition, _, _) => **condition == Condition::False,
+        Expression::JoinProjectRename(expr1, expr2, condition, _, _) => is_empty(expr1) || is_empty(expr2) || **condition == Condition::False,
+        Expression::Product(expr1, expr2) => is_empty(expr1) || is_empty(expr2),
+        Expression::Except(expr1, _) => is_empty(expr1),
+        Expression::Union(expr1, expr2, _) => is_empty(expr1) && is_empty(expr2),
+        Expression::Limit(_, 0, _) | Expression::TopN(_, _, 0, _) => true,
+        Expression::Project(expression_from, _) | Expression::Rename(expression_from, _, _) | Expression::Sort(expression_from, _) |
+            Expression::Limit(expression_from, _, _) | Expression::TopN(expression_from, _, _, _) | Expression::Distinct(expression_from) => is_empty(expression_from),
+        // Sans groupement, une aggrégation donne une entrée même sur une relation vide
+        Expression::Aggregate(expression_from, group_by, _) => !group_by.is_empty() && is_empty(expression_from),
+        Expression::Empty(_) | Expression::Load(_, _) => false
+    }
+}
+
+/**
+ * Reorder nested products, so that relations are joined along the conditions that link them.
+ *
+ * Products and the selections above them are flattened into relations and conjuncts. The relations are then
+ * joined greedily: at each step, the two groups linked by a condition whose join has the lowest estimated cost
+ * are joined, under the conditions linking them. A cartesian product is only built when no condition links
+ * the remaining groups. The columns keep their order. Needs DetectLoadColumns.
+ */
+pub struct ReorderJoinsOptimizer { }
+impl Optimizer for ReorderJoinsOptimizer {
+    fn name(&self) -> &'static str {
+        "ReorderJoins"
+    }
+
+    fn optimize(&self, expression: Box<Expression>) -> Result<Box<Expression>> {
+        if !matches!(*expression, Expression::Select(_, _) | Expression::Product(_, _)) || count_relations(&expression) < 3 {
+            return visit_children(self, *expression);
+        }
+
+        let columns = columns_of(&expression)?;
+        let mut relations = Vec::new();
+        let mut conditions = Vec::new();
+        flatten_products(*expression, &mut relations, &mut conditions);
+
+        let relations = relations.into_iter().map(|relation| Ok(*self.optimize(Box::new(relation))?)).collect::<Result<Vec<_>>>()?;
+        let reordered = join_greedily(relations, conditions)?;
+
+        // Un produit dans un autre ordre donne ses colonnes dans un autre ordre, qu'on rétablit
+        if columns_of(&reordered)? == columns {
+            Ok(reordered)
+        } else {
+            Ok(Box::new(Expression::Project(reordered, columns)))
+        }
+    }
+}
+
+fn count_relations(expression: &Expression) -> usize {
+    match expression {
+        Expression::Select(expression_from, _) => count_relations(expression_from),
+        Expression::Product(expr1, expr2) => count_relations(expr1) + count_relations(expr2),
+        _ => 1
+    }
+}
+
+fn flatten_products(expression: Expression, relations: &mut Vec<Expression>, conditions: &mut Vec<Condition>) {
+    match expression {
+        Expression::Select(expression_from, condition) => {
+            split_conjuncts(*condition, conditions);
+            flatten_products(*expression_from, relations, conditions);
+        },
+        Expression::Product(expr1, expr2) => {
+            flatten_products(*expr1, relations, conditions);
+            flatten_products(*expr2, relations, conditions);
+        },
+        expression => relations.push(expression)
+    }
+}
+
+fn split_conjuncts(condition: Condition, conditions: &mut Vec<Condition>) {
+    match condition {
+        Condition::And(c1, c2) => {
+            split_conjuncts(*c1, conditions);
+            split_conjuncts(*c2, conditions);
+        },
+        Condition::True => (),
+        condition => conditions.push(condition)
+    }
+}
+
+fn select_all(expression: Box<Expression>, conditions: Vec<Condition>) -> Box<Expression> {
+    match conditions.into_iter().map(Box::new).reduce(|c1, c2| Box::new(Condition::And(c1, c2))) {
+        Some(condition) => Box::new(Expression::Select(expression, condition)),
+        None => expression
+    }
+}
+
+/**
+ * Relations already joined together, and their columns.
+ */
+struct JoinGroup {
+    plan: Box<Expression>,
+    columns: HashSet<String>
+}
+
+fn join_greedily(relations: Vec<Expression>, conditions: Vec<Condition>) -> Result<Box<Expression>> {
+    let mut groups = relations.into_iter().map(|plan| Ok(JoinGroup { columns: get_exposed_columns(&plan)?, plan: Box::new(plan) })).collect::<Result<Vec<_>>>()?;
+    let mut conditions = conditions.into_iter().map(|condition| {
+        let mut fields = HashSet::new();
+        columns_used_in_condition(&condition, &mut fields);
+        (condition, fields)
+    }).collect::<Vec<_>>();
+
+    // Les conditions sur une seule relation restent sur celle-ci
+    for group in groups.iter_mut() {
+        let (own, others): (Vec<_>, Vec<_>) = conditions.into_iter().partition(|(_, fields)| !fields.is_empty() && fields.is_subset(&group.columns));
+        conditions = others;
+        group.plan = select_all(group.plan.clone(), own.into_iter().map(|(condition, _)| condition).collect());
+    }
+
+    while groups.len() > 1 {
+        let linking = |group1: &JoinGroup, group2: &JoinGroup| conditions.iter()
+            .filter(|(_, fields)| !fields.is_empty() && fields.iter().all(|field| group1.columns.contains(field) || group2.columns.contains(field)))
+            .map(|(condition, _)| condition.clone())
+            .collect::<Vec<_>>();
+
+        let mut pairs = Vec::new();
+        for i in 0..groups.len() {
+            for j in i+1..groups.len() {
+                pairs.push((i, j, linking(&groups[i], &groups[j])));
+            }
         }
+        let connected = pairs.iter().any(|(_, _, linking)| !linking.is_empty());
+
+        let mut best: Option<(f64, usize, usize)> = None;
+        for (i, j, linking) in pairs {
+            if connected && linking.is_empty() {
+                continue;
+            }
+
+            let candidate = select_all(Box::new(Expression::Product(groups[i].plan.clone(), groups[j].plan.clone())), linking);
+            let cost = estimate(&candidate)?.cost;
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, i, j));
+            }
+        }
+
+        // Il y a au moins deux groupes, donc au moins une paire
+        let (_, i, j) = best.ok_or_else(|| EngineError::Unsupported(String::from("no relations to join")))?;
+        let group2 = groups.remove(j);
+        let group1 = groups.remove(i);
+
+        let mut columns = group1.columns;
+        columns.extend(group2.columns);
+        let (linking, others): (Vec<_>, Vec<_>) = conditions.into_iter().partition(|(_, fields)| !fields.is_empty() && fields.is_subset(&columns));
+        conditions = others;
+
+        let plan = select_all(Box::new(Expression::Product(group1.plan, group2.plan)), linking.into_iter().map(|(condition, _)| condition).collect());
+        groups.insert(i, JoinGroup { plan, columns });
     }
-}
\ No newline at end of file
+
+    // Les conditions restantes ne portent sur aucune colonne connue, on les garde au dessus
+    let plan = groups.pop().map(|group| group.plan).ok_or_else(|| EngineError::Unsupported(String::from("no relations to join")))?;
+    Ok(select_all(plan, conditions.into_iter().map(|(condition, _)| condition).collect()))
+}
+
+/**
+ * Put first the side of each join with the fewest estimated entries, as hash joins keep it in memory.
+ * Both orders are compared with the cost model, from the statistics of the files.
+ */
+pub struct ChooseJoinSidesOptimizer { }
+impl Optimizer for ChooseJoinSidesOptimizer {
+    fn name(&self) -> &'static str {
+        "ChooseJoinSides"
+    }
+
+    fn optimize(&self, expression: Box<Expression>) -> Result<Box<Expression>> {
+        match *expression {
+            // Les colonnes de sortie sont données par la projection du jpr : les côtés n'ayant aucune colonne en commun
+            // (ApplyProjectionsEarly garde les renommages qui les distinguent), elles ne dépendent pas de leur ordre
+            Expression::JoinProjectRename(expr1, expr2, condition, old_attrs, new_attrs) => {
+                let (expr1, expr2) = (self.optimize(expr1)?, self.optimize(expr2)?);
+                let swapped = Expression::JoinProjectRename(expr2.clone(), expr1.clone(), condition.clone(), old_attrs.clone(), new_attrs.clone());
+
+                cheapest(vec![Expression::JoinProjectRename(expr1, expr2, condition, old_attrs, new_attrs), swapped])
+            },
+            _ => visit_children(self, *expression)
+        }
+    }
+}
+
+/**
+ * Fuse a sort followed by a limit into a top-n, which only keeps the first entries in a bounded heap.
+ *
+ * Should be run last, as the other optimizations do not know about top-n expressions.
+ */
+pub struct FuseSortLimitOptimizer { }
+impl Optimizer for FuseSortLimitOptimizer {
+    fn name(&self) -> &'static str {
+        "FuseSortLimit"
+    }
+
+    fn optimize(&self, expression: Box<Expression>) -> Result<Box<Expression>> {
+        Ok(match *expression {
+            Expression::Limit(expr, limit, offset) => match *expr {
+                Expression::Sort(expr, keys) => Box::new(Expression::TopN(self.optimize(expr)?, keys, limit, offset)),
+                // ApplyProjectionsEarly may have put a projection in between
+                Expression::Project(project_from, columns) if matches!(*project_from, Expression::Sort(_, _)) => {
+                    let top_n = self.optimize(Box::new(Expression::Limit(project_from, limit, offset)))?;
+                    Box::new(Expression::Project(top_n, columns))
+                },
+                expr => Box::new(Expression::Limit(self.optimize(Box::new(expr))?, limit, offset))
+            },
+            _ => visit_children(self, *expression)?
+        })
+    }
+}
+
+/**
+ * Compute a set of columns whose values identify entries of an expression, if we know one.
+ * An expression with a key is free of duplicates.
+ */
+fn get_key(expression: &Expression) -> Option<HashSet<String>> {
+    match expression {
+        // Sans les colonnes des fichiers chargés, on ne connaît pas de clé
+        Expression::Distinct(expression_from) => get_exposed_columns(expression_from).ok(),
+        Expression::Aggregate(_, group_by, _) => Some(group_by.iter().cloned().collect()),
+        // Une relation vide n'a pas de doublons
+        Expression::Empty(_) => Some(HashSet::new()),
+        Expression::Select(expression_from, _) | Expression::Sort(expression_from, _) | Expression::Limit(expression_from, _, _) |
+            Expression::TopN(expression_from, _, _, _) | Expression::Except(expression_from, _) => get_key(expression_from),
+        Expression::Project(expression_from, columns) => get_key(expression_from).filter(|key| key.iter().all(|column| columns.contains(column))),
+        Expression::Rename(expression_from, old_columns, new_columns) => get_key(expression_from).map(|key| key.into_iter().map(|column| {
+            match old_columns.iter().position(|old| *old == column) {
+                Some(i) => new_columns[i].clone(),
+                None => column
+            }
+        }).collect()),
+        Expression::Product(expr1, expr2) => match (get_key(expr1), get_key(expr2)) {
+            (Some(mut key1), Some(key2)) => { key1.extend(key2); Some(key1) },
+            _ => None
+        },
+        Expression::Union(_, _, _) | Expression::Load(_, _) | Expression::ReadSelectProjectRename(_, _, _, _) |
+            Expression::JoinProjectRename(_, _, _, _, _) => None
+    }
+}
+
+/**
+ * Remove duplicate eliminations on expressions that are already free of duplicates,
+ * e.g. after an aggregation or below a projection that keeps a key.
+ * The remaining ones are moved below sorts, so that fewer entries are sorted.
+ *
+ * DetectLoadColumnsOptimizer must be executed before.
+ */
+pub struct RemoveRedundantDistinctOptimizer { }
+impl Optimizer for RemoveRedundantDistinctOptimizer {
+    fn name(&self) -> &'static str {
+        "RemoveRedundantDistinct"
+    }
+
+    fn optimize(&self, expression: Box<Expression>) -> Result<Box<Expression>> {
+        Ok(match *expression {
+            Expression::Distinct(expression_from) if get_key(&expression_from).is_some() => self.optimize(expression_from)?,
+            Expression::Distinct(expression_from) => match *expression_from {
+                Expression::Sort(sort_from, keys) => Box::new(Expression::Sort(self.optimize(Box::new(Expression::Distinct(sort_from)))?, keys)),
+                expression_from => Box::new(Expression::Distinct(self.optimize(Box::new(expression_from))?))
+            },
+            _ => visit_children(self, *expression)?
+        })
+    }
+}
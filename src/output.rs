@@ -1,43 +1,295 @@
 use crate::types::*;
-use csv::Writer;
+use crate::error::*;
+use crate::eval::Stream;
+use csv::{QuoteStyle, Terminator, WriterBuilder};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 
-pub fn print_table(t: Table) {
-    let (fields, values) = t;
+/**
+ * How tables are written as CSV.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    pub quote_style: QuoteStyle,
+    pub terminator: Terminator,
+    pub header: bool // whether the first line holds the column names
+}
+
+impl Default for CsvDialect {
+    fn default() -> CsvDialect {
+        CsvDialect { delimiter: b',', quote_style: QuoteStyle::Necessary, terminator: Terminator::CRLF, header: true }
+    }
+}
+
+/**
+ * Parse a delimiter: a single ASCII character, or "tab".
+ */
+pub fn parse_delimiter(delimiter: &str) -> Result<u8> {
+    match delimiter {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if delimiter.len() == 1 && delimiter.is_ascii() => Ok(delimiter.as_bytes()[0]),
+        _ => Err(EngineError::parse("--delimiter", format!("\"{}\" is not a single ASCII character", delimiter)))
+    }
+}
 
-    let mut keys = vec![""; fields.len()];
-    for (key, value) in &fields {
-        keys[*value] = &key;
+pub fn parse_quote_style(quote_style: &str) -> Result<QuoteStyle> {
+    match quote_style {
+        "necessary" => Ok(QuoteStyle::Necessary),
+        "always" => Ok(QuoteStyle::Always),
+        "non-numeric" => Ok(QuoteStyle::NonNumeric),
+        "never" => Ok(QuoteStyle::Never),
+        _ => Err(EngineError::parse("--quote-style", format!("\"{}\" is not one of necessary, always, non-numeric or never", quote_style)))
     }
+}
 
-    for key in keys {
-        print!("{} ", key);
+pub fn parse_terminator(terminator: &str) -> Result<Terminator> {
+    match terminator {
+        "crlf" => Ok(Terminator::CRLF),
+        "lf" => Ok(Terminator::Any(b'\n')),
+        _ => Err(EngineError::parse("--line-terminator", format!("\"{}\" is not one of crlf or lf", terminator)))
     }
-    println!();
-    for j in 0..values.len() {
-        for i in 0..fields.len() {
-            print!("{:?} ", values[j][i]);
+}
+
+/**
+ * The column names, ordered by their position in the entries; a position without any name gets an empty one.
+ */
+pub fn ordered_columns(fields: &HashMap<String, usize>) -> Vec<&str> {
+    let width = fields.values().map(|position| position + 1).max().unwrap_or(0);
+    let mut keys = vec![""; width];
+    for (key, value) in fields {
+        keys[*value] = key;
+    }
+
+    keys
+}
+
+/**
+ * The formats results can be written in.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Table, // columns aligned with spaces, for humans
+    Csv,
+    Tsv,
+    Json, // an array of objects
+    JsonLines, // an object per line
+    Markdown
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Result<OutputFormat> {
+        match name {
+            "table" => Ok(OutputFormat::Table),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::JsonLines),
+            "markdown" => Ok(OutputFormat::Markdown),
+            _ => Err(EngineError::parse("--format", format!("\"{}\" is not one of table, csv, tsv, json, jsonl or markdown", name)))
         }
-        println!();
     }
+
+    /**
+     * Guess the format of an output file from its extension, defaulting to CSV; the standard output gets a text table.
+     */
+    pub fn from_path(path: &Option<String>) -> OutputFormat {
+        let filename = match path {
+            Some(filename) => filename.to_lowercase(),
+            None => return OutputFormat::Table
+        };
+
+        match filename.rsplit('.').next() {
+            Some("tsv") | Some("tab") => OutputFormat::Tsv,
+            Some("json") => OutputFormat::Json,
+            Some("jsonl") | Some("ndjson") => OutputFormat::JsonLines,
+            Some("md") | Some("markdown") => OutputFormat::Markdown,
+            Some("txt") => OutputFormat::Table,
+            _ => OutputFormat::Csv
+        }
+    }
+}
+
+pub fn print_table(t: Stream, format: OutputFormat, dialect: &CsvDialect) -> Result<()> {
+    let stdout = io::stdout();
+
+    write_output(t, stdout.lock(), "<stdout>", format, dialect)
+}
+
+pub fn write_table(t: Stream, filename: String, format: OutputFormat, dialect: &CsvDialect) -> Result<()> {
+    let file = File::create(&filename).map_err(|e| EngineError::io(&filename, e))?;
+
+    write_output(t, file, &filename, format, dialect)
+}
+
+/**
+ * Write a table in the given format, its columns in the order of their positions; destination names the writer in errors.
+ * The dialect is used by CSV, and by TSV except for its delimiter.
+ *
+ * CSV, TSV and JSON entries are written as they are pulled, while text and markdown tables, whose columns are
+ * aligned on their widest cell, keep all of them in memory.
+ */
+pub fn write_output(t: Stream, writer: impl Write, destination: &str, format: OutputFormat, dialect: &CsvDialect) -> Result<()> {
+    let mut writer = BufWriter::new(writer);
+
+    match format {
+        OutputFormat::Csv => write_csv(t, &mut writer, destination, dialect)?,
+        OutputFormat::Tsv => write_csv(t, &mut writer, destination, &CsvDialect { delimiter: b'\t', ..*dialect })?,
+        OutputFormat::Table => write_text_table(t, &mut writer, destination)?,
+        OutputFormat::Json => write_json(t, &mut writer, destination)?,
+        OutputFormat::JsonLines => write_json_lines(t, &mut writer, destination)?,
+        OutputFormat::Markdown => write_markdown(t, &mut writer, destination)?
+    }
+
+    writer.flush().map_err(|e| EngineError::io(destination, e))
 }
 
-pub fn write_table(t: Table, filename: String) {
-    let mut wtr = Writer::from_path(filename).unwrap();
+/**
+ * Write a table as CSV, its columns in the order of their positions; destination names the writer in errors.
+ */
+pub fn write_csv(t: Stream, writer: impl Write, destination: &str, dialect: &CsvDialect) -> Result<()> {
+    let mut wtr = WriterBuilder::new()
+        .delimiter(dialect.delimiter)
+        .quote_style(dialect.quote_style)
+        .terminator(dialect.terminator)
+        .from_writer(writer);
 
     let (headers, entries) = t;
 
-    wtr.write_record(headers.keys()).unwrap();
+    if dialect.header {
+        wtr.write_record(ordered_columns(&headers)).map_err(|e| EngineError::io(destination, e))?;
+    }
     for entry in entries {
-        wtr.write_record(entry.into_iter().map(|v| get_string_for_value(v))).unwrap();
+        let record = entry?.into_iter().map(get_string_for_value).collect::<Result<Vec<_>>>()?;
+        wtr.write_record(record).map_err(|e| EngineError::io(destination, e))?;
     }
 
-    wtr.flush().unwrap();
+    wtr.flush().map_err(|e| EngineError::io(destination, e))
 }
 
-fn get_string_for_value(v: Value) -> String {
+fn get_string_for_value(v: Value) -> Result<String> {
     match v {
-        Value::Int(i) => i.to_string(),
-        Value::Str(s) => s,
-        _ => panic!("A column shouldn't appear at this point")
+        Value::Int(i) => Ok(i.to_string()),
+        Value::Float(x) => Ok(float_to_string(x)),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Date(d) => Ok(d.format("%Y-%m-%d").to_string()),
+        Value::Str(s) => Ok(s),
+        Value::Null => Ok(String::new()), // comme les champs vides lus
+        Value::Column(c) => Err(EngineError::Type(format!("column {} shouldn't appear in a result", c)))
     }
-}
\ No newline at end of file
+}
+
+/**
+ * The values of the entries as text, NULL being written out, and whether each column only holds numbers (or NULL),
+ * to align them to the right.
+ */
+fn text_cells(entries: Vec<Entry>, width: usize) -> Result<(Vec<Vec<String>>, Vec<bool>)> {
+    let mut numeric = vec![!entries.is_empty(); width];
+    for entry in &entries {
+        for (i, value) in entry.iter().enumerate() {
+            numeric[i] = numeric[i] && matches!(value, Value::Int(_) | Value::Float(_) | Value::Null);
+        }
+    }
+    let cell = |value: Value| match value {
+        Value::Null => Ok(String::from("NULL")),
+        value => get_string_for_value(value)
+    };
+    let rows = entries.into_iter().map(|entry| entry.into_iter().map(cell).collect()).collect::<Result<Vec<_>>>()?;
+
+    Ok((rows, numeric))
+}
+
+fn write_text_table(t: Stream, writer: &mut impl Write, destination: &str) -> Result<()> {
+    let (headers, entries) = t;
+    let columns = ordered_columns(&headers);
+    let (rows, numeric) = text_cells(entries.collect::<Result<Vec<_>>>()?, columns.len())?;
+
+    let mut widths = columns.iter().map(|column| column.chars().count()).collect::<Vec<_>>();
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: &[&str]| cells.iter().enumerate().map(|(i, cell)| {
+        let padding = " ".repeat(widths[i] - cell.chars().count());
+        if numeric[i] { format!("{}{}", padding, cell) } else { format!("{}{}", cell, padding) }
+    }).collect::<Vec<_>>().join(" | ").trim_end().to_string();
+
+    let mut output = line(&columns) + "\n";
+    output += &widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<_>>().join("-+-");
+    output += "\n";
+    for row in &rows {
+        output += &line(&row.iter().map(String::as_str).collect::<Vec<_>>());
+        output += "\n";
+    }
+
+    writer.write_all(output.as_bytes()).map_err(|e| EngineError::io(destination, e))
+}
+
+fn write_markdown(t: Stream, writer: &mut impl Write, destination: &str) -> Result<()> {
+    let (headers, entries) = t;
+    let columns = ordered_columns(&headers);
+    let (rows, numeric) = text_cells(entries.collect::<Result<Vec<_>>>()?, columns.len())?;
+
+    // Une barre verticale ou un retour à la ligne couperait la cellule
+    let escaped = |cell: &str| cell.replace('|', "\\|").replace("\r\n", "<br>").replace('\n', "<br>");
+    let line = |cells: Vec<String>| format!("| {} |\n", cells.join(" | "));
+
+    let mut output = line(columns.iter().map(|column| escaped(column)).collect());
+    output += &line(numeric.iter().map(|numeric| String::from(if *numeric { "---:" } else { "---" })).collect());
+    for row in rows {
+        output += &line(row.iter().map(|cell| escaped(cell)).collect());
+    }
+
+    writer.write_all(output.as_bytes()).map_err(|e| EngineError::io(destination, e))
+}
+
+/**
+ * An entry as a JSON object, its keys in the order of the columns.
+ */
+fn json_object(columns: &[&str], entry: Entry, separator: &str) -> Result<String> {
+    let fields = columns.iter().zip(entry).map(|(column, value)| {
+        let value = match value {
+            Value::Int(i) => i.to_string(),
+            Value::Float(x) => float_to_string(x),
+            Value::Bool(b) => b.to_string(),
+            Value::Date(d) => format!("\"{}\"", d.format("%Y-%m-%d")), // JSON n'a pas de type date
+            Value::Str(s) => serde_json::Value::String(s).to_string(),
+            Value::Null => String::from("null"),
+            Value::Column(c) => return Err(EngineError::Type(format!("column {} shouldn't appear in a result", c)))
+        };
+
+        Ok(format!("{}:{}{}", serde_json::Value::String(String::from(*column)), separator, value))
+    }).collect::<Result<Vec<_>>>()?;
+
+    Ok(format!("{{{}}}", fields.join(&format!(",{}", separator))))
+}
+
+fn write_json(t: Stream, writer: &mut impl Write, destination: &str) -> Result<()> {
+    let (headers, entries) = t;
+    let columns = ordered_columns(&headers);
+
+    // Chaque objet est précédé de ce qui le sépare du précédent, pour ne rien garder en mémoire
+    let mut empty = true;
+    for entry in entries {
+        let separator = if empty { "[\n" } else { ",\n" };
+        write!(writer, "{}  {}", separator, json_object(&columns, entry?, " ")?).map_err(|e| EngineError::io(destination, e))?;
+        empty = false;
+    }
+    let end = if empty { "[]\n" } else { "\n]\n" };
+
+    writer.write_all(end.as_bytes()).map_err(|e| EngineError::io(destination, e))
+}
+
+fn write_json_lines(t: Stream, writer: &mut impl Write, destination: &str) -> Result<()> {
+    let (headers, entries) = t;
+    let columns = ordered_columns(&headers);
+
+    for entry in entries {
+        writeln!(writer, "{}", json_object(&columns, entry?, "")?).map_err(|e| EngineError::io(destination, e))?;
+    }
+
+    Ok(())
+}
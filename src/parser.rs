@@ -1,21 +1,125 @@
 use crate::types::*;
+use crate::sql::*;
+use crate::error::*;
+use chrono::NaiveDate;
 use std::fs::File;
 
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use std::io::BufReader;
 use std::io::{self, Read};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum ConditionParse {
+    Constant(ConstantParse), // "True" or "False"
+    Log1 {logical: Logical1Parse, condition: Box<ConditionParse>},
+    Log2 {logical: Logical2Parse, condition1: Box<ConditionParse>, condition2: Box<ConditionParse>},
+    Comp {comparator: ComparatorParse, attribute1: AttributeParse, attribute2: AttributeParse},
+    NullTest {comparator: NullTestParse, attribute: AttributeParse}
+}
+
+#[derive(Deserialize, Serialize)]
+pub enum ConstantParse {
     True,
-    False,
-    Log1 {logical: String, condition: Box<ConditionParse>},
-    Log2 {logical: String, condition1: Box<ConditionParse>, condition2: Box<ConditionParse>},
-    Comp {comparator: String, attribute1: String, attribute2: String}
+    False
+}
+
+#[derive(Deserialize, Serialize)]
+pub enum Logical1Parse {
+    #[serde(rename = "not")]
+    Not
+}
+
+#[derive(Deserialize, Serialize)]
+pub enum Logical2Parse {
+    #[serde(rename = "or")]
+    Or,
+    #[serde(rename = "and")]
+    And
+}
+
+#[derive(Deserialize, Serialize)]
+pub enum ComparatorParse {
+    #[serde(rename = "=")]
+    Equal,
+    #[serde(rename = "!=", alias = "<>")]
+    NotEqual,
+    #[serde(rename = "<")]
+    Less,
+    #[serde(rename = "<=")]
+    LessEq,
+    #[serde(rename = ">")]
+    More,
+    #[serde(rename = ">=")]
+    MoreEq
+}
+
+#[derive(Deserialize, Serialize)]
+pub enum NullTestParse {
+    #[serde(rename = "is null")]
+    IsNull,
+    #[serde(rename = "is not null")]
+    IsNotNull
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum AttributeParse {
+    Literal {literal: LiteralParse}, // an explicit literal, e.g. {"literal": "Ursa"}, {"literal": 12.5}, {"literal": true} or {"literal": null}
+    Date {#[serde(with = "iso_date")] date: NaiveDate}, // e.g. {"date": "2021-03-14"}
+    Name(String) // a column name, or a number
+}
+
+/**
+ * The constants written as JSON values; the order of the variants matters, 12 being an integer and not a float.
+ */
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum LiteralParse {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String)
+}
+
+/**
+ * Dates, written as ISO-8601 strings.
+ */
+mod iso_date {
+    use chrono::NaiveDate;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<NaiveDate, D::Error> {
+        let date = String::deserialize(deserializer)?;
+        NaiveDate::parse_from_str(&date, "%Y-%m-%d").map_err(|e| D::Error::custom(format!("\"{}\" is not a date like 2021-03-14: {}", date, e)))
+    }
+
+    pub fn serialize<S: Serializer>(date: &NaiveDate, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&date.format("%Y-%m-%d").to_string())
+    }
 }
 
-#[derive(Deserialize)]
+impl From<AttributeParse> for Value {
+    fn from(attribute: AttributeParse) -> Value {
+        match attribute {
+            AttributeParse::Literal {literal: LiteralParse::Null} => Value::Null,
+            AttributeParse::Literal {literal: LiteralParse::Bool(b)} => Value::Bool(b),
+            AttributeParse::Literal {literal: LiteralParse::Int(i)} => Value::Int(i),
+            AttributeParse::Literal {literal: LiteralParse::Float(x)} => Value::Float(x),
+            AttributeParse::Literal {literal: LiteralParse::Str(s)} => Value::Str(s),
+            AttributeParse::Date {date} => Value::Date(date),
+            AttributeParse::Name(name) => match (name.parse::<i64>(), name.parse::<f64>()) {
+                (Ok(i), _) => Value::Int(i),
+                (_, Ok(x)) if x.is_finite() => Value::Float(x),
+                _ => Value::Column(name)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
 #[serde(tag = "operation", content = "args")] 
 pub enum ExpressionParse {
     #[serde(rename = "selection")]
@@ -27,50 +131,169 @@ pub enum ExpressionParse {
     #[serde(rename = "minus")]
     Except {object1: Box<ExpressionParse>, object2: Box<ExpressionParse>},
     #[serde(rename = "union")]
-    Union {object1: Box<ExpressionParse>, object2: Box<ExpressionParse>},
+    Union {object1: Box<ExpressionParse>, object2: Box<ExpressionParse>, #[serde(default = "default_matching")] columns: ColumnMatching},
+    #[serde(rename = "union all")]
+    UnionAll {object1: Box<ExpressionParse>, object2: Box<ExpressionParse>, #[serde(default = "default_matching")] columns: ColumnMatching},
+    #[serde(rename = "distinct")]
+    Distinct {object: Box<ExpressionParse>},
     #[serde(rename = "product")]
     Product {object1: Box<ExpressionParse>, object2: Box<ExpressionParse>},
     #[serde(rename = "load")]
-    Read {filename: String},
+    Read {filename: String, #[serde(flatten)] dialect: DialectParse},
+    #[serde(rename = "empty")]
+    Empty {attributes: Vec<String>},
     #[serde(rename = "rspr")]
-    ReadSelectProjectRename {filename: String, condition: Box<ConditionParse>, #[serde(rename = "old attributes")] old_attributes: Vec<String>, #[serde(rename = "new attributes")] new_attributes: Vec<String>},
+    ReadSelectProjectRename {filename: String, #[serde(flatten)] dialect: DialectParse, condition: Box<ConditionParse>, #[serde(rename = "old attributes")] old_attributes: Vec<String>, #[serde(rename = "new attributes")] new_attributes: Vec<String>},
+    #[serde(rename = "aggregate")]
+    Aggregate {object: Box<ExpressionParse>, #[serde(rename = "group by")] group_by: Vec<String>, aggregates: Vec<AggregateParse>},
+    #[serde(rename = "sort")]
+    Sort {object: Box<ExpressionParse>, keys: Vec<SortKeyParse>},
+    #[serde(rename = "limit")]
+    Limit {object: Box<ExpressionParse>, limit: usize, #[serde(default)] offset: usize},
+    #[serde(rename = "topn")]
+    TopN {object: Box<ExpressionParse>, keys: Vec<SortKeyParse>, limit: usize, #[serde(default)] offset: usize},
     #[serde(rename = "jpr")]
     JoinProjectRename {object1: Box<ExpressionParse>, object2: Box<ExpressionParse>, condition: Box<ConditionParse>, #[serde(rename = "old attributes")] old_attributes: Vec<String>, #[serde(rename = "new attributes")] new_attributes: Vec<String>}
 }
 
+/**
+ * The optional arguments of load and rspr describing how to read the file, the defaults being left out.
+ */
+#[derive(Deserialize, Serialize, Default)]
+pub struct DialectParse {
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "ascii_char")]
+    delimiter: Option<u8>, // a single character, or "tab"
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "ascii_char")]
+    quote: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    header: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    columns: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "ascii_char")]
+    comment: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    trim: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "null values")]
+    nulls: Option<Vec<String>>
+}
+
+/**
+ * Characters of CSV dialects, written as strings of a single ASCII character.
+ */
+mod ascii_char {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<u8>, D::Error> {
+        match Option::<String>::deserialize(deserializer)?.as_deref() {
+            None => Ok(None),
+            Some("tab") | Some("\t") => Ok(Some(b'\t')),
+            Some(c) if c.len() == 1 && c.is_ascii() => Ok(Some(c.as_bytes()[0])),
+            Some(c) => Err(D::Error::custom(format!("\"{}\" is not a single ASCII character", c)))
+        }
+    }
+
+    pub fn serialize<S: Serializer>(c: &Option<u8>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match c {
+            Some(b'\t') => serializer.serialize_str("tab"),
+            Some(c) => serializer.serialize_str(&(*c as char).to_string()),
+            None => serializer.serialize_none()
+        }
+    }
+}
+
+impl From<DialectParse> for InputDialect {
+    fn from(dialect: DialectParse) -> InputDialect {
+        let default = InputDialect::default();
+
+        InputDialect {
+            delimiter: dialect.delimiter.unwrap_or(default.delimiter),
+            quote: dialect.quote.unwrap_or(default.quote),
+            has_header: dialect.header.unwrap_or(default.has_header),
+            columns: dialect.columns,
+            comment: dialect.comment,
+            trim: dialect.trim.unwrap_or(default.trim),
+            nulls: dialect.nulls.unwrap_or(default.nulls)
+        }
+    }
+}
+
+impl From<InputDialect> for DialectParse {
+    fn from(dialect: InputDialect) -> DialectParse {
+        let default = InputDialect::default();
+
+        DialectParse {
+            delimiter: Some(dialect.delimiter).filter(|delimiter| *delimiter != default.delimiter),
+            quote: Some(dialect.quote).filter(|quote| *quote != default.quote),
+            header: Some(dialect.has_header).filter(|has_header| *has_header != default.has_header),
+            columns: dialect.columns,
+            comment: dialect.comment,
+            trim: Some(dialect.trim).filter(|trim| *trim != default.trim),
+            nulls: Some(dialect.nulls).filter(|nulls| *nulls != default.nulls)
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct AggregateParse {
+    function: AggregateFunction,
+    attribute: String, // "*" is allowed for count
+    name: String
+}
+
+impl From<AggregateParse> for (AggregateFunction, String, String) {
+    fn from(aggregate: AggregateParse) -> (AggregateFunction, String, String) {
+        (aggregate.function, aggregate.attribute, aggregate.name)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SortKeyParse {
+    attribute: String,
+    #[serde(default = "default_order")]
+    order: SortOrder
+}
+
+fn default_order() -> SortOrder {
+    SortOrder::Asc
+}
+
+fn default_matching() -> ColumnMatching {
+    ColumnMatching::ByName
+}
+
+impl From<SortKeyParse> for (String, SortOrder) {
+    fn from(key: SortKeyParse) -> (String, SortOrder) {
+        (key.attribute, key.order)
+    }
+}
+
 impl From<ConditionParse> for Condition {
     fn from(condition: ConditionParse) -> Condition {
         match condition {
-            ConditionParse::True => Condition::True,
-            ConditionParse::False => Condition::False,
-            ConditionParse::Log1 {logical: op, condition: c} =>
-                match &op[..] {
-                   "not" => Condition::Not(Box::new(Condition::from(*c))),
-                    _ => panic!(format!("unknown conditional operator of arity 1 {}", op))
-                },
+            ConditionParse::Constant(ConstantParse::True) => Condition::True,
+            ConditionParse::Constant(ConstantParse::False) => Condition::False,
+            ConditionParse::Log1 {logical: Logical1Parse::Not, condition: c} => Condition::Not(Box::new(Condition::from(*c))),
             ConditionParse::Log2 {logical: op, condition1: c1, condition2: c2} => 
-                match &op[..] {
-                    "or" => Condition::Or(Box::new(Condition::from(*c1)), Box::new(Condition::from(*c2))),
-                    "and" => Condition::And(Box::new(Condition::from(*c1)), Box::new(Condition::from(*c2))),
-                    _ => panic!(format!("unknown conditional operator of arity 2 {}", op))
+                match op {
+                    Logical2Parse::Or => Condition::Or(Box::new(Condition::from(*c1)), Box::new(Condition::from(*c2))),
+                    Logical2Parse::And => Condition::And(Box::new(Condition::from(*c1)), Box::new(Condition::from(*c2)))
                 },
             ConditionParse::Comp {comparator: c, attribute1: a1, attribute2: a2} => {
-                let v1 = match a1.parse::<i64>() {
-                    Ok(i) => Value::Int(i),
-                    Err(_) => Value::Column(String::from(a1))
-                };
-                let v2 = match a2.parse::<i64>() {
-                    Ok(i) => Value::Int(i),
-                    Err(_) => Value::Column(String::from(a2))
-                };
-
-                match &c[..] {
-                    "=" => Condition::Equal(v1, v2),
-                    "<" => Condition::Less(v1, v2),
-                    ">" => Condition::More(v1, v2),
-                    _ => panic!(format!("unknown comparator {}", c))
+                let v1 = Value::from(a1);
+                let v2 = Value::from(a2);
+
+                match c {
+                    ComparatorParse::Equal => Condition::Equal(v1, v2),
+                    ComparatorParse::NotEqual => Condition::NotEqual(v1, v2),
+                    ComparatorParse::Less => Condition::Less(v1, v2),
+                    ComparatorParse::LessEq => Condition::LessEq(v1, v2),
+                    ComparatorParse::More => Condition::More(v1, v2),
+                    ComparatorParse::MoreEq => Condition::MoreEq(v1, v2)
                 }
-            }
+            },
+            ConditionParse::NullTest {comparator: NullTestParse::IsNull, attribute: a} => Condition::IsNull(Value::from(a)),
+            ConditionParse::NullTest {comparator: NullTestParse::IsNotNull, attribute: a} => Condition::Not(Box::new(Condition::IsNull(Value::from(a))))
         }
     }
 }
@@ -82,32 +305,158 @@ impl From<ExpressionParse> for Expression {
             ExpressionParse::Project {object: o, attributes: a} => Expression::Project(Box::new(Expression::from(*o)), a),
             ExpressionParse::Rename {object: o, old_attributes: oa, new_attributes: na} => Expression::Rename(Box::new(Expression::from(*o)), oa, na),
             ExpressionParse::Except {object1: o1, object2: o2} => Expression::Except(Box::new(Expression::from(*o1)), Box::new(Expression::from(*o2))),
-            ExpressionParse::Union {object1: o1, object2: o2} => Expression::Union(Box::new(Expression::from(*o1)), Box::new(Expression::from(*o2))),
+            // "union" a une sémantique ensembliste, "union all" garde les doublons
+            ExpressionParse::Union {object1: o1, object2: o2, columns: c} =>
+                Expression::Distinct(Box::new(Expression::Union(Box::new(Expression::from(*o1)), Box::new(Expression::from(*o2)), c))),
+            ExpressionParse::UnionAll {object1: o1, object2: o2, columns: c} => Expression::Union(Box::new(Expression::from(*o1)), Box::new(Expression::from(*o2)), c),
+            ExpressionParse::Distinct {object: o} => Expression::Distinct(Box::new(Expression::from(*o))),
             ExpressionParse::Product {object1: o1, object2: o2} => Expression::Product(Box::new(Expression::from(*o1)), Box::new(Expression::from(*o2))),
-            ExpressionParse::Read {filename: f} => Expression::Load(f, None),
-            ExpressionParse::ReadSelectProjectRename {filename: f, condition: c, old_attributes: oa, new_attributes: na} => Expression::ReadSelectProjectRename(f, Box::new(Condition::from(*c)), oa, na),
+            ExpressionParse::Read {filename: f, dialect: d} => Expression::Load(CsvFile { filename: f, dialect: d.into() }, None),
+            ExpressionParse::Empty {attributes: a} => Expression::Empty(a),
+            ExpressionParse::ReadSelectProjectRename {filename: f, dialect: d, condition: c, old_attributes: oa, new_attributes: na} =>
+                Expression::ReadSelectProjectRename(CsvFile { filename: f, dialect: d.into() }, Box::new(Condition::from(*c)), oa, na),
+            ExpressionParse::Aggregate {object: o, group_by: g, aggregates: a} => Expression::Aggregate(Box::new(Expression::from(*o)), g, a.into_iter().map(|aggregate| aggregate.into()).collect()),
+            ExpressionParse::Sort {object: o, keys: k} => Expression::Sort(Box::new(Expression::from(*o)), k.into_iter().map(|key| key.into()).collect()),
+            ExpressionParse::Limit {object: o, limit: l, offset: off} => Expression::Limit(Box::new(Expression::from(*o)), l, off),
+            ExpressionParse::TopN {object: o, keys: k, limit: l, offset: off} => Expression::TopN(Box::new(Expression::from(*o)), k.into_iter().map(|key| key.into()).collect(), l, off),
             ExpressionParse::JoinProjectRename {object1: o1, object2: o2, condition: c, old_attributes: oa, new_attributes: na} => Expression::JoinProjectRename(Box::new(Expression::from(*o1)), Box::new(Expression::from(*o2)), Box::new(Condition::from(*c)), oa, na)
         }
     }
 }
 
+/**
+ * Parse a JSON query, locating errors with the path of the faulty field, e.g. "args.object.condition".
+ */
+pub fn get_expression_from_json(json: &str) -> Result<Expression> {
+    let deserializer = &mut serde_json::Deserializer::from_str(json);
+
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let location = e.path().to_string();
+        EngineError::parse(&location, e.into_inner())
+    })
+}
+
+impl From<Value> for AttributeParse {
+    fn from(value: Value) -> AttributeParse {
+        match value {
+            Value::Str(s) => AttributeParse::Literal {literal: LiteralParse::Str(s)},
+            Value::Int(i) => AttributeParse::Name(i.to_string()),
+            Value::Float(x) => AttributeParse::Literal {literal: LiteralParse::Float(x)},
+            Value::Bool(b) => AttributeParse::Literal {literal: LiteralParse::Bool(b)},
+            Value::Date(date) => AttributeParse::Date {date},
+            Value::Null => AttributeParse::Literal {literal: LiteralParse::Null},
+            Value::Column(name) => AttributeParse::Name(name)
+        }
+    }
+}
+
+impl From<Condition> for ConditionParse {
+    fn from(condition: Condition) -> ConditionParse {
+        let comparison = |comparator: ComparatorParse, v1: Value, v2: Value|
+            ConditionParse::Comp {comparator, attribute1: AttributeParse::from(v1), attribute2: AttributeParse::from(v2)};
+
+        match condition {
+            Condition::True => ConditionParse::Constant(ConstantParse::True),
+            Condition::False => ConditionParse::Constant(ConstantParse::False),
+            Condition::Not(c) => match *c {
+                Condition::IsNull(v) => ConditionParse::NullTest {comparator: NullTestParse::IsNotNull, attribute: AttributeParse::from(v)},
+                c => ConditionParse::Log1 {logical: Logical1Parse::Not, condition: Box::new(ConditionParse::from(c))}
+            },
+            Condition::Or(c1, c2) => ConditionParse::Log2 {logical: Logical2Parse::Or, condition1: Box::new(ConditionParse::from(*c1)), condition2: Box::new(ConditionParse::from(*c2))},
+            Condition::And(c1, c2) => ConditionParse::Log2 {logical: Logical2Parse::And, condition1: Box::new(ConditionParse::from(*c1)), condition2: Box::new(ConditionParse::from(*c2))},
+            Condition::Equal(v1, v2) => comparison(ComparatorParse::Equal, v1, v2),
+            Condition::NotEqual(v1, v2) => comparison(ComparatorParse::NotEqual, v1, v2),
+            Condition::Less(v1, v2) => comparison(ComparatorParse::Less, v1, v2),
+            Condition::LessEq(v1, v2) => comparison(ComparatorParse::LessEq, v1, v2),
+            Condition::More(v1, v2) => comparison(ComparatorParse::More, v1, v2),
+            Condition::MoreEq(v1, v2) => comparison(ComparatorParse::MoreEq, v1, v2),
+            Condition::IsNull(v) => ConditionParse::NullTest {comparator: NullTestParse::IsNull, attribute: AttributeParse::from(v)}
+        }
+    }
+}
+
+impl From<Expression> for ExpressionParse {
+    fn from(expression: Expression) -> ExpressionParse {
+        let object = |e: Box<Expression>| Box::new(ExpressionParse::from(*e));
+        let condition = |c: Box<Condition>| Box::new(ConditionParse::from(*c));
+        let keys = |k: Vec<(String, SortOrder)>| k.into_iter().map(|(attribute, order)| SortKeyParse {attribute, order}).collect();
+
+        match expression {
+            Expression::Select(o, c) => ExpressionParse::Select {object: object(o), condition: condition(c)},
+            Expression::Project(o, a) => ExpressionParse::Project {object: object(o), attributes: a},
+            Expression::Rename(o, oa, na) => ExpressionParse::Rename {object: object(o), old_attributes: oa, new_attributes: na},
+            Expression::Except(o1, o2) => ExpressionParse::Except {object1: object(o1), object2: object(o2)},
+            // On retrouve l'union ensembliste sous la forme d'un distinct sur une union
+            Expression::Distinct(o) => match *o {
+                Expression::Union(o1, o2, c) => ExpressionParse::Union {object1: object(o1), object2: object(o2), columns: c},
+                o => ExpressionParse::Distinct {object: Box::new(ExpressionParse::from(o))}
+            },
+            Expression::Union(o1, o2, c) => ExpressionParse::UnionAll {object1: object(o1), object2: object(o2), columns: c},
+            Expression::Product(o1, o2) => ExpressionParse::Product {object1: object(o1), object2: object(o2)},
+            Expression::Load(f, _) => ExpressionParse::Read {filename: f.filename, dialect: f.dialect.into()},
+            Expression::Empty(a) => ExpressionParse::Empty {attributes: a},
+            Expression::ReadSelectProjectRename(f, c, oa, na) =>
+                ExpressionParse::ReadSelectProjectRename {filename: f.filename, dialect: f.dialect.into(), condition: condition(c), old_attributes: oa, new_attributes: na},
+            Expression::Aggregate(o, g, a) => ExpressionParse::Aggregate {
+                object: object(o),
+                group_by: g,
+                aggregates: a.into_iter().map(|(function, attribute, name)| AggregateParse {function, attribute, name}).collect()
+            },
+            Expression::Sort(o, k) => ExpressionParse::Sort {object: object(o), keys: keys(k)},
+            Expression::Limit(o, l, off) => ExpressionParse::Limit {object: object(o), limit: l, offset: off},
+            Expression::TopN(o, k, l, off) => ExpressionParse::TopN {object: object(o), keys: keys(k), limit: l, offset: off},
+            Expression::JoinProjectRename(o1, o2, c, oa, na) => ExpressionParse::JoinProjectRename {object1: object(o1), object2: object(o2), condition: condition(c), old_attributes: oa, new_attributes: na}
+        }
+    }
+}
+
+/**
+ * Write an expression in the JSON grammar, so that it can be saved and parsed again later.
+ */
+pub fn expression_to_json(expression: &Expression) -> Result<String> {
+    serde_json::to_string_pretty(expression).map_err(|e| EngineError::Unsupported(e.to_string()))
+}
+
 #[allow(dead_code)]
-pub fn get_expression_from_str(json: &'static str) -> Expression {
-    serde_json::from_str(json).unwrap()
+pub fn get_expression_from_str(json: &str) -> Expression {
+    get_expression_from_json(json).unwrap()
+}
+
+/**
+ * The languages queries can be written in.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryLanguage {
+    Json,
+    Sql
 }
 
-pub fn get_expression_from(path: Option<String>) -> Expression {
+impl QueryLanguage {
+    /**
+     * Guess the language of a query file from its extension, defaulting to JSON.
+     */
+    pub fn from_path(path: &Option<String>) -> QueryLanguage {
+        match path {
+            Some(filename) if filename.to_lowercase().ends_with(".sql") => QueryLanguage::Sql,
+            _ => QueryLanguage::Json
+        }
+    }
+}
+
+pub fn get_expression_from(path: Option<String>, language: QueryLanguage) -> Result<Expression> {
+    let mut buffer = String::new();
     match path {
         Some(filename) => {
-            let file = File::open(filename).unwrap();
-            
-            serde_json::from_reader(BufReader::new(file)).unwrap()
+            let file = File::open(&filename).map_err(|e| EngineError::io(&filename, e))?;
+            BufReader::new(file).read_to_string(&mut buffer).map_err(|e| EngineError::io(&filename, e))?;
         },
         None => {
-            let mut buffer = String::new();
-            io::stdin().read_to_string(&mut buffer).unwrap();
-
-            serde_json::from_str(&buffer).unwrap()
+            io::stdin().read_to_string(&mut buffer).map_err(|e| EngineError::io("<stdin>", e))?;
         }
     }
+
+    match language {
+        QueryLanguage::Json => get_expression_from_json(&buffer),
+        QueryLanguage::Sql => get_expression_from_sql(&buffer)
+    }
 }
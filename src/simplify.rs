@@ -0,0 +1,266 @@
+use crate::types::*;
+use crate::eval::compare_values;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/**
+ * Normalize a condition, giving one that holds for exactly the same entries and is cheaper to evaluate.
+ *
+ * Comparisons between constants are folded, negations are pushed down to the comparisons (De Morgan),
+ * and conjunctions that can never hold are replaced by False. A negated ordering comparison stays negated:
+ * values of different types are not comparable, so not (a < b) does not mean a >= b.
+ *
+ * Comparisons with NULL are unknown, and so is their negation: a condition that is never true may become False,
+ * as negations only remain on comparisons, but a disjunction such as x = 1 or x != 1 is not always true.
+ */
+pub fn simplify(condition: Condition) -> Box<Condition> {
+    Box::new(normalize(condition, false))
+}
+
+fn normalize(condition: Condition, negated: bool) -> Condition {
+    match condition {
+        Condition::True => if negated { Condition::False } else { Condition::True },
+        Condition::False => if negated { Condition::True } else { Condition::False },
+        Condition::Not(c) => normalize(*c, !negated),
+        // not (c1 and c2) = (not c1) or (not c2), et inversement
+        Condition::And(c1, c2) if negated => disjunction(normalize(*c1, true), normalize(*c2, true)),
+        Condition::Or(c1, c2) if negated => conjunction(normalize(*c1, true), normalize(*c2, true)),
+        Condition::And(c1, c2) => conjunction(normalize(*c1, false), normalize(*c2, false)),
+        Condition::Or(c1, c2) => disjunction(normalize(*c1, false), normalize(*c2, false)),
+        test @ Condition::IsNull(Value::Column(_)) => if negated { Condition::Not(Box::new(test)) } else { test },
+        Condition::IsNull(constant) => if matches!(constant, Value::Null) != negated { Condition::True } else { Condition::False },
+        comparison if compares_null(&comparison) => Condition::False,
+        comparison => {
+            // x op x vaut, pour x non NULL, la même chose quelle que soit sa valeur
+            if let Some((column, holds)) = self_comparison(&comparison) {
+                return if holds != negated { Condition::Not(Box::new(Condition::IsNull(Value::Column(column)))) } else { Condition::False };
+            }
+
+            let comparison = match comparison {
+                // != est exactement la négation de =
+                Condition::Equal(v1, v2) if negated => Condition::NotEqual(v1, v2),
+                Condition::NotEqual(v1, v2) if negated => Condition::Equal(v1, v2),
+                comparison if negated => Condition::Not(Box::new(fold(comparison))),
+                comparison => comparison
+            };
+
+            match comparison {
+                Condition::Not(c) => match *c {
+                    Condition::True => Condition::False,
+                    Condition::False => Condition::True,
+                    c => Condition::Not(Box::new(c))
+                },
+                comparison => fold(comparison)
+            }
+        }
+    }
+}
+
+fn operands(comparison: &Condition) -> Option<(&Value, &Value)> {
+    match comparison {
+        Condition::Equal(v1, v2) | Condition::NotEqual(v1, v2) | Condition::Less(v1, v2) |
+            Condition::LessEq(v1, v2) | Condition::More(v1, v2) | Condition::MoreEq(v1, v2) => Some((v1, v2)),
+        _ => None
+    }
+}
+
+/**
+ * Whether a comparison holds for values in the given order.
+ */
+fn holds_for(comparison: &Condition, ordering: Option<Ordering>) -> bool {
+    match comparison {
+        Condition::Equal(_, _) => ordering == Some(Ordering::Equal),
+        Condition::NotEqual(_, _) => ordering != Some(Ordering::Equal),
+        Condition::Less(_, _) => ordering == Some(Ordering::Less),
+        Condition::LessEq(_, _) => matches!(ordering, Some(Ordering::Less) | Some(Ordering::Equal)),
+        Condition::More(_, _) => ordering == Some(Ordering::Greater),
+        _ => matches!(ordering, Some(Ordering::Greater) | Some(Ordering::Equal))
+    }
+}
+
+fn compares_null(comparison: &Condition) -> bool {
+    operands(comparison).is_some_and(|(v1, v2)| matches!(v1, Value::Null) || matches!(v2, Value::Null))
+}
+
+/**
+ * The column a comparison compares with itself, and whether the comparison then holds when the column is not NULL.
+ */
+fn self_comparison(comparison: &Condition) -> Option<(String, bool)> {
+    match operands(comparison)? {
+        (Value::Column(c1), Value::Column(c2)) if c1 == c2 => Some((c1.clone(), holds_for(comparison, Some(Ordering::Equal)))),
+        _ => None
+    }
+}
+
+/**
+ * Evaluate a comparison between constants, which does not depend on the entry.
+ */
+fn fold(comparison: Condition) -> Condition {
+    let holds = match operands(&comparison) {
+        Some((Value::Column(_), _)) | Some((_, Value::Column(_))) | None => return comparison,
+        Some((v1, v2)) => holds_for(&comparison, compare_values(v1, v2))
+    };
+
+    if holds { Condition::True } else { Condition::False }
+}
+
+/**
+ * The condition holding exactly when the given one does not, when it is known without evaluating it.
+ */
+fn complement(condition: &Condition) -> Option<Condition> {
+    match condition {
+        Condition::Equal(v1, v2) => Some(Condition::NotEqual(v1.clone(), v2.clone())),
+        Condition::NotEqual(v1, v2) => Some(Condition::Equal(v1.clone(), v2.clone())),
+        Condition::IsNull(v) => Some(Condition::Not(Box::new(Condition::IsNull(v.clone())))),
+        Condition::Not(c) => Some((**c).clone()),
+        _ => None
+    }
+}
+
+fn conjunction(c1: Condition, c2: Condition) -> Condition {
+    let mut conjuncts = Vec::new();
+    for condition in [c1, c2] {
+        match condition {
+            Condition::False => return Condition::False,
+            Condition::True => (),
+            condition => split(condition, true, &mut conjuncts)
+        }
+    }
+
+    if contradictory(&conjuncts) {
+        Condition::False
+    } else {
+        rebuild(conjuncts, true)
+    }
+}
+
+fn disjunction(c1: Condition, c2: Condition) -> Condition {
+    let mut disjuncts = Vec::new();
+    for condition in [c1, c2] {
+        match condition {
+            Condition::True => return Condition::True,
+            Condition::False => (),
+            condition => split(condition, false, &mut disjuncts)
+        }
+    }
+
+    // x IS NULL or x IS NOT NULL est toujours vrai ; c or not c est inconnu quand c compare NULL
+    let null_test = |condition: &Condition| matches!(condition, Condition::IsNull(_));
+    if disjuncts.iter().any(|condition| null_test(condition) && complement(condition).is_some_and(|opposite| disjuncts.contains(&opposite))) {
+        Condition::True
+    } else {
+        rebuild(disjuncts, false)
+    }
+}
+
+/**
+ * Add the operands of a chain of conjunctions (or disjunctions) to terms, dropping duplicates.
+ */
+fn split(condition: Condition, and: bool, terms: &mut Vec<Condition>) {
+    match condition {
+        Condition::And(c1, c2) if and => {
+            split(*c1, and, terms);
+            split(*c2, and, terms);
+        },
+        Condition::Or(c1, c2) if !and => {
+            split(*c1, and, terms);
+            split(*c2, and, terms);
+        },
+        condition => if !terms.contains(&condition) {
+            terms.push(condition);
+        }
+    }
+}
+
+fn rebuild(terms: Vec<Condition>, and: bool) -> Condition {
+    terms.into_iter()
+        .reduce(|c1, c2| if and { Condition::And(Box::new(c1), Box::new(c2)) } else { Condition::Or(Box::new(c1), Box::new(c2)) })
+        .unwrap_or(if and { Condition::True } else { Condition::False })
+}
+
+/**
+ * Bounds on the values of a column, from the comparisons of a conjunction with constants.
+ */
+#[derive(Default)]
+struct Range {
+    lower: Option<(Value, bool)>, // bound, whether it is included
+    upper: Option<(Value, bool)>,
+    excluded: Vec<Value>
+}
+
+/**
+ * Whether some conjuncts can never hold together: a condition and its negation, comparisons
+ * of a column with constants that no value satisfies, such as x = 1 and x = 2, or x < 3 and x > 5,
+ * or such a comparison on a column that is NULL.
+ */
+fn contradictory(conjuncts: &[Condition]) -> bool {
+    if conjuncts.iter().any(|condition| complement(condition).is_some_and(|opposite| conjuncts.contains(&opposite))) {
+        return true;
+    }
+
+    let mut ranges: HashMap<&String, Range> = HashMap::new();
+    let mut nulls = Vec::new();
+    for condition in conjuncts {
+        let (column, comparison, constant) = match condition {
+            Condition::IsNull(Value::Column(c)) => {
+                nulls.push(c);
+                continue;
+            },
+            Condition::Equal(Value::Column(c), v) | Condition::Equal(v, Value::Column(c)) => (c, Ordering::Equal, v),
+            Condition::Less(Value::Column(c), v) | Condition::More(v, Value::Column(c)) => (c, Ordering::Less, v),
+            Condition::More(Value::Column(c), v) | Condition::Less(v, Value::Column(c)) => (c, Ordering::Greater, v),
+            Condition::LessEq(Value::Column(c), v) | Condition::MoreEq(v, Value::Column(c)) => (c, Ordering::Less, v),
+            Condition::MoreEq(Value::Column(c), v) | Condition::LessEq(v, Value::Column(c)) => (c, Ordering::Greater, v),
+            Condition::NotEqual(Value::Column(c), v) | Condition::NotEqual(v, Value::Column(c)) if !matches!(v, Value::Column(_)) => {
+                ranges.entry(c).or_default().excluded.push(v.clone());
+                continue;
+            },
+            _ => continue
+        };
+        if matches!(constant, Value::Column(_)) {
+            continue;
+        }
+
+        let included = matches!(condition, Condition::Equal(_, _) | Condition::LessEq(_, _) | Condition::MoreEq(_, _));
+        let range = ranges.entry(column).or_default();
+        let tightened = match comparison {
+            Ordering::Equal => tighten(&mut range.lower, constant, true, Ordering::Greater) && tighten(&mut range.upper, constant, true, Ordering::Less),
+            Ordering::Greater => tighten(&mut range.lower, constant, included, Ordering::Greater),
+            Ordering::Less => tighten(&mut range.upper, constant, included, Ordering::Less)
+        };
+        if !tightened {
+            return true;
+        }
+    }
+
+    nulls.iter().any(|column| ranges.contains_key(column)) || ranges.values().any(|range| !satisfiable(range))
+}
+
+/**
+ * Keep the tightest of two bounds, wanted being the ordering of a tighter bound to the current one.
+ * Returns false when they cannot be compared: a value cannot have two types.
+ */
+fn tighten(bound: &mut Option<(Value, bool)>, constant: &Value, included: bool, wanted: Ordering) -> bool {
+    match bound {
+        None => *bound = Some((constant.clone(), included)),
+        Some((current, current_included)) => match compare_values(constant, current) {
+            None => return false,
+            Some(Ordering::Equal) => *current_included = *current_included && included,
+            Some(ordering) if ordering == wanted => *bound = Some((constant.clone(), included)),
+            Some(_) => ()
+        }
+    }
+
+    true
+}
+
+fn satisfiable(range: &Range) -> bool {
+    match (&range.lower, &range.upper) {
+        (Some((lower, lower_included)), Some((upper, upper_included))) => match compare_values(lower, upper) {
+            Some(Ordering::Less) => true,
+            Some(Ordering::Equal) => *lower_included && *upper_included && !range.excluded.contains(lower),
+            _ => false
+        },
+        _ => true
+    }
+}
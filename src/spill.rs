@@ -0,0 +1,185 @@
+use crate::types::*;
+use crate::error::*;
+use crate::eval::Rows;
+use std::cell::Cell;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+thread_local! {
+    // Au-delà de cette taille, les opérateurs bloquants écrivent leurs entrées dans des fichiers temporaires
+    static MEMORY_LIMIT: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/**
+ * Set the memory each operator may use for the entries it keeps, None meaning no limit.
+ */
+pub fn set_memory_limit(limit: Option<usize>) {
+    MEMORY_LIMIT.with(|memory_limit| memory_limit.set(limit));
+}
+
+pub fn memory_limit() -> Option<usize> {
+    MEMORY_LIMIT.with(|memory_limit| memory_limit.get())
+}
+
+/**
+ * Parse a size in bytes, with an optional K, M or G suffix, e.g. "512K" or "64M".
+ */
+pub fn parse_memory_limit(size: &str) -> Result<usize> {
+    let size = size.trim();
+    let (number, unit) = match size.char_indices().last() {
+        Some((i, 'k')) | Some((i, 'K')) => (&size[..i], 1 << 10),
+        Some((i, 'm')) | Some((i, 'M')) => (&size[..i], 1 << 20),
+        Some((i, 'g')) | Some((i, 'G')) => (&size[..i], 1 << 30),
+        _ => (size, 1)
+    };
+
+    number.trim().parse::<usize>().ok()
+        .and_then(|number| number.checked_mul(unit))
+        .filter(|limit| *limit > 0)
+        .ok_or_else(|| EngineError::parse("--memory-limit", format!("\"{}\" is not a size, e.g. 512K, 64M or 2G", size)))
+}
+
+/**
+ * An estimation of the memory taken by an entry.
+ */
+pub fn entry_size(entry: &Entry) -> usize {
+    std::mem::size_of::<Entry>() + entry.iter().map(|value| std::mem::size_of::<Value>() + match value {
+        Value::Str(s) | Value::Column(s) => s.capacity(),
+        Value::Int(_) | Value::Float(_) | Value::Bool(_) | Value::Date(_) | Value::Null => 0
+    }).sum::<usize>()
+}
+
+/**
+ * Pull entries until the memory limit is reached.
+ * Returns them, and whether the rows were exhausted; without any limit, all the entries are pulled.
+ */
+pub fn fill(rows: &mut Rows) -> Result<(Vec<Entry>, bool)> {
+    let limit = memory_limit().unwrap_or(usize::MAX);
+    let mut entries = Vec::new();
+    let mut size = 0;
+
+    for row in rows {
+        let entry = row?;
+        size += entry_size(&entry);
+        entries.push(entry);
+
+        if size > limit {
+            return Ok((entries, false));
+        }
+    }
+
+    Ok((entries, true))
+}
+
+/**
+ * A temporary file of entries, one JSON array per line. It is removed once dropped.
+ */
+pub struct SpillFile {
+    path: PathBuf,
+    writer: BufWriter<File>
+}
+
+impl SpillFile {
+    pub fn create() -> Result<SpillFile> {
+        let path = std::env::temp_dir().join(format!("dbdm-{}-{}.spill", std::process::id(), SPILL_FILES.fetch_add(1, Ordering::Relaxed)));
+        let file = File::create(&path).map_err(|e| EngineError::io(&path.to_string_lossy(), e))?;
+
+        Ok(SpillFile { path, writer: BufWriter::new(file) })
+    }
+
+    /**
+     * Write all the given entries in a new temporary file.
+     */
+    pub fn from_rows(rows: impl Iterator<Item = Result<Entry>>) -> Result<SpillFile> {
+        let mut file = SpillFile::create()?;
+        for row in rows {
+            file.push(&row?)?;
+        }
+
+        Ok(file)
+    }
+
+    pub fn push(&mut self, entry: &Entry) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, entry).map_err(|e| self.error(e))?;
+        self.writer.write_all(b"\n").map_err(|e| self.error(e))
+    }
+
+    /**
+     * Read the entries written so far, from the start. The file can be read several times.
+     */
+    pub fn rows(&mut self) -> Result<Rows> {
+        Ok(Box::new(self.reader()?))
+    }
+
+    /**
+     * Read the entries written so far, the file being removed once they are dropped.
+     */
+    pub fn into_rows(mut self) -> Result<Rows> {
+        let mut reader = self.reader()?;
+        reader.file = Some(self);
+
+        Ok(Box::new(reader))
+    }
+
+    fn reader(&mut self) -> Result<SpillReader> {
+        self.writer.flush().map_err(|e| self.error(e))?;
+        let file = File::open(&self.path).map_err(|e| self.error(e))?;
+
+        Ok(SpillReader { lines: BufReader::new(file).lines(), path: self.path.to_string_lossy().into_owned(), file: None })
+    }
+
+    fn error(&self, error: impl std::fmt::Display) -> EngineError {
+        EngineError::io(&self.path.to_string_lossy(), error)
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        // Le fichier peut encore être lu par un itérateur : on ignore l'échec de la suppression
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+struct SpillReader {
+    lines: Lines<BufReader<File>>,
+    path: String,
+    file: Option<SpillFile> // keeps the file until it has been read
+}
+
+impl Iterator for SpillReader {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Result<Entry>> {
+        let line = self.lines.next()?;
+
+        Some(line.map_err(|e| EngineError::io(&self.path, e))
+            .and_then(|line| serde_json::from_str(&line).map_err(|e| EngineError::io(&self.path, e))))
+    }
+}
+
+/**
+ * Split entries between count temporary files, according to the hash of their key.
+ * Entries with the same key end up in the same file; the seed changes the split from one level to the next.
+ */
+pub fn partition(rows: impl Iterator<Item = Result<Entry>>, key: &[usize], seed: usize, count: usize) -> Result<Vec<SpillFile>> {
+    let mut files = (0..count).map(|_| SpillFile::create()).collect::<Result<Vec<_>>>()?;
+
+    for row in rows {
+        let entry = row?;
+        let mut hasher = DefaultHasher::new();
+        seed.hash(&mut hasher);
+        for index in key {
+            entry[*index].hash(&mut hasher);
+        }
+
+        files[(hasher.finish() % count as u64) as usize].push(&entry)?;
+    }
+
+    Ok(files)
+}
@@ -0,0 +1,650 @@
+/*!
+ * A small SQL front-end, lowering a subset of SQL directly into `Expression`s.
+ *
+ * Supported grammar (keywords are case-insensitive):
+ *
+ * QUERY ::= SELECT_STMT ((UNION [ALL] | EXCEPT) SELECT_STMT)* [ORDER BY KEY (, KEY)*] [LIMIT INTEGER [OFFSET INTEGER]] [;]
+ * KEY ::= COLUMN [ASC | DESC]
+ * SELECT_STMT ::= SELECT [DISTINCT] ITEMS FROM TABLE (JOIN TABLE ON COND)* [WHERE COND] [GROUP BY COLUMNS] | ( QUERY )
+ * ITEMS ::= * | ITEM [AS NAME] (, ITEM [AS NAME])*
+ * ITEM ::= COLUMN | FUNCTION ( COLUMN ) | COUNT ( * )
+ * FUNCTION ::= COUNT | SUM | MIN | MAX | AVG
+ * TABLE ::= (FILENAME | ( QUERY )) [[AS] ALIAS]
+ * COND ::= COND OR COND | COND AND COND | NOT COND | ( COND ) | OPERAND COMP OPERAND | OPERAND IS [NOT] NULL
+ * OPERAND ::= COLUMN | INTEGER | FLOAT | 'STRING' | TRUE | FALSE | DATE 'YYYY-MM-DD' | NULL
+ * COMP ::= = | != | <> | < | <= | > | >=
+ *
+ * The columns of an aliased table are renamed into `alias.column`. A column may then be written `alias.column`,
+ * or without its alias when a single table of the FROM clause has it; the select list outputs it without its alias.
+ * UNION matches the columns of its sides by position. EXCEPT matches them by name, and removes duplicates like UNION.
+ * ORDER BY may use the columns of the FROM clause that are not selected, except after UNION or EXCEPT where it only knows the output columns.
+ */
+
+use crate::types::*;
+use crate::error::*;
+use crate::validate::columns_of;
+use chrono::NaiveDate;
+
+const COMPARATORS: [&str; 7] = ["=", "!=", "<>", "<", "<=", ">", ">="];
+// Les mots qui peuvent suivre une table, et ne sont donc pas pris pour son alias
+const CLAUSE_KEYWORDS: [&str; 9] = ["join", "on", "where", "group", "order", "limit", "offset", "union", "except"];
+
+/**
+ * An element of the select list: a column or an aggregate, with an optional alias.
+ */
+enum SelectItem {
+    Column(String, Option<String>),
+    Aggregate(AggregateFunction, String, Option<String>)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String), // bare words, keywords and "quoted identifiers"
+    Integer(i64),
+    Float(f64),
+    Str(String), // 'quoted strings'
+    Symbol(String)
+}
+
+/**
+ * Human readable position of the i-th character of the query, e.g. "line 2, column 7".
+ */
+fn location(chars: &[char], i: usize) -> String {
+    let line = chars[..i].iter().filter(|c| **c == '\n').count() + 1;
+    let column = i - chars[..i].iter().rposition(|c| *c == '\n').map(|p| p + 1).unwrap_or(0) + 1;
+
+    format!("line {}, column {}", line, column)
+}
+
+/**
+ * Split the query into tokens, each with its position for error messages.
+ */
+fn tokenize(sql: &str) -> Result<Vec<(Token, String)>> {
+    let chars = sql.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_' || c == '.' || c == '/' || c == '-';
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '\'' || c == '"' {
+            // On lit jusqu'au guillemet fermant, un guillemet doublé étant échappé
+            let mut content = String::new();
+            i += 1;
+            loop {
+                if i >= chars.len() {
+                    return Err(EngineError::parse(&location(&chars, start), "unterminated quoted string"));
+                }
+                if chars[i] == c {
+                    if i + 1 < chars.len() && chars[i+1] == c {
+                        content.push(c);
+                        i += 2;
+                        continue;
+                    }
+                    i += 1;
+                    break;
+                }
+                content.push(chars[i]);
+                i += 1;
+            }
+
+            tokens.push((if c == '\'' { Token::Str(content) } else { Token::Identifier(content) }, location(&chars, start)));
+        } else if i + 1 < chars.len() && ["<=", ">=", "<>", "!="].contains(&&chars[i..i+2].iter().collect::<String>()[..]) {
+            tokens.push((Token::Symbol(chars[i..i+2].iter().collect()), location(&chars, start)));
+            i += 2;
+        } else if "(),*=<>;".contains(c) {
+            tokens.push((Token::Symbol(c.to_string()), location(&chars, start)));
+            i += 1;
+        } else if is_word_char(c) {
+            while i < chars.len() && is_word_char(chars[i]) {
+                i += 1;
+            }
+            let word = chars[start..i].iter().collect::<String>();
+
+            tokens.push((match (word.parse::<i64>(), word.parse::<f64>()) {
+                (Ok(n), _) => Token::Integer(n),
+                (_, Ok(x)) if x.is_finite() => Token::Float(x),
+                _ => Token::Identifier(word)
+            }, location(&chars, start)));
+        } else {
+            return Err(EngineError::parse(&location(&chars, start), format!("unexpected character '{}'", c)));
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct SqlParser {
+    tokens: Vec<(Token, String)>,
+    position: usize,
+    scope: Vec<(Option<String>, Expression)>, // the tables of the current FROM clause, with their alias, before renaming
+    finished_scope: Vec<(Option<String>, Expression)> // the tables of the last parsed FROM clause, for its ORDER BY
+}
+
+impl SqlParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    /**
+     * The location of the current token, for error messages.
+     */
+    fn here(&self) -> String {
+        self.tokens.get(self.position).map(|(_, location)| location.clone()).unwrap_or_else(|| String::from("end of query"))
+    }
+
+    /**
+     * Build a parse error located at the current token.
+     */
+    fn error(&self, message: impl std::fmt::Display) -> EngineError {
+        EngineError::parse(&self.here(), message)
+    }
+
+    /**
+     * The name of a column in the current FROM clause, qualified by the alias of its table if it has one,
+     * and its name without alias. Unknown columns are left for the validation to report.
+     */
+    fn resolve(&self, name: &str, location: &str) -> Result<(String, String)> {
+        // Sans alias, les colonnes gardent leur nom : inutile de lire les fichiers
+        if self.scope.iter().all(|(alias, _)| alias.is_none()) {
+            return Ok((String::from(name), String::from(name)));
+        }
+
+        let mut candidates = Vec::new();
+        for (alias, table) in &self.scope {
+            for column in columns_of(table)? {
+                let qualified = match alias {
+                    Some(alias) => format!("{}.{}", alias, column),
+                    None => column.clone()
+                };
+                if qualified == name || column == name {
+                    candidates.push((qualified, column));
+                }
+            }
+        }
+
+        match candidates.len() {
+            0 => Ok((String::from(name), String::from(name))),
+            1 => Ok(candidates.remove(0)),
+            _ => Err(EngineError::parse(location, format!("ambiguous column {}, write it alias.{} with the alias of its table", name, name)))
+        }
+    }
+
+    /**
+     * A column of the current FROM clause, qualified by the alias of its table if it has one.
+     */
+    fn column(&mut self) -> Result<String> {
+        let location = self.here();
+        let name = self.identifier()?;
+
+        Ok(self.resolve(&name, &location)?.0)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self.peek().cloned().ok_or_else(|| self.error("unexpected end of query"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /**
+     * Consume the next token, reporting an error located at it if `f` rejects it.
+     */
+    fn next_as<T>(&mut self, expected: &str, f: impl FnOnce(Token) -> Option<T>) -> Result<T> {
+        let error = self.error(format!("expected {}, found {:?}", expected, self.peek()));
+        f(self.next()?).ok_or(error)
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Identifier(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn peek_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(Token::Symbol(s)) if s == symbol)
+    }
+
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek_keyword(keyword);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn accept_symbol(&mut self, symbol: &str) -> bool {
+        let found = self.peek_symbol(symbol);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if !self.accept_keyword(keyword) {
+            return Err(self.error(format!("expected {}, found {:?}", keyword, self.peek())));
+        }
+        Ok(())
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<()> {
+        if !self.accept_symbol(symbol) {
+            return Err(self.error(format!("expected '{}', found {:?}", symbol, self.peek())));
+        }
+        Ok(())
+    }
+
+    fn identifier(&mut self) -> Result<String> {
+        self.next_as("an identifier", |token| match token {
+            Token::Identifier(name) => Some(name),
+            _ => None
+        })
+    }
+
+    fn query(&mut self) -> Result<Expression> {
+        let mut expression = self.select_statement()?;
+        let scope = std::mem::take(&mut self.finished_scope);
+        let mut set_operation = false;
+
+        loop {
+            if self.accept_keyword("union") {
+                let all = self.accept_keyword("all");
+                expression = Expression::Union(Box::new(expression), Box::new(self.select_statement()?), ColumnMatching::ByPosition);
+                if !all {
+                    expression = Expression::Distinct(Box::new(expression));
+                }
+            } else if self.accept_keyword("except") {
+                // Comme en SQL, EXCEPT retire aussi les doublons
+                expression = Expression::Distinct(Box::new(Expression::Except(Box::new(expression), Box::new(self.select_statement()?))));
+            } else {
+                break;
+            }
+            set_operation = true;
+        }
+
+        let mut keys = None;
+        if self.accept_keyword("order") {
+            self.expect_keyword("by")?;
+
+            let mut columns = Vec::new();
+            loop {
+                let location = self.here();
+                let column = self.identifier()?;
+                let order = if self.accept_keyword("desc") {
+                    SortOrder::Desc
+                } else {
+                    self.accept_keyword("asc");
+                    SortOrder::Asc
+                };
+                columns.push((location, column, order));
+
+                if !self.accept_symbol(",") {
+                    break;
+                }
+            }
+            keys = Some(columns);
+        }
+
+        let mut limit = None;
+        if self.accept_keyword("limit") {
+            let count = self.integer()?;
+            let offset = if self.accept_keyword("offset") { self.integer()? } else { 0 };
+            limit = Some((count, offset));
+        }
+
+        match keys {
+            // Le tri d'un seul SELECT se fait sous sa projection, et peut donc utiliser des colonnes qui ne sont pas sélectionnées
+            Some(keys) if !set_operation => {
+                let outer_scope = std::mem::replace(&mut self.scope, scope);
+                let keys = self.order_keys(&expression, keys);
+                self.scope = outer_scope;
+
+                Ok(order_below_projection(expression, keys?, limit))
+            },
+            keys => {
+                if let Some(keys) = keys {
+                    let keys = keys.into_iter().map(|(_, column, order)| (column, order)).collect();
+                    expression = Expression::Sort(Box::new(expression), keys);
+                }
+
+                Ok(limited(expression, limit))
+            }
+        }
+    }
+
+    /**
+     * The columns of the ORDER BY keys of a single SELECT, below its projection:
+     * an output alias stands for the column it renames, other columns are resolved in its FROM clause.
+     */
+    fn order_keys(&self, expression: &Expression, keys: Vec<(String, String, SortOrder)>) -> Result<Vec<(String, SortOrder)>> {
+        let expression = match expression {
+            Expression::Distinct(expression_from) => expression_from,
+            expression => expression
+        };
+        let (expression, old_columns, new_columns) = match expression {
+            Expression::Rename(expression_from, old_columns, new_columns) => (&**expression_from, &old_columns[..], &new_columns[..]),
+            expression => (expression, &[][..], &[][..])
+        };
+        let outputs = match expression {
+            Expression::Project(_, columns) => &columns[..],
+            _ => &[][..]
+        };
+
+        keys.into_iter().map(|(location, column, order)| {
+            let column = match new_columns.iter().position(|new| *new == column) {
+                Some(i) => old_columns[i].clone(),
+                None if outputs.contains(&column) => column,
+                None => self.resolve(&column, &location)?.0
+            };
+            Ok((column, order))
+        }).collect()
+    }
+
+    fn integer(&mut self) -> Result<usize> {
+        self.next_as("a positive integer", |token| match token {
+            Token::Integer(i) if i >= 0 => Some(i as usize),
+            _ => None
+        })
+    }
+
+    fn select_statement(&mut self) -> Result<Expression> {
+        if self.accept_symbol("(") {
+            let expression = self.query()?;
+            self.expect_symbol(")")?;
+            return Ok(expression);
+        }
+
+        // Les sous requêtes ont leur propre clause FROM
+        let outer_scope = std::mem::take(&mut self.scope);
+        let expression = self.select_from();
+        self.finished_scope = std::mem::replace(&mut self.scope, outer_scope);
+
+        expression
+    }
+
+    fn select_from(&mut self) -> Result<Expression> {
+        self.expect_keyword("select")?;
+        let distinct = self.accept_keyword("distinct");
+
+        // None means "*"
+        let items = if self.accept_symbol("*") {
+            None
+        } else {
+            // Les colonnes sont résolues une fois la clause FROM lue
+            let mut items = vec![(self.here(), self.select_item()?)];
+            while self.accept_symbol(",") {
+                items.push((self.here(), self.select_item()?));
+            }
+            Some(items)
+        };
+
+        self.expect_keyword("from")?;
+        let mut expression = self.table()?;
+
+        while self.accept_keyword("join") {
+            let joined = self.table()?;
+            self.expect_keyword("on")?;
+            let condition = self.condition()?;
+
+            expression = Expression::Select(Box::new(Expression::Product(Box::new(expression), Box::new(joined))), Box::new(condition));
+        }
+
+        if self.accept_keyword("where") {
+            expression = Expression::Select(Box::new(expression), Box::new(self.condition()?));
+        }
+
+        let mut group_by = None;
+        if self.accept_keyword("group") {
+            self.expect_keyword("by")?;
+
+            let mut columns = vec![self.column()?];
+            while self.accept_symbol(",") {
+                columns.push(self.column()?);
+            }
+            group_by = Some(columns);
+        }
+
+        let items = match items {
+            Some(items) => items,
+            None if distinct => return Ok(Expression::Distinct(Box::new(expression))),
+            None => return Ok(expression)
+        };
+
+        // On aggrège dès qu'il y a un group by ou une fonction d'aggrégation
+        let mut aggregates = Vec::new();
+        for (location, item) in &items {
+            if let SelectItem::Aggregate(function, column, alias) = item {
+                let name = alias.clone().unwrap_or_else(|| aggregate_name(*function, column));
+                let column = if column == "*" { column.clone() } else { self.resolve(column, location)?.0 };
+                aggregates.push((*function, column, name));
+            }
+        }
+        if group_by.is_some() || !aggregates.is_empty() {
+            expression = Expression::Aggregate(Box::new(expression), group_by.unwrap_or_default(), aggregates);
+        }
+
+        // Chaque colonne est projetée sous son nom qualifié, puis sort sous son alias ou son nom sans alias
+        let mut columns = Vec::new();
+        let (mut old_columns, mut new_columns) = (Vec::new(), Vec::new());
+        for (location, item) in items {
+            match item {
+                SelectItem::Column(column, alias) => {
+                    let (qualified, name) = self.resolve(&column, &location)?;
+                    let output = alias.unwrap_or(name);
+                    if output != qualified {
+                        old_columns.push(qualified.clone());
+                        new_columns.push(output);
+                    }
+                    columns.push(qualified);
+                },
+                SelectItem::Aggregate(function, column, alias) => columns.push(alias.unwrap_or_else(|| aggregate_name(function, &column)))
+            }
+        }
+        expression = Expression::Project(Box::new(expression), columns);
+
+        if !old_columns.is_empty() {
+            expression = Expression::Rename(Box::new(expression), old_columns, new_columns);
+        }
+
+        if distinct {
+            expression = Expression::Distinct(Box::new(expression));
+        }
+
+        Ok(expression)
+    }
+
+    fn select_item(&mut self) -> Result<SelectItem> {
+        let name = self.identifier()?;
+
+        let functions = [
+            ("count", AggregateFunction::Count),
+            ("sum", AggregateFunction::Sum),
+            ("min", AggregateFunction::Min),
+            ("max", AggregateFunction::Max),
+            ("avg", AggregateFunction::Avg)
+        ];
+        let function = functions.iter().find(|(function, _)| name.eq_ignore_ascii_case(function));
+        let item = match function {
+            Some((_, function)) if self.accept_symbol("(") => {
+                let column = if self.accept_symbol("*") { String::from("*") } else { self.identifier()? };
+                self.expect_symbol(")")?;
+
+                SelectItem::Aggregate(*function, column, None)
+            },
+            _ => SelectItem::Column(name, None)
+        };
+
+        if !self.accept_keyword("as") {
+            return Ok(item);
+        }
+        let alias = Some(self.identifier()?);
+        Ok(match item {
+            SelectItem::Column(column, _) => SelectItem::Column(column, alias),
+            SelectItem::Aggregate(function, column, _) => SelectItem::Aggregate(function, column, alias)
+        })
+    }
+
+    /**
+     * A table of the FROM clause, added to the scope; the columns of an aliased table are renamed into alias.column.
+     */
+    fn table(&mut self) -> Result<Expression> {
+        let expression = if self.accept_symbol("(") {
+            let expression = self.query()?;
+            self.expect_symbol(")")?;
+            expression
+        } else {
+            self.next_as("a table", |token| match token {
+                Token::Identifier(filename) | Token::Str(filename) => Some(Expression::Load(CsvFile::new(filename), None)),
+                _ => None
+            })?
+        };
+
+        let aliased = self.accept_keyword("as") ||
+            matches!(self.peek(), Some(Token::Identifier(word)) if !CLAUSE_KEYWORDS.iter().any(|keyword| word.eq_ignore_ascii_case(keyword)));
+        if !aliased {
+            self.scope.push((None, expression.clone()));
+            return Ok(expression);
+        }
+
+        let alias = self.identifier()?;
+        let columns = columns_of(&expression)?;
+        let qualified = columns.iter().map(|column| format!("{}.{}", alias, column)).collect();
+        self.scope.push((Some(alias), expression.clone()));
+
+        Ok(Expression::Rename(Box::new(expression), columns, qualified))
+    }
+
+    fn condition(&mut self) -> Result<Condition> {
+        let mut condition = self.and_condition()?;
+        while self.accept_keyword("or") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and_condition()?));
+        }
+        Ok(condition)
+    }
+
+    fn and_condition(&mut self) -> Result<Condition> {
+        let mut condition = self.not_condition()?;
+        while self.accept_keyword("and") {
+            condition = Condition::And(Box::new(condition), Box::new(self.not_condition()?));
+        }
+        Ok(condition)
+    }
+
+    fn not_condition(&mut self) -> Result<Condition> {
+        if self.accept_keyword("not") {
+            return Ok(Condition::Not(Box::new(self.not_condition()?)));
+        }
+        if self.accept_symbol("(") {
+            let condition = self.condition()?;
+            self.expect_symbol(")")?;
+            return Ok(condition);
+        }
+        // TRUE et FALSE sont des conditions, sauf s'ils sont comparés
+        let compared = matches!(self.tokens.get(self.position + 1), Some((Token::Symbol(s), _)) if COMPARATORS.contains(&&s[..]));
+        if !compared && self.accept_keyword("true") {
+            return Ok(Condition::True);
+        }
+        if !compared && self.accept_keyword("false") {
+            return Ok(Condition::False);
+        }
+
+        let v1 = self.operand()?;
+        if self.accept_keyword("is") {
+            let negated = self.accept_keyword("not");
+            self.expect_keyword("null")?;
+
+            let test = Condition::IsNull(v1);
+            return Ok(if negated { Condition::Not(Box::new(test)) } else { test });
+        }
+        let comparator = self.next_as("a comparator", |token| match token {
+            Token::Symbol(s) if COMPARATORS.contains(&&s[..]) => Some(s),
+            _ => None
+        })?;
+        let v2 = self.operand()?;
+
+        Ok(match &comparator[..] {
+            "=" => Condition::Equal(v1, v2),
+            "!=" | "<>" => Condition::NotEqual(v1, v2),
+            "<" => Condition::Less(v1, v2),
+            "<=" => Condition::LessEq(v1, v2),
+            ">" => Condition::More(v1, v2),
+            _ => Condition::MoreEq(v1, v2)
+        })
+    }
+
+    fn operand(&mut self) -> Result<Value> {
+        let location = self.here();
+        let value = self.literal_or_column()?;
+
+        match value {
+            Value::Column(name) => Ok(Value::Column(self.resolve(&name, &location)?.0)),
+            value => Ok(value)
+        }
+    }
+
+    fn literal_or_column(&mut self) -> Result<Value> {
+        // DATE '2021-03-14'
+        if self.peek_keyword("date") && matches!(self.tokens.get(self.position + 1), Some((Token::Str(_), _))) {
+            self.position += 1;
+            return self.next_as("a date like '2021-03-14'", |token| match token {
+                Token::Str(date) => NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok().map(Value::Date),
+                _ => None
+            });
+        }
+
+        self.next_as("a column or a literal", |token| match token {
+            Token::Identifier(word) if word.eq_ignore_ascii_case("true") => Some(Value::Bool(true)),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("false") => Some(Value::Bool(false)),
+            Token::Identifier(word) if word.eq_ignore_ascii_case("null") => Some(Value::Null),
+            Token::Identifier(column) => Some(Value::Column(column)),
+            Token::Integer(i) => Some(Value::Int(i)),
+            Token::Float(x) => Some(Value::Float(x)),
+            Token::Str(s) => Some(Value::Str(s)),
+            _ => None
+        })
+    }
+}
+
+/**
+ * Name of an aggregate column with no alias, e.g. "count(*)".
+ */
+fn aggregate_name(function: AggregateFunction, column: &str) -> String {
+    format!("{}({})", format!("{:?}", function).to_lowercase(), column)
+}
+
+/**
+ * Sort the entries of a single SELECT just below its projection, then keep the first ones
+ * (above the duplicate elimination, which changes their number).
+ */
+fn order_below_projection(expression: Expression, keys: Vec<(String, SortOrder)>, limit: Option<(usize, usize)>) -> Expression {
+    match expression {
+        Expression::Distinct(expression_from) => limited(Expression::Distinct(Box::new(order_below_projection(*expression_from, keys, None))), limit),
+        Expression::Rename(expression_from, old_columns, new_columns) =>
+            Expression::Rename(Box::new(order_below_projection(*expression_from, keys, limit)), old_columns, new_columns),
+        Expression::Project(expression_from, columns) => Expression::Project(Box::new(limited(Expression::Sort(expression_from, keys), limit)), columns),
+        // SELECT * n'a pas de projection
+        expression => limited(Expression::Sort(Box::new(expression), keys), limit)
+    }
+}
+
+fn limited(expression: Expression, limit: Option<(usize, usize)>) -> Expression {
+    match limit {
+        Some((limit, offset)) => Expression::Limit(Box::new(expression), limit, offset),
+        None => expression
+    }
+}
+
+pub fn get_expression_from_sql(sql: &str) -> Result<Expression> {
+    let mut parser = SqlParser { tokens: tokenize(sql)?, position: 0, scope: Vec::new(), finished_scope: Vec::new() };
+
+    let expression = parser.query()?;
+    parser.accept_symbol(";");
+    if let Some(token) = parser.peek() {
+        return Err(parser.error(format!("unexpected {:?} after the end of the query", token)));
+    }
+
+    Ok(expression)
+}
@@ -0,0 +1,151 @@
+use crate::types::*;
+use crate::error::*;
+use crate::eval::{parse_field, total_order};
+use crate::input::open_csv;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+/**
+ * Statistics of a CSV file, gathered from its first `SAMPLE_SIZE` entries.
+ */
+#[derive(Debug)]
+pub struct TableStats {
+    pub rows: usize,
+    pub columns: HashMap<String, ColumnStats>
+}
+
+#[derive(Debug, Clone)]
+pub struct ColumnStats {
+    pub distinct: usize, // number of distinct values, nulls excluded; estimated beyond SKETCH_SIZE
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    pub nulls: usize // number of NULL fields
+}
+
+/**
+ * The statistics of each file read so far during this run, along with its modification time when they were gathered.
+ */
+type StatsCache = HashMap<CsvFile, (Option<SystemTime>, Rc<TableStats>)>;
+
+thread_local! {
+    // Les statistiques d'un fichier ne sont pas enregistrées, mais ne lisent qu'un échantillon : l'optimisation ne dépend pas de sa taille
+    static STATS_CACHE: RefCell<StatsCache> = RefCell::new(HashMap::new());
+}
+
+// Nombre de plus petites empreintes gardées par colonne : en deçà, le nombre de valeurs distinctes est exact
+const SKETCH_SIZE: usize = 1024;
+// Nombre d'entrées lues au début de chaque fichier : les statistiques des fichiers plus grands sont extrapolées
+pub const SAMPLE_SIZE: usize = 10000;
+
+/**
+ * The statistics of a CSV file, sampled once per run and again only when it has been modified since the last call.
+ */
+pub fn table_stats(file: &CsvFile) -> Result<Rc<TableStats>> {
+    let modified = std::fs::metadata(&file.filename).and_then(|metadata| metadata.modified()).ok();
+    let cached = STATS_CACHE.with(|cache| cache.borrow().get(file)
+        .filter(|(cached_modified, _)| modified.is_some() && *cached_modified == modified)
+        .map(|(_, stats)| stats.clone()));
+
+    match cached {
+        Some(stats) => Ok(stats),
+        None => {
+            let stats = Rc::new(collect_stats(file)?);
+            STATS_CACHE.with(|cache| cache.borrow_mut().insert(file.clone(), (modified, stats.clone())));
+
+            Ok(stats)
+        }
+    }
+}
+
+/**
+ * Estimates the number of distinct values of a column in bounded memory, by keeping only the
+ * `SKETCH_SIZE` smallest hashes of its values (a "k minimum values" sketch).
+ */
+struct DistinctSketch {
+    hashes: BTreeSet<u64>
+}
+
+impl DistinctSketch {
+    fn new() -> DistinctSketch {
+        DistinctSketch { hashes: BTreeSet::new() }
+    }
+
+    fn insert(&mut self, value: &Value) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if self.hashes.len() < SKETCH_SIZE {
+            self.hashes.insert(hash);
+        } else if hash < *self.hashes.iter().next_back().unwrap() && self.hashes.insert(hash) {
+            self.hashes.pop_last();
+        }
+    }
+
+    fn distinct(&self) -> usize {
+        if self.hashes.len() < SKETCH_SIZE {
+            return self.hashes.len();
+        }
+
+        // Les empreintes sont uniformes : la k-ième plus petite indique quelle part de l'espace couvrent k valeurs
+        let greatest = *self.hashes.iter().next_back().unwrap() as f64;
+        ((SKETCH_SIZE - 1) as f64 * (u64::MAX as f64 / greatest)).round() as usize
+    }
+}
+
+fn collect_stats(file: &CsvFile) -> Result<TableStats> {
+    let (mut rdr, headers) = open_csv(file)?;
+
+    let mut rows = 0;
+    let mut sketches = headers.iter().map(|_| DistinctSketch::new()).collect::<Vec<_>>();
+    let mut columns = vec![ColumnStats { distinct: 0, min: None, max: None, nulls: 0 }; headers.len()];
+
+    let mut records = rdr.records();
+    for record in records.by_ref().take(SAMPLE_SIZE) {
+        let record = record.map_err(|e| EngineError::io(&file.filename, e))?;
+        rows += 1;
+
+        for ((field, column), sketch) in record.iter().zip(columns.iter_mut()).zip(sketches.iter_mut()) {
+            let value = parse_field(field, &file.dialect.nulls);
+            if let Value::Null = value {
+                column.nulls += 1;
+                continue;
+            }
+
+            sketch.insert(&value);
+            if column.min.as_ref().is_none_or(|min| total_order(&value, min) == Ordering::Less) {
+                column.min = Some(value.clone());
+            }
+            if column.max.as_ref().is_none_or(|max| total_order(&value, max) == Ordering::Greater) {
+                column.max = Some(value);
+            }
+        }
+    }
+
+    for (column, sketch) in columns.iter_mut().zip(sketches) {
+        column.distinct = sketch.distinct().min(rows - column.nulls);
+    }
+
+    // Au-delà de l'échantillon, le nombre d'entrées est extrapolé d'après la taille du fichier
+    let sampled_bytes = records.reader().position().byte();
+    if rows < SAMPLE_SIZE || sampled_bytes == 0 || records.next().is_none() {
+        return Ok(TableStats { rows, columns: headers.into_iter().zip(columns).collect() });
+    }
+
+    let file_bytes = std::fs::metadata(&file.filename).map_err(|e| EngineError::io(&file.filename, e))?.len();
+    let scale = file_bytes as f64 / sampled_bytes as f64;
+    for column in columns.iter_mut() {
+        // Une colonne dont presque toutes les valeurs de l'échantillon diffèrent est supposée en avoir autant que d'entrées
+        if column.distinct as f64 >= 0.9 * (rows - column.nulls) as f64 {
+            column.distinct = (column.distinct as f64 * scale).round() as usize;
+        }
+        column.nulls = (column.nulls as f64 * scale).round() as usize;
+    }
+
+    Ok(TableStats { rows: (rows as f64 * scale).round() as usize, columns: headers.into_iter().zip(columns).collect() })
+}
@@ -0,0 +1,88 @@
+use crate::cost::*;
+use crate::eval::*;
+use crate::optimize::*;
+use crate::stats::*;
+use crate::types::*;
+use crate::tests::temporary_file::*;
+use crate::tests::helpers::*;
+use std::rc::Rc;
+
+#[test]
+fn test_table_stats() {
+    let projets = CsvFile::new(String::from("project_spec/samples/projets.csv"));
+    let stats = table_stats(&projets).unwrap();
+    assert_eq!(stats.rows, 20);
+    assert_eq!(stats.columns["idp"].distinct, 20);
+    assert_eq!(stats.columns["idp"].min, Some(Value::Int(1)));
+    assert_eq!(stats.columns["idp"].max, Some(Value::Int(20)));
+    assert_eq!(stats.columns["titre"].nulls, 0);
+
+    // The file is only read once
+    assert!(Rc::ptr_eq(&stats, &table_stats(&projets).unwrap()));
+}
+
+#[test]
+fn test_distinct_estimate_of_large_files() {
+    // Many more distinct values than the sketch keeps, and a column with a few of them
+    let content = (0..50000).map(|i| format!("{:05},{}\n", i, i % 7)).collect::<String>();
+    let large = TemporaryFile::new("large.csv", &format!("id,weekday\n{}", content));
+
+    let stats = table_stats(&CsvFile::new(large.path.clone())).unwrap();
+
+    // Only the first entries are read, the others are extrapolated from the size of the file
+    assert!((49000..=51000).contains(&stats.rows), "{} entries estimated", stats.rows);
+    assert_eq!(stats.columns["id"].max, Some(Value::Int(SAMPLE_SIZE as i64 - 1)));
+    assert_eq!(stats.columns["weekday"].distinct, 7);
+    let distinct = stats.columns["id"].distinct as f64;
+    assert!((45000.0..=55000.0).contains(&distinct), "{} distinct values estimated", distinct);
+}
+
+#[test]
+fn test_cardinality_estimates() {
+    let rows = |expression: Expression| estimate(&Box::new(expression)).unwrap().rows;
+
+    assert_eq!(rows(Expression::Product(load("projets.csv"), load("membres.csv"))), 20.0 * 223.0);
+    assert_eq!(rows(Expression::Select(load("projets.csv"), Box::new(Condition::Equal(column("idp"), Value::Int(7))))), 1.0);
+    assert_eq!(rows(Expression::Select(load("projets.csv"), Box::new(Condition::Equal(column("idp"), Value::Int(50))))), 0.0);
+
+    let below_five = rows(Expression::Select(load("projets.csv"), Box::new(Condition::Less(column("idp"), Value::Int(5)))));
+    assert!((3.0..=5.0).contains(&below_five));
+
+    // Each entry of membres matches one project
+    let join = rows(Expression::JoinProjectRename(
+        Box::new(Expression::Rename(load("projets.csv"), vec![String::from("idp")], vec![String::from("pid")])),
+        load("membres.csv"),
+        Box::new(Condition::Equal(column("pid"), column("idp"))),
+        vec![String::from("titre"), String::from("ide")],
+        vec![String::from("titre"), String::from("ide")]
+    ));
+    assert!((200.0..=250.0).contains(&join));
+}
+
+#[test]
+fn test_choose_join_sides() {
+    let join = |expr1, expr2| Box::new(Expression::JoinProjectRename(
+        expr1,
+        expr2,
+        Box::new(Condition::Equal(column("pid"), column("idp"))),
+        vec![String::from("titre"), String::from("ide")],
+        vec![String::from("titre"), String::from("ide")]
+    ));
+    let projets = Box::new(Expression::Rename(load("projets.csv"), vec![String::from("idp")], vec![String::from("pid")]));
+
+    // The smallest side goes first, to be hashed
+    let expression = join(load("membres.csv"), projets.clone());
+    let optimized = ChooseJoinSidesOptimizer{}.optimize(expression.clone()).unwrap();
+    assert_eq!(optimized, join(projets.clone(), load("membres.csv")));
+    assert!(estimate(&optimized).unwrap().cost < estimate(&expression).unwrap().cost);
+
+    let (columns, mut entries) = eval(*expression).unwrap();
+    let (optimized_columns, mut optimized_entries) = eval(*optimized.clone()).unwrap();
+    assert_eq!(optimized_columns, columns);
+    entries.sort_by_cached_key(|entry| format!("{:?}", entry));
+    optimized_entries.sort_by_cached_key(|entry| format!("{:?}", entry));
+    assert_eq!(optimized_entries, entries);
+
+    // Already in the right order
+    assert_eq!(ChooseJoinSidesOptimizer{}.optimize(optimized.clone()).unwrap(), optimized);
+}
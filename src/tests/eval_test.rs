@@ -1,13 +1,538 @@
 use crate::eval::*;
 use crate::types::*;
+use crate::parser::*;
+use crate::error::*;
+use crate::tests::temporary_file::*;
 
 #[test]
 fn test_load() {
-    let (columns, entries) = eval(Box::new(Expression::Load(String::from("project_spec/samples/projets.csv"), None)));
+    let (columns, entries) = eval(Expression::Load(CsvFile::new(String::from("project_spec/samples/projets.csv")), None)).unwrap();
 
-    let mut columns = columns.keys().into_iter().cloned().collect::<Vec<String>>();
+    let mut columns = columns.keys().cloned().collect::<Vec<String>>();
     columns.sort();
 
     assert_eq!(columns, vec!["idp", "responsable", "titre"]);
     assert_eq!(entries[0].len(), 3);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_select_string_literal() {
+    let (columns, entries) = eval(get_expression_from_str(
+        r#"
+        {"operation": "selection", "args": {
+            "condition": {"comparator": "=", "attribute1": "titre", "attribute2": {"literal": "Ursa"}},
+            "object": {
+                "operation": "load",
+                "args": { "filename": "project_spec/samples/projets.csv"}
+            }
+        }}
+        "#
+    )).unwrap();
+
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0][*columns.get("idp").unwrap()], Value::Int(12));
+}
+
+#[test]
+fn test_comparators_on_strings() {
+    let expression = |comparator: &str| Box::new(get_expression_from_str(&format!(
+        r#"
+        {{"operation": "selection", "args": {{
+            "condition": {{"comparator": "{}", "attribute1": "titre", "attribute2": {{"literal": "Ursa"}}}},
+            "object": {{
+                "operation": "load",
+                "args": {{ "filename": "project_spec/samples/projets.csv"}}
+            }}
+        }}}}
+        "#, comparator
+    )));
+
+    let count = |comparator: &str| eval(*expression(comparator)).unwrap().1.len();
+
+    assert_eq!(count("="), 1);
+    assert_eq!(count("!="), 19);
+    assert_eq!(count("<>"), 19);
+    assert_eq!(count("<") + count(">="), 20);
+    assert_eq!(count("<=") + count(">"), 20);
+    assert_eq!(count(">="), count(">") + 1);
+}
+
+#[test]
+fn test_aggregate() {
+    let (columns, entries) = eval(get_expression_from_str(
+        r#"
+        {"operation": "aggregate", "args": {
+            "group by": ["idp"],
+            "aggregates": [
+                {"function": "count", "attribute": "*", "name": "members"},
+                {"function": "sum", "attribute": "ide", "name": "total"},
+                {"function": "min", "attribute": "ide", "name": "first"},
+                {"function": "max", "attribute": "ide", "name": "last"},
+                {"function": "avg", "attribute": "ide", "name": "average"}
+            ],
+            "object": {
+                "operation": "load",
+                "args": { "filename": "project_spec/samples/membres.csv"}
+            }
+        }}
+        "#
+    )).unwrap();
+
+    let mut column_list = columns.keys().cloned().collect::<Vec<_>>();
+    column_list.sort();
+    assert_eq!(column_list, vec!["average", "first", "idp", "last", "members", "total"]);
+    assert_eq!(entries.len(), 20);
+
+    let members = entries.iter().map(|entry| match entry[columns["members"]] {
+        Value::Int(i) => i,
+        _ => panic!("count should be an integer")
+    }).sum::<i64>();
+    assert_eq!(members, 223);
+
+    for entry in &entries {
+        let value = |column: &str| match entry[columns[column]] {
+            Value::Int(i) => i as f64,
+            Value::Float(f) => f,
+            _ => panic!("number expected")
+        };
+        assert!(value("first") <= value("average") && value("average") <= value("last"));
+        assert_eq!(value("average"), value("total") / value("members"));
+    }
+}
+
+#[test]
+fn test_aggregate_without_group_on_empty_input() {
+    let (columns, entries) = eval(crate::sql::get_expression_from_sql(
+        "SELECT count(*) AS n, sum(idp) AS total, avg(idp) AS average, min(titre) AS first, max(titre) AS last \
+         FROM project_spec/samples/projets.csv WHERE idp < 0"
+    ).unwrap()).unwrap();
+
+    // A single entry, where count is 0 and the other functions are NULL
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0][columns["n"]], Value::Int(0));
+    for column in ["total", "average", "first", "last"] {
+        assert_eq!(entries[0][columns[column]], Value::Null);
+    }
+}
+
+#[test]
+fn test_average_of_integers_is_not_rounded() {
+    let (columns, entries) = eval(get_expression_from_str(
+        r#"
+        {"operation": "aggregate", "args": {
+            "group by": [],
+            "aggregates": [{"function": "avg", "attribute": "idp", "name": "average"}],
+            "object": {
+                "operation": "load",
+                "args": { "filename": "project_spec/samples/projets.csv"}
+            }
+        }}
+        "#
+    )).unwrap();
+
+    // The projects are numbered from 1 to 20
+    assert_eq!(entries[0][columns["average"]], Value::Float(10.5));
+}
+
+#[test]
+fn test_top_n_matches_sort_and_limit() {
+    let sorted = |operation: &str, extra_args: &str| eval(get_expression_from_str(&format!(
+        r#"
+        {{"operation": "{}", "args": {{
+            "keys": [{{"attribute": "ide", "order": "desc"}}, {{"attribute": "idp"}}],
+            {}
+            "object": {{
+                "operation": "load",
+                "args": {{ "filename": "project_spec/samples/membres.csv"}}
+            }}
+        }}}}
+        "#, operation, extra_args
+    ))).unwrap();
+
+    let (columns, all) = sorted("sort", "");
+    let ide = columns["ide"];
+    let idp = columns["idp"];
+    assert!(all.windows(2).all(|pair| match (&pair[0][ide], &pair[1][ide], &pair[0][idp], &pair[1][idp]) {
+        (Value::Int(ide1), Value::Int(ide2), Value::Int(idp1), Value::Int(idp2)) => ide1 > ide2 || (ide1 == ide2 && idp1 <= idp2),
+        _ => false
+    }));
+
+    let (_, top) = sorted("topn", r#""limit": 10, "offset": 5,"#);
+    assert_eq!(top, all[5..15].to_vec());
+
+    let (_, limited) = eval(get_expression_from_str(
+        r#"
+        {"operation": "limit", "args": {
+            "limit": 1000, "offset": 220,
+            "object": {
+                "operation": "load",
+                "args": { "filename": "project_spec/samples/membres.csv"}
+            }
+        }}
+        "#
+    )).unwrap();
+    assert_eq!(limited.len(), 3);
+}
+
+#[test]
+fn test_union_set_and_bag_semantics() {
+    let union = |operation: &str| eval(get_expression_from_str(&format!(
+        r#"
+        {{"operation": "{}", "args": {{
+            "object1": {{
+                "operation": "load",
+                "args": {{ "filename": "project_spec/samples/membres.csv"}}
+            }},
+            "object2": {{
+                "operation": "load",
+                "args": {{ "filename": "project_spec/samples/membres.csv"}}
+            }}
+        }}}}
+        "#, operation
+    ))).unwrap().1;
+
+    let bag = union("union all");
+    let set = union("union");
+    assert_eq!(bag.len(), 2 * 223);
+    assert_eq!(set.len(), bag.iter().collect::<std::collections::HashSet<_>>().len());
+    assert!(set.len() <= 223);
+}
+
+#[test]
+fn test_minus_realigns_columns() {
+    let (_, entries) = eval(get_expression_from_str(
+        r#"
+        {"operation": "minus", "args": {
+            "object1": {
+                "operation": "product",
+                "args": {
+                    "object1": {"operation": "load", "args": { "filename": "project_spec/samples/projets.csv"}},
+                    "object2": {"operation": "load", "args": { "filename": "project_spec/samples/departements.csv"}}
+                }
+            },
+            "object2": {
+                "operation": "selection",
+                "args": {
+                    "condition": {"comparator": "<", "attribute1": "idd", "attribute2": "10"},
+                    "object": {
+                        "operation": "product",
+                        "args": {
+                            "object1": {"operation": "load", "args": { "filename": "project_spec/samples/departements.csv"}},
+                            "object2": {"operation": "load", "args": { "filename": "project_spec/samples/projets.csv"}}
+                        }
+                    }
+                }
+            }
+        }}
+        "#
+    )).unwrap();
+
+    // Only the entries with idd = 10 remain, one per project
+    assert_eq!(entries.len(), 20);
+}
+
+#[test]
+fn test_union_by_position() {
+    let (columns, entries) = eval(get_expression_from_str(
+        r#"
+        {"operation": "union all", "args": {
+            "columns": "by position",
+            "object1": {"operation": "load", "args": { "filename": "project_spec/samples/membres.csv"}},
+            "object2": {
+                "operation": "projection",
+                "args": {
+                    "attributes": ["idd", "directeur"],
+                    "object": {"operation": "load", "args": { "filename": "project_spec/samples/departements.csv"}}
+                }
+            }
+        }}
+        "#
+    )).unwrap();
+
+    let mut column_list = columns.keys().cloned().collect::<Vec<_>>();
+    column_list.sort();
+    assert_eq!(column_list, vec!["ide", "idp"]);
+    assert_eq!(entries.len(), 223 + 10);
+}
+
+#[test]
+fn test_union_schema_mismatch() {
+    let result = eval(get_expression_from_str(
+        r#"
+        {"operation": "union", "args": {
+            "object1": {
+                "operation": "projection",
+                "args": {
+                    "attributes": ["idp", "titre"],
+                    "object": {"operation": "load", "args": { "filename": "project_spec/samples/projets.csv"}}
+                }
+            },
+            "object2": {
+                "operation": "renaming",
+                "args": {
+                    "old attributes": ["nom"], "new attributes": ["titre"],
+                    "object": {
+                        "operation": "projection",
+                        "args": {
+                            "attributes": ["idd", "nom"],
+                            "object": {"operation": "load", "args": { "filename": "project_spec/samples/departements.csv"}}
+                        }
+                    }
+                }
+            }
+        }}
+        "#
+    ));
+
+    match result {
+        Err(EngineError::SchemaMismatch(message)) =>
+            assert!(message.contains("columns [\"idp\"] only appear on the left and columns [\"idd\"] only on the right")),
+        _ => panic!("expected a schema mismatch")
+    }
+}
+
+#[test]
+fn test_errors_are_reported() {
+    let unknown_column = eval(get_expression_from_str(
+        r#"
+        {"operation": "projection", "args": {
+            "attributes": ["idp", "budget"],
+            "object": {"operation": "load", "args": { "filename": "project_spec/samples/projets.csv"}}
+        }}
+        "#
+    ));
+    assert!(matches!(unknown_column, Err(EngineError::UnknownColumn { ref column, .. }) if column == "budget"));
+
+    let missing_file = eval(Expression::Load(CsvFile::new(String::from("project_spec/samples/nowhere.csv")), None));
+    assert_eq!(missing_file.map(|_| ()).unwrap_err().exit_code(), 2);
+
+    let bad_comparator = get_expression_from_json(
+        r#"{"operation": "selection", "args": {"condition": {"comparator": "~", "attribute1": "idp", "attribute2": "1"},
+            "object": {"operation": "load", "args": { "filename": "project_spec/samples/projets.csv"}}}}"#
+    );
+    assert!(matches!(bad_comparator, Err(EngineError::Parse { ref location, .. }) if location == "args.condition"));
+}
+
+#[test]
+fn test_operators_stream_their_entries() {
+    // The limit stops pulling entries from the product, which only reads the right side in full
+    let expression = Box::new(get_expression_from_str(
+        r#"
+        {"operation": "limit", "args": {
+            "limit": 3, "offset": 2,
+            "object": {"operation": "product", "args": {
+                "object1": {"operation": "load", "args": { "filename": "project_spec/samples/projets.csv"}},
+                "object2": {"operation": "load", "args": { "filename": "project_spec/samples/membres.csv"}}
+            }}
+        }}
+        "#
+    ));
+    let (table, stats) = eval_analyzed(*expression.clone()).unwrap();
+    assert_eq!(table.1.len(), 3);
+    assert_eq!(stats.iter().map(|node_stats| node_stats.rows).collect::<Vec<_>>(), vec![3, 5, 1, 223]);
+    assert_eq!(eval(*expression).unwrap().1, table.1);
+
+    // Nothing is read before the first entry is pulled
+    let (columns, mut rows) = compile(Expression::Load(CsvFile::new(String::from("project_spec/samples/projets.csv")), None)).unwrap();
+    assert!(columns.contains_key("idp"));
+    assert!(rows.next().unwrap().is_ok());
+}
+
+#[test]
+fn test_join_algorithm_choice() {
+    assert_eq!(choose_join_algorithm(1, 0, None), JoinAlgorithm::Hash);
+    assert_eq!(choose_join_algorithm(0, 1, None), JoinAlgorithm::SortMerge);
+    assert_eq!(choose_join_algorithm(1, 2, Some(1000)), JoinAlgorithm::SortMerge);
+    assert_eq!(choose_join_algorithm(0, 0, None), JoinAlgorithm::NestedLoop);
+    assert_eq!(choose_join_algorithm(1, 0, Some(3)), JoinAlgorithm::NestedLoop);
+}
+
+#[test]
+fn test_joins_match_selections_over_products() {
+    let load = |filename: &str, renaming: &[(&str, &str)]| Box::new(Expression::Rename(
+        Box::new(Expression::Load(CsvFile::new(format!("project_spec/samples/{}", filename)), None)),
+        renaming.iter().map(|(old, _)| String::from(*old)).collect(),
+        renaming.iter().map(|(_, new)| String::from(*new)).collect()
+    ));
+    let column = |name: &str| Value::Column(String::from(name));
+    let and = |c1, c2| Box::new(Condition::And(c1, c2));
+
+    // Each join must give the same entries as a selection over the product, whatever its algorithm
+    let joins = vec![
+        // A comparison alone: sort-merge join
+        (load("projets.csv", &[("idp", "pid")]), load("membres.csv", &[]), Box::new(Condition::Less(column("pid"), column("idp")))),
+        (load("membres.csv", &[]), load("projets.csv", &[("idp", "pid")]), Box::new(Condition::MoreEq(column("pid"), column("idp")))),
+        // An equality and a comparison: sort-merge join within the groups of the same key
+        (load("membres.csv", &[("idp", "idp1")]), load("membres.csv", &[("idp", "idp2"), ("ide", "ide2")]),
+            and(Box::new(Condition::Equal(column("ide"), column("ide2"))), Box::new(Condition::More(column("idp1"), column("idp2"))))),
+        // An equality alone: hash join
+        (load("employes.csv", &[("nom", "employe")]), load("membres.csv", &[("ide", "membre")]), Box::new(Condition::Equal(column("membre"), column("ide")))),
+        // A tiny side: nested loop
+        (load("departements.csv", &[]), load("employes.csv", &[("nom", "employe")]), Box::new(Condition::Equal(column("dpt"), column("idd"))))
+    ];
+
+    for (left, right, condition) in joins {
+        let (columns, mut expected) = eval(Expression::Select(Box::new(Expression::Product(left.clone(), right.clone())), condition.clone())).unwrap();
+        let mut names = columns.keys().cloned().collect::<Vec<_>>();
+        names.sort_by_key(|name| columns[name]);
+
+        let (join_columns, mut joined) = eval(Expression::JoinProjectRename(left, right, condition, names.clone(), names)).unwrap();
+        assert_eq!(join_columns, columns);
+        assert!(!expected.is_empty());
+
+        expected.sort_by_cached_key(|entry| format!("{:?}", entry));
+        joined.sort_by_cached_key(|entry| format!("{:?}", entry));
+        assert_eq!(joined, expected);
+    }
+}
+
+fn invoices() -> TemporaryFile {
+    TemporaryFile::new("invoices.csv", "id,price,paid,due\n1,12.50,true,2021-03-14\n2,8,FALSE,2021-01-02\n3,9.75,false,2020-12-31\n")
+}
+
+#[test]
+fn test_typed_values() {
+    let invoices = invoices();
+    let path = &invoices.path;
+    let (columns, entries) = eval(Expression::Load(CsvFile::new(path.clone()), None)).unwrap();
+    assert_eq!(entries[0][columns["price"]], Value::Float(12.5));
+    assert_eq!(entries[1][columns["paid"]], Value::Bool(false));
+    assert_eq!(entries[2][columns["due"]], Value::Date(chrono::NaiveDate::from_ymd_opt(2020, 12, 31).unwrap()));
+
+    let ids = |condition: &str| {
+        let (columns, entries) = eval(get_expression_from_str(&format!(
+            r#"{{"operation": "selection", "args": {{"condition": {}, "object": {{"operation": "load", "args": {{"filename": "{}"}}}}}}}}"#,
+            condition, path
+        ))).unwrap();
+        entries.iter().map(|entry| entry[columns["id"]].clone()).collect::<Vec<_>>()
+    };
+
+    // Integers and floats are compared numerically
+    assert_eq!(ids(r#"{"comparator": ">", "attribute1": "price", "attribute2": "9"}"#), vec![Value::Int(1), Value::Int(3)]);
+    assert_eq!(ids(r#"{"comparator": "=", "attribute1": "price", "attribute2": "8.0"}"#), vec![Value::Int(2)]);
+    assert_eq!(ids(r#"{"comparator": "=", "attribute1": "paid", "attribute2": {"literal": true}}"#), vec![Value::Int(1)]);
+    assert_eq!(ids(r#"{"comparator": "<", "attribute1": "due", "attribute2": {"date": "2021-01-01"}}"#), vec![Value::Int(3)]);
+
+    let (columns, entries) = eval(get_expression_from_str(&format!(
+        r#"{{"operation": "aggregate", "args": {{"group by": [], "aggregates": [
+            {{"function": "sum", "attribute": "price", "name": "total"}},
+            {{"function": "avg", "attribute": "price", "name": "average"}},
+            {{"function": "max", "attribute": "due", "name": "last"}}
+        ], "object": {{"operation": "load", "args": {{"filename": "{}"}}}}}}}}"#,
+        path
+    ))).unwrap();
+    assert_eq!(entries[0][columns["total"]], Value::Float(30.25));
+    assert_eq!(entries[0][columns["average"]], Value::Float(30.25 / 3.0));
+    assert_eq!(entries[0][columns["last"]], Value::Date(chrono::NaiveDate::from_ymd_opt(2021, 3, 14).unwrap()));
+}
+
+#[test]
+fn test_integers_equal_to_floats() {
+    use std::collections::HashSet;
+
+    // Hash joins rely on equal values having the same hash
+    assert_eq!(Value::Int(8), Value::Float(8.0));
+    assert_eq!(HashSet::from([Value::Int(8), Value::Float(8.0), Value::Float(8.5)]).len(), 2);
+    assert_eq!(parse_field("8", &[]), Value::Int(8));
+    assert_eq!(parse_field("NaN", &[]), Value::Str(String::from("NaN")));
+    assert_eq!(parse_field("2021-02-30", &[]), Value::Str(String::from("2021-02-30")));
+    assert_eq!(total_order(&Value::Float(7.5), &Value::Int(8)), std::cmp::Ordering::Less);
+}
+
+#[test]
+fn test_large_integers_compare_exactly_with_floats() {
+    use std::cmp::Ordering;
+    use std::collections::HashSet;
+
+    // 2^53 + 1 is rounded to 2^53 as a float, but the integers stay different
+    let big = 1i64 << 53;
+    assert_eq!(Value::Int(big), Value::Float(big as f64));
+    assert_ne!(Value::Int(big + 1), Value::Float(big as f64));
+    assert_eq!(HashSet::from([Value::Int(big), Value::Int(big + 1), Value::Float(big as f64)]).len(), 2);
+
+    assert_eq!(compare_values(&Value::Int(big + 1), &Value::Float(big as f64)), Some(Ordering::Greater));
+    assert_eq!(compare_values(&Value::Float(big as f64), &Value::Int(big + 1)), Some(Ordering::Less));
+    assert_eq!(compare_values(&Value::Int(-3), &Value::Float(-2.5)), Some(Ordering::Less));
+    assert_eq!(compare_values(&Value::Int(i64::MAX), &Value::Float(9223372036854775808.0)), Some(Ordering::Less));
+    assert_eq!(compare_values(&Value::Int(i64::MIN), &Value::Float(-9223372036854775808.0)), Some(Ordering::Equal));
+}
+
+#[test]
+fn test_three_valued_logic() {
+    let scores = TemporaryFile::new("scores.csv", "id,score\n1,10\n2,\n3,30\n");
+    let path = &scores.path;
+    let ids = |condition: &str| {
+        let (columns, entries) = eval(get_expression_from_str(&format!(
+            r#"{{"operation": "selection", "args": {{"condition": {}, "object": {{"operation": "load", "args": {{"filename": "{}"}}}}}}}}"#,
+            condition, path
+        ))).unwrap();
+        entries.iter().map(|entry| entry[columns["id"]].clone()).collect::<Vec<_>>()
+    };
+
+    // Comparisons with NULL are unknown, and so are their negations
+    assert_eq!(ids(r#"{"logical": "not", "condition": {"comparator": "=", "attribute1": "score", "attribute2": "10"}}"#), vec![Value::Int(3)]);
+    assert_eq!(ids(r#"{"logical": "or", "condition1": {"comparator": "<", "attribute1": "score", "attribute2": "20"},
+        "condition2": {"comparator": ">=", "attribute1": "score", "attribute2": "20"}}"#), vec![Value::Int(1), Value::Int(3)]);
+    // Unknown or true is true
+    assert_eq!(ids(r#"{"logical": "or", "condition1": {"comparator": "=", "attribute1": "score", "attribute2": "10"},
+        "condition2": {"comparator": "=", "attribute1": "id", "attribute2": "2"}}"#), vec![Value::Int(1), Value::Int(2)]);
+    assert_eq!(ids(r#"{"comparator": "is null", "attribute": "score"}"#), vec![Value::Int(2)]);
+    assert_eq!(ids(r#"{"comparator": "is not null", "attribute": "score"}"#), vec![Value::Int(1), Value::Int(3)]);
+    assert_eq!(ids(r#"{"comparator": "=", "attribute1": "score", "attribute2": {"literal": null}}"#), vec![]);
+
+    let (columns, entries) = eval(get_expression_from_str(&format!(
+        r#"{{"operation": "aggregate", "args": {{"group by": [], "aggregates": [
+            {{"function": "count", "attribute": "*", "name": "entries"}},
+            {{"function": "count", "attribute": "score", "name": "scores"}},
+            {{"function": "avg", "attribute": "score", "name": "average"}}
+        ], "object": {{"operation": "load", "args": {{"filename": "{}"}}}}}}}}"#,
+        path
+    ))).unwrap();
+    // NULL is ignored, except by count(*)
+    assert_eq!(entries[0][columns["entries"]], Value::Int(3));
+    assert_eq!(entries[0][columns["scores"]], Value::Int(2));
+    assert_eq!(entries[0][columns["average"]], Value::Float(20.0));
+
+    // Other texts can stand for NULL, the empty field then being an empty string
+    let missing = TemporaryFile::new("missing.csv", "id,score\n1,NA\n2,\n");
+    let mut file = CsvFile::new(missing.path.clone());
+    file.dialect.nulls = vec![String::from("NA")];
+    let (_, entries) = eval(Expression::Load(file, None)).unwrap();
+    assert_eq!(entries[0][1], Value::Null);
+    assert_eq!(entries[1][1], Value::Str(String::new()));
+}
+
+#[test]
+fn test_null_keys_never_join() {
+    // Enough entries for a hash or sort-merge join rather than a nested loop
+    let left = (0..20).map(|i| format!("{},{}\n", if i % 4 == 0 { String::new() } else { (i % 5).to_string() }, i)).collect::<String>();
+    let right = (0..20).map(|i| format!("{},{}\n", if i % 3 == 0 { String::new() } else { (i % 5).to_string() }, i)).collect::<String>();
+    let (left_file, right_file) = (TemporaryFile::new("left.csv", &format!("k,a\n{}", left)), TemporaryFile::new("right.csv", &format!("k2,b\n{}", right)));
+    let left = Box::new(Expression::Load(CsvFile::new(left_file.path.clone()), None));
+    let right = Box::new(Expression::Load(CsvFile::new(right_file.path.clone()), None));
+
+    let column = |name: &str| Value::Column(String::from(name));
+    let equality = Condition::Equal(column("k"), column("k2"));
+    let conditions = vec![
+        equality.clone(),
+        Condition::And(Box::new(equality), Box::new(Condition::Less(column("a"), column("b"))))
+    ];
+
+    for condition in conditions {
+        let (columns, mut expected) = eval(Expression::Select(Box::new(Expression::Product(left.clone(), right.clone())), Box::new(condition.clone()))).unwrap();
+        let mut names = columns.keys().cloned().collect::<Vec<_>>();
+        names.sort_by_key(|name| columns[name]);
+
+        let (_, mut joined) = eval(Expression::JoinProjectRename(left.clone(), right.clone(), Box::new(condition), names.clone(), names)).unwrap();
+        assert!(!expected.is_empty());
+        assert!(joined.iter().all(|entry| entry[columns["k"]] != Value::Null));
+
+        expected.sort_by_cached_key(|entry| format!("{:?}", entry));
+        joined.sort_by_cached_key(|entry| format!("{:?}", entry));
+        assert_eq!(joined, expected);
+    }
+
+    // Minus compares whole entries, NULL included
+    let (_, entries) = eval(Expression::Except(left.clone(), left)).unwrap();
+    assert!(entries.is_empty());
+}
@@ -0,0 +1,68 @@
+use crate::eval::*;
+use crate::explain::*;
+use crate::sql::*;
+
+#[test]
+fn test_format_plan() {
+    let expression = Box::new(get_expression_from_sql(
+        "SELECT idp AS id FROM project_spec/samples/projets.csv WHERE idp < 5 AND NOT titre = 'Ursa'"
+    ).unwrap());
+
+    assert_eq!(format_plan(&expression), concat!(
+        "Rename [idp -> id]\n",
+        "  Project [idp]\n",
+        "    Select (idp < 5 AND NOT titre = 'Ursa')\n",
+        "      Load project_spec/samples/projets.csv\n"
+    ));
+}
+
+#[test]
+fn test_format_analyzed_plan() {
+    let expression = Box::new(get_expression_from_sql(
+        "SELECT idp FROM project_spec/samples/projets.csv WHERE idp < 5"
+    ).unwrap());
+
+    let (table, stats) = eval_analyzed(*expression.clone()).unwrap();
+    assert_eq!(stats.iter().map(|node_stats| node_stats.rows).collect::<Vec<_>>(), vec![4, 4, 20]);
+    assert_eq!(table.1.len(), 4);
+
+    let plan = format_analyzed_plan(&expression, &stats);
+    assert!(plan.lines().nth(2).unwrap().starts_with("    Load project_spec/samples/projets.csv  (rows: 20, time: "));
+
+    // Profiling stops once the analyzed evaluation is done
+    let (_, stats) = eval_analyzed(get_expression_from_sql("SELECT * FROM project_spec/samples/membres.csv").unwrap()).unwrap();
+    assert_eq!(stats.len(), 1);
+}
+
+#[test]
+fn test_plans_to_dot() {
+    let expression = Box::new(get_expression_from_sql(
+        "SELECT titre FROM project_spec/samples/projets.csv WHERE titre = 'Ursa' EXCEPT SELECT titre FROM project_spec/samples/projets.csv"
+    ).unwrap());
+
+    assert_eq!(plans_to_dot(&[(String::from("Initial"), expression.clone())]), concat!(
+        "digraph plans {\n",
+        "  node [shape=box, fontname=\"monospace\"];\n",
+        "  subgraph cluster_0 {\n",
+        "  label=\"Initial\";\n",
+        "  p0_0 [label=\"Distinct\"];\n",
+        "  p0_1 [label=\"Minus\"];\n",
+        "  p0_2 [label=\"Project [titre]\"];\n",
+        "  p0_3 [label=\"Select titre = 'Ursa'\"];\n",
+        "  p0_4 [label=\"Load project_spec/samples/projets.csv\"];\n",
+        "  p0_3 -> p0_4;\n",
+        "  p0_2 -> p0_3;\n",
+        "  p0_1 -> p0_2;\n",
+        "  p0_5 [label=\"Project [titre]\"];\n",
+        "  p0_6 [label=\"Load project_spec/samples/projets.csv\"];\n",
+        "  p0_5 -> p0_6;\n",
+        "  p0_1 -> p0_5;\n",
+        "  p0_0 -> p0_1;\n",
+        "  }\n",
+        "}\n"
+    ));
+
+    // Each plan has its own box and node identifiers
+    let plans = plans_to_dot(&[(String::from("Initial"), expression.clone()), (String::from("Other"), expression)]);
+    assert!(plans.contains("subgraph cluster_1 {\n  label=\"Other\";\n  p1_0 [label=\"Distinct\"];"));
+}
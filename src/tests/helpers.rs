@@ -0,0 +1,16 @@
+use crate::types::*;
+
+/**
+ * Load a file of the sample tables of the subject.
+ */
+pub fn load(filename: &str) -> Box<Expression> {
+    Box::new(Expression::Load(CsvFile::new(format!("project_spec/samples/{}", filename)), None))
+}
+
+pub fn column(name: &str) -> Value {
+    Value::Column(String::from(name))
+}
+
+pub fn strings(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| String::from(*name)).collect()
+}
@@ -0,0 +1,70 @@
+use crate::eval::*;
+use crate::input::*;
+use crate::optimize::*;
+use crate::parser::*;
+use crate::types::*;
+use crate::tests::temporary_file::*;
+use std::collections::HashSet;
+
+#[test]
+fn test_semicolon_export() {
+    // A byte order mark, comments and spaces around the fields
+    let export = TemporaryFile::new("export.csv", "\u{feff}id; name\n# exported by hand\n1; Ada\n2;'Alan; Turing' \n");
+    let expression = Box::new(get_expression_from_str(&format!(
+        r##"{{"operation": "load", "args": {{"filename": "{}", "delimiter": ";", "quote": "'", "comment": "#", "trim": true}}}}"##,
+        export.path
+    )));
+
+    let (columns, entries) = eval(*expression).unwrap();
+    assert_eq!(columns["id"], 0);
+    assert_eq!(columns["name"], 1);
+    assert_eq!(entries, vec![
+        vec![Value::Int(1), Value::Str(String::from("Ada"))],
+        vec![Value::Int(2), Value::Str(String::from("Alan; Turing"))]
+    ]);
+}
+
+#[test]
+fn test_headerless_file() {
+    let headerless = TemporaryFile::new("headerless.tsv", "1\tAda\n2\tAlan\n");
+    let mut file = CsvFile::new(headerless.path.clone());
+    file.dialect.delimiter = b'\t';
+    file.dialect.has_header = false;
+
+    // Without names, the columns are numbered; the first line is an entry
+    assert_eq!(read_headers(&file).unwrap(), vec![String::from("column1"), String::from("column2")]);
+    let (_, entries) = eval(Expression::Load(file.clone(), None)).unwrap();
+    assert_eq!(entries.len(), 2);
+
+    file.dialect.columns = Some(vec![String::from("id"), String::from("name")]);
+    let detected = DetectLoadColumnsOptimizer{}.optimize(Box::new(Expression::Load(file.clone(), None))).unwrap();
+    assert!(matches!(*detected, Expression::Load(_, Some(ref columns)) if *columns == HashSet::from([String::from("id"), String::from("name")])));
+
+    let rspr = Box::new(Expression::ReadSelectProjectRename(
+        file.clone(),
+        Box::new(Condition::Equal(Value::Column(String::from("id")), Value::Int(2))),
+        vec![String::from("name")],
+        vec![String::from("who")]
+    ));
+    let (columns, entries) = eval(*rspr).unwrap();
+    assert_eq!(columns["who"], 0);
+    assert_eq!(entries, vec![vec![Value::Str(String::from("Alan"))]]);
+
+    file.dialect.columns = Some(vec![String::from("id")]);
+    assert!(read_headers(&file).is_err());
+}
+
+#[test]
+fn test_dialect_to_json() {
+    let json = r#"{"operation": "rspr", "args": {"filename": "data.tsv", "delimiter": "tab", "header": false, "columns": ["a", "b"],
+        "condition": "True", "old attributes": ["a"], "new attributes": ["a"]}}"#;
+    let expression = get_expression_from_str(json);
+    assert_eq!(get_expression_from_str(&expression_to_json(&expression).unwrap()), expression);
+
+    // Default options are left out
+    let load = Expression::Load(CsvFile::new(String::from("data.csv")), None);
+    assert!(!expression_to_json(&load).unwrap().contains("delimiter"));
+
+    let invalid = get_expression_from_json(r#"{"operation": "load", "args": {"filename": "data.csv", "delimiter": ";;"}}"#);
+    assert!(invalid.is_err());
+}
@@ -1,5 +1,7 @@
+use crate::eval::*;
 use crate::optimize::*;
 use crate::parser::*;
+use crate::types::*;
 
 #[test]
 fn test_apply_projections_early() {
@@ -27,7 +29,7 @@ fn test_apply_projections_early() {
         Box::new(DetectLoadColumnsOptimizer{}),
         Box::new(ApplyProjectionsEarlyOptimizer{}),
     ]};
-    let expression = optimizer.optimize(expression);
+    let expression = optimizer.optimize(expression).unwrap();
 
     let expected = get_expression_from_str(
         r#"{
@@ -52,6 +54,39 @@ fn test_apply_projections_early() {
     assert_eq!(*expression, expected);
 }
 
+#[test]
+fn test_apply_projections_early_below_products() {
+    // The selection is asked for the columns of both sides of the product, but only projects on its own
+    let expression = Box::new(get_expression_from_str(
+        r#"
+        {"operation": "projection", "args": {
+            "attributes": ["titre", "nom"],
+            "object": {"operation": "product", "args": {
+                "object1": {"operation": "selection", "args": {
+                    "condition": {"comparator": ">", "attribute1": "responsable", "attribute2": "60"},
+                    "object": {"operation": "load", "args": { "filename": "project_spec/samples/projets.csv"}}
+                }},
+                "object2": {"operation": "load", "args": { "filename": "project_spec/samples/employes.csv"}}
+            }}
+        }}
+        "#
+    ));
+
+    let optimizer = ChainOptimizer{optimizers: vec![
+        Box::new(DetectLoadColumnsOptimizer{}),
+        Box::new(ApplyProjectionsEarlyOptimizer{}),
+    ]};
+    let optimized = optimizer.optimize(expression.clone()).unwrap();
+
+    let sorted = |expression: Box<Expression>| {
+        let (columns, entries) = eval(*expression).unwrap();
+        let mut pairs = entries.iter().map(|entry| (entry[columns["titre"]].clone(), entry[columns["nom"]].clone())).collect::<Vec<_>>();
+        pairs.sort_by_key(|pair| format!("{:?}", pair));
+        pairs
+    };
+    assert_eq!(sorted(optimized), sorted(expression));
+}
+
 #[test]
 fn test_push_down_selections() {
     let expression = Box::new(get_expression_from_str(
@@ -78,7 +113,7 @@ fn test_push_down_selections() {
         Box::new(DetectLoadColumnsOptimizer{}),
         Box::new(PushDownSelectionsOptimizer{}),
     ]};
-    let expression = optimizer.optimize(expression);
+    let expression = optimizer.optimize(expression).unwrap();
 
     let expected = get_expression_from_str(
         r#"{
@@ -131,7 +166,7 @@ fn test_push_down_selections_with_products() {
         Box::new(DetectLoadColumnsOptimizer{}),
         Box::new(PushDownSelectionsOptimizer{}),
     ]};
-    let expression = optimizer.optimize(expression);
+    let expression = optimizer.optimize(expression).unwrap();
 
     let expected = get_expression_from_str(
         r#"
@@ -178,7 +213,7 @@ fn test_read_select_project_rename_fold() {
         Box::new(DetectLoadColumnsOptimizer{}),
         Box::new(FoldComplexExpressionsOptimizer{}),
     ]};
-    let expression = optimizer.optimize(expression);
+    let expression = optimizer.optimize(expression).unwrap();
 
     let expected = get_expression_from_str(
         r#"
@@ -195,4 +230,391 @@ fn test_read_select_project_rename_fold() {
     );
 
     assert_eq!(*expression, expected);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_push_down_selections_keeps_literals() {
+    let expression = Box::new(get_expression_from_str(
+        r#"
+        {"operation": "selection", "args": {
+            "condition": {"comparator": "=", "attribute1": "truc", "attribute2": {"literal": "truc"}},
+            "object": {
+                "operation": "renaming",
+                "args": {
+                    "old attributes": ["titre"],
+                    "new attributes": ["truc"],
+                    "object": {
+                        "operation": "load",
+                        "args": { "filename": "project_spec/samples/projets.csv"}
+                    }
+                }
+            }
+        }}
+        "#
+    ));
+
+    let optimizer = ChainOptimizer{optimizers: vec![
+        Box::new(DetectLoadColumnsOptimizer{}),
+        Box::new(PushDownSelectionsOptimizer{}),
+    ]};
+    let expression = optimizer.optimize(expression).unwrap();
+
+    // Only the column is renamed, the literal is left untouched
+    let expected = get_expression_from_str(
+        r#"{
+            "operation": "renaming",
+            "args": {
+                "old attributes": ["titre"],
+                "new attributes": ["truc"],
+                "object": {
+                    "operation": "selection",
+                    "args": {
+                        "condition": {"comparator": "=", "attribute1": "titre", "attribute2": {"literal": "truc"}},
+                        "object": {
+                        "operation": "load",
+                        "args": { "filename": "project_spec/samples/projets.csv"}
+                    }
+                }
+            }
+        }}
+        "#
+    );
+
+    assert_eq!(*expression, expected);
+}
+
+#[test]
+fn test_push_down_selections_with_aggregates() {
+    let expression = Box::new(get_expression_from_str(
+        r#"
+        {"operation": "selection", "args": {
+            "condition": {"comparator": ">", "attribute1": "idp", "attribute2": "3"},
+            "object": {"operation": "selection", "args": {
+                "condition": {"comparator": ">", "attribute1": "members", "attribute2": "10"},
+                "object": {
+                    "operation": "aggregate",
+                    "args": {
+                        "group by": ["idp"],
+                        "aggregates": [{"function": "count", "attribute": "*", "name": "members"}],
+                        "object": {
+                            "operation": "load",
+                            "args": { "filename": "project_spec/samples/membres.csv"}
+                        }
+                    }
+                }
+            }}
+        }}
+        "#
+    ));
+
+    let optimizer = ChainOptimizer{optimizers: vec![
+        Box::new(DetectLoadColumnsOptimizer{}),
+        Box::new(PushDownSelectionsOptimizer{}),
+    ]};
+    let expression = optimizer.optimize(expression).unwrap();
+
+    // The selection on the group key goes below the aggregation, the other one stays above
+    let expected = get_expression_from_str(
+        r#"
+        {"operation": "selection", "args": {
+            "condition": {"comparator": ">", "attribute1": "members", "attribute2": "10"},
+            "object": {
+                "operation": "aggregate",
+                "args": {
+                    "group by": ["idp"],
+                    "aggregates": [{"function": "count", "attribute": "*", "name": "members"}],
+                    "object": {
+                        "operation": "selection",
+                        "args": {
+                            "condition": {"comparator": ">", "attribute1": "idp", "attribute2": "3"},
+                            "object": {
+                                "operation": "load",
+                                "args": { "filename": "project_spec/samples/membres.csv"}
+                            }
+                        }
+                    }
+                }
+            }
+        }}
+        "#
+    );
+
+    assert_eq!(*expression, expected);
+}
+
+#[test]
+fn test_push_down_selections_keeps_constant_conditions_above_aggregates() {
+    // Without grouping, the aggregation of an empty relation still has an entry, which False must remove
+    let expression = Box::new(Expression::Select(
+        Box::new(Expression::Aggregate(
+            Box::new(Expression::Load(CsvFile::new(String::from("project_spec/samples/projets.csv")), None)),
+            vec![],
+            vec![(AggregateFunction::Count, String::from("*"), String::from("n"))]
+        )),
+        Box::new(Condition::False)
+    ));
+
+    let optimized = PushDownSelectionsOptimizer{}.optimize(expression.clone()).unwrap();
+    assert_eq!(optimized, expression);
+    assert!(eval(*optimized).unwrap().1.is_empty());
+}
+
+#[test]
+fn test_fuse_sort_limit() {
+    let expression = Box::new(get_expression_from_str(
+        r#"
+        {"operation": "selection", "args": {
+            "condition": {"comparator": ">", "attribute1": "idp", "attribute2": "3"},
+            "object": {
+                "operation": "limit",
+                "args": {
+                    "limit": 5,
+                    "object": {
+                        "operation": "sort",
+                        "args": {
+                            "keys": [{"attribute": "responsable", "order": "desc"}],
+                            "object": {
+                                "operation": "load",
+                                "args": { "filename": "project_spec/samples/projets.csv"}
+                            }
+                        }
+                    }
+                }
+            }
+        }}
+        "#
+    ));
+
+    let optimizer = ChainOptimizer{optimizers: vec![
+        Box::new(DetectLoadColumnsOptimizer{}),
+        Box::new(PushDownSelectionsOptimizer{}),
+        Box::new(FuseSortLimitOptimizer{}),
+    ]};
+    let expression = optimizer.optimize(expression).unwrap();
+
+    // The selection must stay above the limit
+    let expected = get_expression_from_str(
+        r#"
+        {"operation": "selection", "args": {
+            "condition": {"comparator": ">", "attribute1": "idp", "attribute2": "3"},
+            "object": {
+                "operation": "topn",
+                "args": {
+                    "keys": [{"attribute": "responsable", "order": "desc"}],
+                    "limit": 5,
+                    "object": {
+                        "operation": "load",
+                        "args": { "filename": "project_spec/samples/projets.csv"}
+                    }
+                }
+            }
+        }}
+        "#
+    );
+
+    assert_eq!(*expression, expected);
+}
+
+#[test]
+fn test_remove_redundant_distinct() {
+    let distinct_of = |object: &str| Box::new(get_expression_from_str(&format!(
+        r#"{{"operation": "distinct", "args": {{"object": {{
+            "operation": "projection",
+            "args": {{
+                "attributes": ["idp"],
+                "object": {}
+            }}
+        }}}}}}"#, object
+    )));
+    let aggregate = r#"{
+        "operation": "aggregate",
+        "args": {
+            "group by": ["idp"],
+            "aggregates": [{"function": "count", "attribute": "*", "name": "members"}],
+            "object": {
+                "operation": "load",
+                "args": { "filename": "project_spec/samples/membres.csv"}
+            }
+        }
+    }"#;
+    let load = r#"{
+        "operation": "load",
+        "args": { "filename": "project_spec/samples/membres.csv"}
+    }"#;
+
+    let optimizer = ChainOptimizer{optimizers: vec![
+        Box::new(DetectLoadColumnsOptimizer{}),
+        Box::new(RemoveRedundantDistinctOptimizer{}),
+    ]};
+
+    // The projection keeps the grouping column, so entries are already distinct
+    let expression = optimizer.optimize(distinct_of(aggregate)).unwrap();
+    assert!(matches!(*expression, Expression::Project(_, _)));
+
+    // No key is known for a loaded file
+    let expression = optimizer.optimize(distinct_of(load)).unwrap();
+    assert!(matches!(*expression, Expression::Distinct(_)));
+}
+
+#[test]
+fn test_optimized_plans_round_trip_through_json() {
+    let optimizer = ChainOptimizer{optimizers: vec![
+        Box::new(UnfoldComplexExpressionsOptimizer{}),
+        Box::new(DetectLoadColumnsOptimizer{}),
+        Box::new(RemoveRedundantDistinctOptimizer{}),
+        Box::new(PushDownSelectionsOptimizer{}),
+        Box::new(ApplyProjectionsEarlyOptimizer{}),
+        Box::new(FoldComplexExpressionsOptimizer{}),
+        Box::new(FuseSortLimitOptimizer{})
+    ]};
+
+    for sample in ["expr_samples/test01.json", "expr_samples/conditions.json", "expr_samples/top_projects.sql", "expr_samples/aggregate.sql"] {
+        let path = Some(String::from(sample));
+        let expression = optimizer.optimize(Box::new(get_expression_from(path.clone(), QueryLanguage::from_path(&path)).unwrap())).unwrap();
+
+        let json = expression_to_json(&expression).unwrap();
+        assert_eq!(get_expression_from_str(&json), *expression, "{} does not round trip", sample);
+    }
+
+    // Constant conditions and set unions are written back as in the grammar
+    let json = r#"{"operation": "union", "args": {"columns": "by position",
+        "object1": {"operation": "selection", "args": {"condition": "False", "object": {"operation": "load", "args": {"filename": "a.csv"}}}},
+        "object2": {"operation": "rspr", "args": {"filename": "b.csv", "condition": {"logical": "not", "condition": "True"},
+            "old attributes": ["x"], "new attributes": ["y"]}}}}"#;
+    let expression = get_expression_from_str(json);
+    assert!(matches!(expression, Expression::Distinct(_)));
+    assert_eq!(get_expression_from_str(&expression_to_json(&expression).unwrap()), expression);
+}
+
+#[test]
+fn test_reorder_joins() {
+    // (departements x membres) x employes, with conditions linking departements and membres to employes only
+    let expression = Box::new(get_expression_from_str(
+        r#"
+        {"operation": "selection", "args": {
+            "condition": {"logical": "and",
+                "condition1": {"comparator": "=", "attribute1": "idd", "attribute2": "dpt"},
+                "condition2": {"comparator": "=", "attribute1": "membre", "attribute2": "ide"}},
+            "object": {"operation": "product", "args": {
+                "object1": {"operation": "product", "args": {
+                    "object1": {"operation": "load", "args": { "filename": "project_spec/samples/departements.csv"}},
+                    "object2": {"operation": "renaming", "args": {
+                        "old attributes": ["ide"], "new attributes": ["membre"],
+                        "object": {"operation": "load", "args": { "filename": "project_spec/samples/membres.csv"}}
+                    }}
+                }},
+                "object2": {"operation": "renaming", "args": {
+                    "old attributes": ["nom"], "new attributes": ["employe"],
+                    "object": {"operation": "load", "args": { "filename": "project_spec/samples/employes.csv"}}
+                }}
+            }}
+        }}
+        "#
+    ));
+
+    let optimizer = ChainOptimizer{optimizers: vec![
+        Box::new(DetectLoadColumnsOptimizer{}),
+        Box::new(ReorderJoinsOptimizer{}),
+    ]};
+    let optimized = optimizer.optimize(expression.clone()).unwrap();
+
+    // Every product is directly under the conditions linking its sides
+    fn has_cartesian_product(expression: &Expression, under_selection: bool) -> bool {
+        match expression {
+            Expression::Product(expr1, expr2) => !under_selection || has_cartesian_product(expr1, false) || has_cartesian_product(expr2, false),
+            Expression::Select(expression_from, _) => has_cartesian_product(expression_from, true),
+            Expression::Project(expression_from, _) | Expression::Rename(expression_from, _, _) => has_cartesian_product(expression_from, false),
+            _ => false
+        }
+    }
+    assert!(has_cartesian_product(&expression, false));
+    assert!(!has_cartesian_product(&optimized, false));
+
+    // The result, columns included, does not change
+    let (columns, mut entries) = eval(*expression).unwrap();
+    let (optimized_columns, mut optimized_entries) = eval(*optimized).unwrap();
+    assert_eq!(optimized_columns, columns);
+    entries.sort_by_cached_key(|entry| format!("{:?}", entry));
+    optimized_entries.sort_by_cached_key(|entry| format!("{:?}", entry));
+    assert_eq!(optimized_entries, entries);
+}
+
+#[test]
+fn test_infer_predicates() {
+    let expression = Box::new(get_expression_from_str(
+        r#"
+        {"operation": "selection", "args": {
+            "condition": {"logical": "and",
+                "condition1": {"comparator": "=", "attribute1": "ide", "attribute2": "responsable"},
+                "condition2": {"comparator": "=", "attribute1": "ide", "attribute2": "38"}},
+            "object": {"operation": "product", "args": {
+                "object1": {"operation": "load", "args": { "filename": "project_spec/samples/employes.csv"}},
+                "object2": {"operation": "load", "args": { "filename": "project_spec/samples/projets.csv"}}
+            }}
+        }}
+        "#
+    ));
+
+    let optimizer = ChainOptimizer{optimizers: vec![
+        Box::new(DetectLoadColumnsOptimizer{}),
+        Box::new(InferPredicatesOptimizer{}),
+        Box::new(PushDownSelectionsOptimizer{}),
+    ]};
+    let optimized = optimizer.optimize(expression.clone()).unwrap();
+
+    // Both sides of the product are filtered on the constant
+    let filtered = |filename: &str, column: &str| Box::new(Expression::Select(
+        Box::new(Expression::Load(CsvFile::new(format!("project_spec/samples/{}", filename)), None)),
+        Box::new(Condition::Equal(Value::Column(String::from(column)), Value::Int(38)))
+    ));
+    let expected = Expression::Select(
+        Box::new(Expression::Product(filtered("employes.csv", "ide"), filtered("projets.csv", "responsable"))),
+        Box::new(Condition::Equal(Value::Column(String::from("ide")), Value::Column(String::from("responsable"))))
+    );
+    assert_eq!(*optimized, expected);
+
+    let (columns, mut entries) = eval(*expression).unwrap();
+    let (optimized_columns, mut optimized_entries) = eval(*optimized).unwrap();
+    assert!(!entries.is_empty());
+    assert_eq!(optimized_columns, columns);
+    entries.sort_by_cached_key(|entry| format!("{:?}", entry));
+    optimized_entries.sort_by_cached_key(|entry| format!("{:?}", entry));
+    assert_eq!(optimized_entries, entries);
+}
+
+#[test]
+fn test_renaming_keeps_join_sides_apart() {
+    // departements.nom is renamed, employes.nom is the one output
+    let expression = Box::new(crate::sql::get_expression_from_sql(
+        "SELECT titre, nom FROM project_spec/samples/projets.csv \
+        JOIN project_spec/samples/employes.csv ON responsable = ide \
+        JOIN (SELECT idd, nom AS dnom FROM project_spec/samples/departements.csv) ON dpt = idd"
+    ).unwrap());
+
+    let optimizer = ChainOptimizer{optimizers: vec![
+        Box::new(UnfoldComplexExpressionsOptimizer{}),
+        Box::new(DetectLoadColumnsOptimizer{}),
+        Box::new(InferPredicatesOptimizer{}),
+        Box::new(SimplifyConditionsOptimizer{}),
+        Box::new(ReorderJoinsOptimizer{}),
+        Box::new(RemoveRedundantDistinctOptimizer{}),
+        Box::new(PushDownSelectionsOptimizer{}),
+        Box::new(ApplyProjectionsEarlyOptimizer{}),
+        Box::new(FoldComplexExpressionsOptimizer{}),
+        Box::new(ChooseJoinSidesOptimizer{}),
+        Box::new(FuseSortLimitOptimizer{})
+    ]};
+    let optimized = optimizer.optimize(expression.clone()).unwrap();
+    assert_eq!(crate::validate::columns_of(&optimized).unwrap().len(), 2);
+
+    // The same titles and employee names, whatever the side each join keeps in memory
+    let names = |expression: Box<Expression>| {
+        let (columns, entries) = eval(*expression).unwrap();
+        let mut names = entries.iter().map(|entry| (entry[columns["titre"]].clone(), entry[columns["nom"]].clone())).collect::<Vec<_>>();
+        names.sort_by_cached_key(|name| format!("{:?}", name));
+        names
+    };
+    let expected = names(expression);
+    assert!(!expected.is_empty());
+    assert_eq!(names(optimized), expected);
+}
@@ -0,0 +1,131 @@
+use crate::output::*;
+use crate::types::*;
+use crate::eval::Stream;
+use crate::error::*;
+use csv::{QuoteStyle, Terminator};
+use std::collections::HashMap;
+
+fn table() -> Table {
+    // Columns listed in another order than their positions
+    let columns = HashMap::from([(String::from("name"), 1), (String::from("id"), 0), (String::from("city"), 2)]);
+    let entries = vec![
+        vec![Value::Int(1), Value::Str(String::from("Ada")), Value::Str(String::from("Paris; France"))],
+        vec![Value::Int(2), Value::Str(String::from("Alan")), Value::Str(String::from("Wilmslow"))]
+    ];
+
+    (columns, entries)
+}
+
+fn stream((columns, entries): Table) -> Stream {
+    (columns, Box::new(entries.into_iter().map(Ok)))
+}
+
+fn written(dialect: &CsvDialect) -> String {
+    let mut output = Vec::new();
+    write_csv(stream(table()), &mut output, "<test>", dialect).unwrap();
+
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn test_header_follows_positions() {
+    assert_eq!(written(&CsvDialect::default()), "id,name,city\r\n1,Ada,Paris; France\r\n2,Alan,Wilmslow\r\n");
+}
+
+#[test]
+fn test_header_with_unnamed_positions() {
+    // A column projected twice keeps a single name for two positions
+    let columns = HashMap::from([(String::from("id"), 0), (String::from("name"), 2)]);
+
+    assert_eq!(ordered_columns(&columns), vec!["id", "", "name"]);
+}
+
+#[test]
+fn test_dialect() {
+    let dialect = CsvDialect { delimiter: b';', quote_style: QuoteStyle::NonNumeric, terminator: Terminator::Any(b'\n'), header: false };
+    assert_eq!(written(&dialect), "1;\"Ada\";\"Paris; France\"\n2;\"Alan\";\"Wilmslow\"\n");
+
+    assert_eq!(parse_delimiter("tab").unwrap(), b'\t');
+    assert_eq!(parse_delimiter("|").unwrap(), b'|');
+    assert!(parse_delimiter("::").is_err());
+}
+
+fn formatted(format: OutputFormat) -> String {
+    let mut output = Vec::new();
+    write_output(stream(table()), &mut output, "<test>", format, &CsvDialect::default()).unwrap();
+
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn test_output_formats() {
+    // Integer columns are aligned to the right
+    assert_eq!(formatted(OutputFormat::Table), "id | name | city\n---+------+--------------\n 1 | Ada  | Paris; France\n 2 | Alan | Wilmslow\n");
+    assert_eq!(formatted(OutputFormat::Markdown), "| id | name | city |\n| ---: | --- | --- |\n| 1 | Ada | Paris; France |\n| 2 | Alan | Wilmslow |\n");
+    assert_eq!(formatted(OutputFormat::Json), "[\n  {\"id\": 1, \"name\": \"Ada\", \"city\": \"Paris; France\"},\n  {\"id\": 2, \"name\": \"Alan\", \"city\": \"Wilmslow\"}\n]\n");
+    assert_eq!(formatted(OutputFormat::JsonLines), "{\"id\":1,\"name\":\"Ada\",\"city\":\"Paris; France\"}\n{\"id\":2,\"name\":\"Alan\",\"city\":\"Wilmslow\"}\n");
+    assert_eq!(formatted(OutputFormat::Tsv), "id\tname\tcity\r\n1\tAda\tParis; France\r\n2\tAlan\tWilmslow\r\n");
+}
+
+#[test]
+fn test_entries_are_written_as_they_are_pulled() {
+    let columns = HashMap::from([(String::from("id"), 0)]);
+    let entries = vec![Ok(vec![Value::Int(1)]), Err(EngineError::Type(String::from("second entry")))];
+    let mut output = Vec::new();
+
+    // The first entry is written before the error of the second one is met
+    let result = write_csv((columns, Box::new(entries.into_iter())), &mut output, "<test>", &CsvDialect::default());
+    assert!(matches!(result, Err(EngineError::Type(_))));
+    assert!(String::from_utf8(output).unwrap().starts_with("id\r\n1\r\n"));
+}
+
+#[test]
+fn test_empty_json_output() {
+    let mut output = Vec::new();
+    write_output(stream((HashMap::from([(String::from("id"), 0)]), vec![])), &mut output, "<test>", OutputFormat::Json, &CsvDialect::default()).unwrap();
+
+    assert_eq!(String::from_utf8(output).unwrap(), "[]\n");
+}
+
+#[test]
+fn test_format_from_path() {
+    let format = |filename: &str| OutputFormat::from_path(&Some(String::from(filename)));
+
+    assert_eq!(OutputFormat::from_path(&None), OutputFormat::Table);
+    assert_eq!(format("result.csv"), OutputFormat::Csv);
+    assert_eq!(format("result.JSON"), OutputFormat::Json);
+    assert_eq!(format("result.jsonl"), OutputFormat::JsonLines);
+    assert_eq!(format("result.md"), OutputFormat::Markdown);
+    assert_eq!(format("result"), OutputFormat::Csv);
+}
+
+#[test]
+fn test_typed_values_output() {
+    let columns = HashMap::from([(String::from("price"), 0), (String::from("paid"), 1), (String::from("due"), 2)]);
+    let entries = vec![vec![Value::Float(12.0), Value::Bool(true), Value::Date(chrono::NaiveDate::from_ymd_opt(2021, 3, 14).unwrap())]];
+    let written = |format: OutputFormat| {
+        let mut output = Vec::new();
+        write_output(stream((columns.clone(), entries.clone())), &mut output, "<test>", format, &CsvDialect::default()).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    // Floats keep their decimal point, to be read back as floats
+    assert_eq!(written(OutputFormat::Csv), "price,paid,due\r\n12.0,true,2021-03-14\r\n");
+    assert_eq!(written(OutputFormat::JsonLines), "{\"price\":12.0,\"paid\":true,\"due\":\"2021-03-14\"}\n");
+}
+
+#[test]
+fn test_null_output() {
+    let columns = HashMap::from([(String::from("id"), 0), (String::from("score"), 1)]);
+    let entries = vec![vec![Value::Int(1), Value::Null], vec![Value::Int(2), Value::Int(30)]];
+    let written = |format: OutputFormat| {
+        let mut output = Vec::new();
+        write_output(stream((columns.clone(), entries.clone())), &mut output, "<test>", format, &CsvDialect::default()).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    // An empty field in CSV, as they are read back as NULL
+    assert_eq!(written(OutputFormat::Csv), "id,score\r\n1,\r\n2,30\r\n");
+    assert_eq!(written(OutputFormat::JsonLines), "{\"id\":1,\"score\":null}\n{\"id\":2,\"score\":30}\n");
+    assert_eq!(written(OutputFormat::Table), "id | score\n---+------\n 1 |  NULL\n 2 |    30\n");
+}
@@ -0,0 +1,107 @@
+use crate::eval::*;
+use crate::optimize::*;
+use crate::parser::*;
+use crate::simplify::*;
+use crate::types::*;
+use crate::tests::helpers::*;
+
+fn and(c1: Condition, c2: Condition) -> Condition {
+    Condition::And(Box::new(c1), Box::new(c2))
+}
+
+fn or(c1: Condition, c2: Condition) -> Condition {
+    Condition::Or(Box::new(c1), Box::new(c2))
+}
+
+fn not(c: Condition) -> Condition {
+    Condition::Not(Box::new(c))
+}
+
+fn simplified(condition: Condition) -> Condition {
+    *simplify(condition)
+}
+
+#[test]
+fn test_fold_constants() {
+    let x_is_one = Condition::Equal(column("x"), Value::Int(1));
+
+    assert_eq!(simplified(Condition::Equal(Value::Int(5), Value::Int(5))), Condition::True);
+    assert_eq!(simplified(Condition::Less(Value::Str(String::from("b")), Value::Str(String::from("a")))), Condition::False);
+    // Values of different types are never equal
+    assert_eq!(simplified(Condition::Equal(Value::Int(5), Value::Str(String::from("5")))), Condition::False);
+    // x <= x only holds when x is not NULL
+    assert_eq!(simplified(Condition::LessEq(column("x"), column("x"))), not(Condition::IsNull(column("x"))));
+    assert_eq!(simplified(not(Condition::Less(column("x"), column("x")))), not(Condition::IsNull(column("x"))));
+    // Comparisons with NULL are never true, nor are their negations
+    assert_eq!(simplified(not(Condition::Equal(column("x"), Value::Null))), Condition::False);
+
+    assert_eq!(simplified(and(Condition::True, x_is_one.clone())), x_is_one);
+    assert_eq!(simplified(or(Condition::False, x_is_one.clone())), x_is_one);
+    assert_eq!(simplified(and(x_is_one.clone(), x_is_one.clone())), x_is_one);
+}
+
+#[test]
+fn test_push_down_negations() {
+    let x_is_one = Condition::Equal(column("x"), Value::Int(1));
+    let y_below_two = Condition::Less(column("y"), Value::Int(2));
+
+    assert_eq!(simplified(not(not(y_below_two.clone()))), y_below_two);
+    assert_eq!(
+        simplified(not(and(x_is_one, y_below_two.clone()))),
+        or(Condition::NotEqual(column("x"), Value::Int(1)), not(y_below_two))
+    );
+    // not (x < 1) also holds for strings, so it is not x >= 1
+    assert_eq!(simplified(not(Condition::Less(column("x"), Value::Int(1)))), not(Condition::Less(column("x"), Value::Int(1))));
+}
+
+#[test]
+fn test_contradictions_and_tautologies() {
+    let x = |comparison: fn(Value, Value) -> Condition, i: i64| comparison(column("x"), Value::Int(i));
+
+    assert_eq!(simplified(and(x(Condition::Equal, 1), x(Condition::Equal, 2))), Condition::False);
+    assert_eq!(simplified(and(x(Condition::Less, 3), Condition::More(Value::Int(5), column("y")))), and(x(Condition::Less, 3), Condition::More(Value::Int(5), column("y"))));
+    assert_eq!(simplified(and(x(Condition::Less, 3), Condition::Less(Value::Int(5), column("x")))), Condition::False);
+    // A float may lie strictly between 1 and 2
+    assert_eq!(simplified(and(x(Condition::More, 1), x(Condition::Less, 2))), and(x(Condition::More, 1), x(Condition::Less, 2)));
+    assert_eq!(simplified(and(x(Condition::MoreEq, 1), Condition::Less(column("x"), Value::Float(1.0)))), Condition::False);
+    assert_eq!(simplified(and(and(x(Condition::MoreEq, 1), x(Condition::LessEq, 1)), x(Condition::NotEqual, 1))), Condition::False);
+    // A value cannot be both an integer and a string
+    assert_eq!(simplified(and(x(Condition::Equal, 1), Condition::Less(column("x"), Value::Str(String::from("z"))))), Condition::False);
+
+    // Unknown when x is NULL, so not always true
+    assert_eq!(simplified(or(x(Condition::Equal, 1), x(Condition::NotEqual, 1))), or(x(Condition::Equal, 1), x(Condition::NotEqual, 1)));
+    let x_is_null = Condition::IsNull(column("x"));
+    assert_eq!(simplified(or(x_is_null.clone(), not(x_is_null.clone()))), Condition::True);
+    assert_eq!(simplified(and(x_is_null, x(Condition::More, 1))), Condition::False);
+    let range = and(x(Condition::MoreEq, 1), x(Condition::LessEq, 3));
+    assert_eq!(simplified(range.clone()), range);
+}
+
+#[test]
+fn test_always_false_selection() {
+    let expression = Box::new(get_expression_from_str(
+        r#"
+        {"operation": "projection", "args": {
+            "attributes": ["titre", "idp"],
+            "object": {"operation": "selection", "args": {
+                "condition": {"logical": "and",
+                    "condition1": {"comparator": "=", "attribute1": "idp", "attribute2": "1"},
+                    "condition2": {"comparator": "=", "attribute1": "idp", "attribute2": "2"}},
+                "object": {"operation": "load", "args": { "filename": "project_spec/samples/projets.csv"}}
+            }}
+        }}
+        "#
+    ));
+
+    let optimizer = ChainOptimizer{optimizers: vec![
+        Box::new(DetectLoadColumnsOptimizer{}),
+        Box::new(SimplifyConditionsOptimizer{}),
+    ]};
+    let optimized = optimizer.optimize(expression).unwrap();
+    assert_eq!(*optimized, Expression::Empty(vec![String::from("titre"), String::from("idp")]));
+
+    let (columns, entries) = eval(*optimized).unwrap();
+    assert_eq!(columns.len(), 2);
+    assert_eq!(columns["titre"], 0);
+    assert!(entries.is_empty());
+}
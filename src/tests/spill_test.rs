@@ -0,0 +1,94 @@
+use crate::eval::*;
+use crate::spill::*;
+use crate::types::*;
+use crate::tests::helpers::*;
+
+/**
+ * Evaluate an expression without memory limit, then with a limit small enough to spill, returning both results.
+ */
+fn eval_with_and_without_spilling(expression: Box<Expression>) -> (Table, Table) {
+    set_memory_limit(None);
+    let in_memory = eval(*expression.clone()).unwrap();
+
+    set_memory_limit(Some(1024));
+    let spilled = eval(*expression);
+    set_memory_limit(None);
+
+    (in_memory, spilled.unwrap())
+}
+
+fn sorted(entries: Vec<Entry>) -> Vec<Vec<String>> {
+    let mut entries = entries.into_iter().map(|entry| entry.iter().map(|value| format!("{:?}", value)).collect::<Vec<_>>()).collect::<Vec<_>>();
+    entries.sort();
+    entries
+}
+
+#[test]
+fn test_spilled_joins_match_in_memory_ones() {
+    let product = Box::new(Expression::Product(load("projets.csv"), load("membres.csv")));
+    let (in_memory, spilled) = eval_with_and_without_spilling(product);
+    assert_eq!(spilled.0, in_memory.0);
+    assert_eq!(in_memory.1.len(), 20 * 223);
+    assert_eq!(sorted(spilled.1), sorted(in_memory.1));
+
+    // The hash join is partitioned on disk
+    let join = Box::new(Expression::JoinProjectRename(
+        Box::new(Expression::Rename(load("projets.csv"), strings(&["idp"]), strings(&["pid"]))),
+        load("membres.csv"),
+        Box::new(Condition::Equal(column("pid"), column("idp"))),
+        strings(&["titre", "ide"]),
+        strings(&["titre", "member"])
+    ));
+    let (in_memory, spilled) = eval_with_and_without_spilling(join);
+    assert_eq!(spilled.0, in_memory.0);
+    assert_eq!(in_memory.1.len(), 223);
+    assert_eq!(sorted(spilled.1), sorted(in_memory.1));
+
+    let minus = Box::new(Expression::Except(
+        load("membres.csv"),
+        Box::new(Expression::Select(load("membres.csv"), Box::new(Condition::Less(column("idp"), Value::Int(10)))))
+    ));
+    let (in_memory, spilled) = eval_with_and_without_spilling(minus);
+    assert!(!in_memory.1.is_empty());
+    assert_eq!(sorted(spilled.1), sorted(in_memory.1));
+}
+
+#[test]
+fn test_external_sort_is_stable() {
+    let sort = Box::new(Expression::Sort(load("membres.csv"), vec![(String::from("ide"), SortOrder::Desc)]));
+    let (in_memory, spilled) = eval_with_and_without_spilling(sort);
+
+    // Equal entries keep the order of the file, as in memory
+    assert_eq!(spilled.1, in_memory.1);
+}
+
+#[test]
+fn test_spilled_floats_are_read_back_exactly() {
+    // Floats spread over the whole range, some of which are parsed back to a neighbouring float by a fast parser
+    let mut bits = 0x9E3779B97F4A7C15u64;
+    let entries = (0..10000).filter_map(|_| {
+        bits ^= bits << 13;
+        bits ^= bits >> 7;
+        bits ^= bits << 17;
+        Some(f64::from_bits(bits)).filter(|x| x.is_finite()).map(|x| vec![Value::Float(x)])
+    }).collect::<Vec<_>>();
+
+    let spilled = SpillFile::from_rows(entries.clone().into_iter().map(Ok)).unwrap().into_rows().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(spilled.len(), entries.len());
+    for (entry, read) in entries.iter().zip(&spilled) {
+        match (&entry[0], &read[0]) {
+            (Value::Float(x), Value::Float(y)) => assert_eq!(x.to_bits(), y.to_bits()),
+            _ => panic!("floats expected")
+        }
+    }
+}
+
+#[test]
+fn test_parse_memory_limit() {
+    assert_eq!(parse_memory_limit("4096").unwrap(), 4096);
+    assert_eq!(parse_memory_limit("512K").unwrap(), 512 << 10);
+    assert_eq!(parse_memory_limit("64m").unwrap(), 64 << 20);
+    assert_eq!(parse_memory_limit("2G").unwrap(), 2 << 30);
+    assert!(parse_memory_limit("0").is_err());
+    assert!(parse_memory_limit("lots").is_err());
+}
@@ -0,0 +1,176 @@
+use crate::eval::*;
+use crate::optimize::*;
+use crate::parser::*;
+use crate::sql::*;
+use crate::error::*;
+use crate::types::*;
+
+#[test]
+fn test_sql_select_project_rename() {
+    let expression = get_expression_from_sql(
+        "SELECT idp AS truc, responsable FROM project_spec/samples/projets.csv WHERE idp < 5;"
+    ).unwrap();
+
+    let expected = get_expression_from_str(
+        r#"{
+            "operation": "renaming",
+            "args": {
+                "old attributes": ["idp"],
+                "new attributes": ["truc"],
+                "object": {
+                    "operation": "projection",
+                    "args": {
+                        "attributes": ["idp", "responsable"],
+                        "object": {
+                            "operation": "selection",
+                            "args": {
+                                "condition": {"comparator": "<", "attribute1": "idp", "attribute2": "5"},
+                                "object": {
+                                    "operation": "load",
+                                    "args": { "filename": "project_spec/samples/projets.csv"}
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }"#
+    );
+
+    assert_eq!(expression, expected);
+}
+
+#[test]
+fn test_sql_join_is_optimized_and_evaluated() {
+    let expression = Box::new(get_expression_from_sql(
+        "select titre from 'project_spec/samples/projets.csv' \
+         join project_spec/samples/employes.csv on ide = responsable \
+         where nom = 'Bruno Shepherd'"
+    ).unwrap());
+
+    let optimizer = ChainOptimizer{optimizers: vec![
+        Box::new(UnfoldComplexExpressionsOptimizer{}),
+        Box::new(DetectLoadColumnsOptimizer{}),
+        Box::new(PushDownSelectionsOptimizer{}),
+        Box::new(ApplyProjectionsEarlyOptimizer{}),
+        Box::new(FoldComplexExpressionsOptimizer{})
+    ]};
+    let (columns, entries) = eval(*optimizer.optimize(expression).unwrap()).unwrap();
+
+    assert_eq!(columns.keys().collect::<Vec<_>>(), vec!["titre"]);
+    assert_eq!(entries.len(), 1);
+}
+
+#[test]
+fn test_sql_errors_are_located() {
+    match get_expression_from_sql("SELECT idp\nFROM projets.csv\nWHERE idp ; 3") {
+        Err(EngineError::Parse { location, message }) => {
+            assert_eq!(location, "line 3, column 11");
+            assert!(message.contains("expected a comparator"));
+        },
+        _ => panic!("expected a parse error")
+    }
+}
+
+#[test]
+fn test_sql_typed_literals() {
+    let expression = get_expression_from_sql(
+        "SELECT id FROM invoices.csv WHERE price >= 9.5 AND paid = TRUE AND due < DATE '2021-06-01'"
+    ).unwrap();
+
+    let expected = get_expression_from_str(
+        r#"{"operation": "projection", "args": {"attributes": ["id"], "object": {"operation": "selection", "args": {
+            "condition": {"logical": "and",
+                "condition1": {"logical": "and",
+                    "condition1": {"comparator": ">=", "attribute1": "price", "attribute2": "9.5"},
+                    "condition2": {"comparator": "=", "attribute1": "paid", "attribute2": {"literal": true}}},
+                "condition2": {"comparator": "<", "attribute1": "due", "attribute2": {"date": "2021-06-01"}}},
+            "object": {"operation": "load", "args": {"filename": "invoices.csv"}}
+        }}}}"#
+    );
+    assert_eq!(expression, expected);
+    // Typed literals survive a round trip through JSON
+    assert_eq!(get_expression_from_str(&expression_to_json(&expression).unwrap()), expression);
+
+    assert!(get_expression_from_sql("SELECT id FROM invoices.csv WHERE due < DATE '2021-02-30'").is_err());
+}
+
+#[test]
+fn test_sql_null_tests() {
+    let expression = get_expression_from_sql("SELECT id FROM scores.csv WHERE score IS NOT NULL OR bonus IS NULL").unwrap();
+
+    let expected = get_expression_from_str(
+        r#"{"operation": "projection", "args": {"attributes": ["id"], "object": {"operation": "selection", "args": {
+            "condition": {"logical": "or",
+                "condition1": {"comparator": "is not null", "attribute": "score"},
+                "condition2": {"comparator": "is null", "attribute": "bonus"}},
+            "object": {"operation": "load", "args": {"filename": "scores.csv"}}
+        }}}}"#
+    );
+    assert_eq!(expression, expected);
+    assert_eq!(get_expression_from_str(&expression_to_json(&expression).unwrap()), expression);
+}
+
+#[test]
+fn test_sql_table_aliases() {
+    let expression = Box::new(get_expression_from_sql(
+        "SELECT e.nom, d.nom AS departement FROM project_spec/samples/employes.csv AS e \
+         JOIN project_spec/samples/departements.csv d ON dpt = idd WHERE d.nom = 'Direction'"
+    ).unwrap());
+
+    let optimizer = ChainOptimizer{optimizers: vec![
+        Box::new(UnfoldComplexExpressionsOptimizer{}),
+        Box::new(DetectLoadColumnsOptimizer{}),
+        Box::new(PushDownSelectionsOptimizer{}),
+        Box::new(ApplyProjectionsEarlyOptimizer{}),
+        Box::new(FoldComplexExpressionsOptimizer{})
+    ]};
+    let (columns, entries) = eval(*optimizer.optimize(expression).unwrap()).unwrap();
+
+    assert_eq!(columns.len(), 2);
+    assert!(entries.iter().all(|entry| entry[columns["departement"]] == Value::Str(String::from("Direction"))));
+    assert_eq!(entries.len(), 14);
+
+    // Without aliases, the column must name a single table of the FROM clause
+    match get_expression_from_sql(
+        "SELECT nom FROM project_spec/samples/employes.csv e JOIN project_spec/samples/departements.csv d ON dpt = idd"
+    ) {
+        Err(EngineError::Parse { location, message }) => {
+            assert_eq!(location, "line 1, column 8");
+            assert!(message.contains("ambiguous column nom"));
+        },
+        _ => panic!("expected a parse error")
+    }
+}
+
+#[test]
+fn test_sql_set_operations() {
+    let union = get_expression_from_sql("SELECT idp FROM projets.csv UNION ALL SELECT ide FROM membres.csv").unwrap();
+    assert!(matches!(union, Expression::Union(_, _, ColumnMatching::ByPosition)));
+
+    // EXCEPT returns a set, like in SQL
+    let (_, entries) = eval(get_expression_from_sql(
+        "SELECT idp FROM project_spec/samples/membres.csv EXCEPT SELECT idp FROM project_spec/samples/projets.csv WHERE idp > 1"
+    ).unwrap()).unwrap();
+    assert_eq!(entries, vec![vec![Value::Int(1)]]);
+}
+
+#[test]
+fn test_sql_order_by_unselected_columns() {
+    let expression = get_expression_from_sql("SELECT titre FROM project_spec/samples/projets.csv ORDER BY idp DESC LIMIT 2").unwrap();
+    let (columns, entries) = eval(expression).unwrap();
+    assert_eq!(columns.keys().collect::<Vec<_>>(), vec!["titre"]);
+    assert_eq!(entries.len(), 2);
+
+    // Output aliases stand for the columns they rename
+    let expression = get_expression_from_sql("SELECT idp AS id FROM projets.csv ORDER BY id").unwrap();
+    let expected = Expression::Rename(
+        Box::new(Expression::Project(
+            Box::new(Expression::Sort(Box::new(Expression::Load(CsvFile::new(String::from("projets.csv")), None)), vec![(String::from("idp"), SortOrder::Asc)])),
+            vec![String::from("idp")]
+        )),
+        vec![String::from("idp")],
+        vec![String::from("id")]
+    );
+    assert_eq!(expression, expected);
+}
@@ -0,0 +1,27 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static FILES: AtomicUsize = AtomicUsize::new(0);
+
+/**
+ * A file written in the temporary directory, removed when dropped, even when the test fails.
+ */
+pub struct TemporaryFile {
+    pub path: String
+}
+
+impl TemporaryFile {
+    pub fn new(name: &str, content: &str) -> TemporaryFile {
+        // Tests run in parallel, so each file gets its own number
+        let number = FILES.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("dbdm-test-{}-{}-{}", std::process::id(), number, name));
+        std::fs::write(&path, content).unwrap();
+
+        TemporaryFile { path: path.to_string_lossy().into_owned() }
+    }
+}
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::validate::*;
+use crate::parser::*;
+use crate::error::*;
+
+#[test]
+fn test_validate_accepts_samples() {
+    for sample in ["expr_samples/test00.json", "expr_samples/test01.json", "expr_samples/test02.json", "expr_samples/top_projects.sql"] {
+        let expression = Box::new(get_expression_from(Some(String::from(sample)), QueryLanguage::from_path(&Some(String::from(sample)))).unwrap());
+
+        assert!(validate(&expression).is_ok(), "{} should be valid", sample);
+    }
+}
+
+#[test]
+fn test_validate_reports_all_problems() {
+    let expression = Box::new(get_expression_from_str(
+        r#"
+        {"operation": "product", "args": {
+            "object1": {
+                "operation": "renaming",
+                "args": {
+                    "old attributes": ["idp", "budget"], "new attributes": ["id"],
+                    "object": {"operation": "load", "args": { "filename": "project_spec/samples/projets.csv"}}
+                }
+            },
+            "object2": {
+                "operation": "projection",
+                "args": {
+                    "attributes": ["idp", "ide", "role"],
+                    "object": {"operation": "load", "args": { "filename": "project_spec/samples/membres.csv"}}
+                }
+            }
+        }}
+        "#
+    ));
+
+    let errors = match validate(&expression) {
+        Err(EngineError::Invalid(errors)) => errors,
+        _ => panic!("the query should be invalid")
+    };
+
+    // The renaming is rejected, its unknown column in the projection is still reported
+    assert_eq!(errors.len(), 2);
+    assert!(matches!(&errors[0], EngineError::SchemaMismatch(message) if message.contains("renaming 2 columns into 1 names")));
+    assert!(matches!(&errors[1], EngineError::UnknownColumn { column, .. } if column == "role"));
+}
+
+#[test]
+fn test_validate_detects_duplicate_columns() {
+    let expression = Box::new(get_expression_from_str(
+        r#"
+        {"operation": "product", "args": {
+            "object1": {"operation": "load", "args": { "filename": "project_spec/samples/projets.csv"}},
+            "object2": {"operation": "load", "args": { "filename": "project_spec/samples/membres.csv"}}
+        }}
+        "#
+    ));
+
+    match validate(&expression) {
+        Err(EngineError::Invalid(errors)) => {
+            assert_eq!(errors.len(), 1);
+            assert!(errors[0].to_string().contains("duplicate output columns [\"idp\"]"));
+        },
+        _ => panic!("the product should be rejected")
+    }
+}
+
+#[test]
+fn test_validate_detects_duplicate_projection() {
+    let expression = Box::new(crate::sql::get_expression_from_sql("SELECT idp, idp FROM project_spec/samples/projets.csv").unwrap());
+
+    match validate(&expression) {
+        Err(EngineError::Invalid(errors)) => {
+            assert_eq!(errors.len(), 1);
+            assert!(errors[0].to_string().contains("projection: duplicate output columns [\"idp\"]"));
+        },
+        _ => panic!("the projection should be rejected")
+    }
+}
@@ -1,21 +1,34 @@
 use crate::parser::*;
 
-use serde_derive::Deserialize;
+use chrono::NaiveDate;
+use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 
 
-#[derive(Debug, Clone, Deserialize, Hash)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum Value {
     Int(i64),
+    Float(f64), // always finite
+    Bool(bool),
+    Date(NaiveDate),
     Str(String),
+    Null, // a missing value
     Column(String)
 }
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Value::Int(i), Value::Int(j)) => i==j,
+            (Value::Float(x), Value::Float(y)) => x==y,
+            (Value::Int(i), Value::Float(x)) | (Value::Float(x), Value::Int(i)) => float_as_int(*x) == Some(*i),
+            (Value::Bool(a), Value::Bool(b)) => a==b,
+            (Value::Date(d), Value::Date(e)) => d==e,
             (Value::Str(s), Value::Str(t)) => s==t,
+            // Deux NULL sont égaux pour regrouper les entrées (distinct, group by, minus), pas dans les conditions
+            (Value::Null, Value::Null) => true,
             (Value::Column(s), Value::Column(t)) => s==t,
             (_, _) => false
         }
@@ -23,6 +36,58 @@ impl PartialEq for Value {
 }
 impl Eq for Value {}
 
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Value::Int(i) => { 0.hash(state); i.hash(state) },
+            // Un flottant entier est égal à l'entier correspondant, il doit donc avoir le même hash
+            Value::Float(x) => match float_as_int(*x) {
+                Some(i) => Value::Int(i).hash(state),
+                None => { 1.hash(state); x.to_bits().hash(state) }
+            },
+            Value::Bool(b) => { 2.hash(state); b.hash(state) },
+            Value::Date(d) => { 3.hash(state); d.hash(state) },
+            Value::Str(s) => { 4.hash(state); s.hash(state) },
+            Value::Null => 5.hash(state),
+            Value::Column(c) => { 6.hash(state); c.hash(state) }
+        }
+    }
+}
+
+// 2^63, exactement représentable en flottant contrairement à i64::MAX
+const TWO_POW_63: f64 = 9223372036854775808.0;
+
+/**
+ * The integer equal to a float, if it is whole and within the range of integers.
+ */
+pub fn float_as_int(x: f64) -> Option<i64> {
+    if x.fract() == 0.0 && (-TWO_POW_63..TWO_POW_63).contains(&x) { Some(x as i64) } else { None }
+}
+
+/**
+ * Compare an integer with a float exactly, instead of rounding the integer to the nearest float.
+ */
+pub fn compare_int_float(i: i64, x: f64) -> Option<Ordering> {
+    let whole = x.trunc();
+    if x.is_nan() {
+        None
+    } else if whole >= TWO_POW_63 {
+        Some(Ordering::Less)
+    } else if whole < -TWO_POW_63 {
+        Some(Ordering::Greater)
+    } else {
+        // Même partie entière : c'est la partie fractionnaire qui départage
+        Some(i.cmp(&(whole as i64)).then(0.0.partial_cmp(&x.fract())?))
+    }
+}
+
+/**
+ * A float as text, keeping a decimal point so that it is read back as a float.
+ */
+pub fn float_to_string(x: f64) -> String {
+    if x.fract() == 0.0 && x.abs() < 1e16 { format!("{:.1}", x) } else { x.to_string() }
+}
+
 pub type Entry = Vec<Value>;
 
 pub type Table = (HashMap<String, usize>, Vec<Entry>);
@@ -35,8 +100,12 @@ pub enum Condition {
     Or(Box<Condition>, Box<Condition>),
     And(Box<Condition>, Box<Condition>),
     Less(Value, Value),
+    LessEq(Value, Value),
     Equal(Value, Value),
-    More(Value, Value)
+    NotEqual(Value, Value),
+    MoreEq(Value, Value),
+    More(Value, Value),
+    IsNull(Value) // IS NOT NULL being its negation
 }
 
 impl PartialEq for Condition {
@@ -47,26 +116,102 @@ impl PartialEq for Condition {
             (Condition::Or(c11, c12), Condition::Or(c21, c22)) |
                 (Condition::And(c11, c12), Condition::And(c21, c22)) => *c11==*c21 && *c12 == *c22,
             (Condition::Less(v11, v12), Condition::Less(v21, v22)) |
+                (Condition::LessEq(v11, v12), Condition::LessEq(v21, v22)) |
                 (Condition::Equal(v11, v12), Condition::Equal(v21, v22)) |
+                (Condition::NotEqual(v11, v12), Condition::NotEqual(v21, v22)) |
+                (Condition::MoreEq(v11, v12), Condition::MoreEq(v21, v22)) |
                 (Condition::More(v11, v12), Condition::More(v21, v22)) => v11==v21 && v12 == v22,
+            (Condition::IsNull(v1), Condition::IsNull(v2)) => v1==v2,
             (_, _) => false
         }
     }
 }
 impl Eq for Condition {}
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc
+}
+
+/**
+ * How the columns of the two sides of a union are matched.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ColumnMatching {
+    #[serde(rename = "by name")]
+    ByName,
+    #[serde(rename = "by position")]
+    ByPosition // the names of the first side are kept
+}
+
+/**
+ * How the fields of a CSV file are separated, and where the names of its columns come from.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InputDialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    pub has_header: bool,
+    pub columns: Option<Vec<String>>, // names of the columns, replacing those of the header if there is one
+    pub comment: Option<u8>, // lines starting with this character are skipped
+    pub trim: bool, // whether spaces around fields and column names are removed
+    pub nulls: Vec<String> // fields read as NULL
+}
+
+impl Default for InputDialect {
+    fn default() -> InputDialect {
+        InputDialect { delimiter: b',', quote: b'"', has_header: true, columns: None, comment: None, trim: false, nulls: vec![String::new()] }
+    }
+}
+
+/**
+ * A CSV file, and how to read it.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CsvFile {
+    pub filename: String,
+    pub dialect: InputDialect
+}
+
+impl CsvFile {
+    /**
+     * A file in the default dialect: comma-separated, with a header.
+     */
+    pub fn new(filename: String) -> CsvFile {
+        CsvFile { filename, dialect: InputDialect::default() }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(from = "ExpressionParse", into = "ExpressionParse")] 
 pub enum Expression {
     Select(Box<Expression>, Box<Condition>),
     Project(Box<Expression>, Vec<String>), // expression, column names
     Rename(Box<Expression>, Vec<String>, Vec<String>), // expression, old column names, new column names
     Except(Box<Expression>, Box<Expression>),
-    Union(Box<Expression>, Box<Expression>),
+    Union(Box<Expression>, Box<Expression>, ColumnMatching),
     Product(Box<Expression>, Box<Expression>),
-    ReadSelectProjectRename(String, Box<Condition>, Vec<String>, Vec<String>),
+    ReadSelectProjectRename(CsvFile, Box<Condition>, Vec<String>, Vec<String>),
     JoinProjectRename(Box<Expression>, Box<Expression>, Box<Condition>, Vec<String>, Vec<String>),
-    Load(String, Option<HashSet<String>>) // Optionally contains the columns to be loaded for future optimizations
+    Aggregate(Box<Expression>, Vec<String>, Vec<(AggregateFunction, String, String)>), // expression, group by columns, (function, column, output name)
+    Sort(Box<Expression>, Vec<(String, SortOrder)>), // expression, sort keys by decreasing priority
+    Limit(Box<Expression>, usize, usize), // expression, limit, offset
+    TopN(Box<Expression>, Vec<(String, SortOrder)>, usize, usize), // fused sort and limit
+    Distinct(Box<Expression>),
+    Empty(Vec<String>), // a relation without any entry, with the given columns
+    Load(CsvFile, Option<HashSet<String>>) // Optionally contains the columns to be loaded for future optimizations
 }
 
 impl PartialEq for Expression {
@@ -79,16 +224,30 @@ impl PartialEq for Expression {
                 *e1==*e2 && o1==o2
             },
             (Expression::Rename(e1, old1, new1), Expression::Rename(e2, old2, new2)) => *e1==*e2 && old1==old2 && new1 == new2,
+            (Expression::Union(e11, e12, m1), Expression::Union(e21, e22, m2)) => *e11==*e21 && *e12 == *e22 && m1 == m2,
             (Expression::Except(e11, e12), Expression::Except(e21, e22)) |
-                (Expression::Union(e11, e12), Expression::Union(e21, e22)) |
                 (Expression::Product(e11, e12), Expression::Product(e21, e22)) => *e11==*e21 && *e12 == *e22,
             (Expression::ReadSelectProjectRename(f1, c1, old1, new1), Expression::ReadSelectProjectRename(f2, c2, old2, new2)) => {
-                let mut rename1 = old1.into_iter().zip(new1.into_iter()).collect::<Vec<_>>();
-                let mut rename2 = old2.into_iter().zip(new2.into_iter()).collect::<Vec<_>>();
+                let mut rename1 = old1.iter().zip(new1).collect::<Vec<_>>();
+                let mut rename2 = old2.iter().zip(new2).collect::<Vec<_>>();
                 rename1.sort(); rename2.sort();
 
                 f1==f2 && *c1 == *c2 && rename1==rename2
             },
+            (Expression::JoinProjectRename(e11, e12, c1, old1, new1), Expression::JoinProjectRename(e21, e22, c2, old2, new2)) => {
+                let mut rename1 = old1.iter().zip(new1).collect::<Vec<_>>();
+                let mut rename2 = old2.iter().zip(new2).collect::<Vec<_>>();
+                rename1.sort(); rename2.sort();
+
+                *e11 == *e21 && *e12 == *e22 && *c1 == *c2 && rename1 == rename2
+            },
+            (Expression::Aggregate(e1, group1, aggs1), Expression::Aggregate(e2, group2, aggs2)) => *e1==*e2 && group1==group2 && aggs1==aggs2,
+            (Expression::Sort(e1, keys1), Expression::Sort(e2, keys2)) => *e1==*e2 && keys1==keys2,
+            (Expression::Limit(e1, limit1, offset1), Expression::Limit(e2, limit2, offset2)) => *e1==*e2 && limit1==limit2 && offset1==offset2,
+            (Expression::TopN(e1, keys1, limit1, offset1), Expression::TopN(e2, keys2, limit2, offset2)) =>
+                *e1==*e2 && keys1==keys2 && limit1==limit2 && offset1==offset2,
+            (Expression::Distinct(e1), Expression::Distinct(e2)) => *e1==*e2,
+            (Expression::Empty(columns1), Expression::Empty(columns2)) => columns1 == columns2,
             (Expression::Load(f1, _), Expression::Load(f2, _)) => f1 == f2,
             (_, _) => false
         }
@@ -0,0 +1,198 @@
+use crate::types::*;
+use crate::error::*;
+use crate::optimize::columns_used_in_condition;
+use crate::input::read_headers;
+use std::collections::HashSet;
+
+/**
+ * Check a query before evaluating it, by inferring the columns of every expression from the headers of the CSV files.
+ *
+ * Reports at once all the unknown columns, the duplicate columns produced by products and renamings,
+ * the renamings whose lists of names do not have the same length and the incompatible sides of set operations.
+ */
+pub fn validate(expression: &Expression) -> Result<()> {
+    let mut errors = Vec::new();
+    infer_columns(expression, &mut errors);
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(EngineError::Invalid(errors))
+    }
+}
+
+/**
+ * The columns of an expression, in order, read from the headers of the CSV files.
+ */
+pub fn columns_of(expression: &Expression) -> Result<Vec<String>> {
+    let mut errors = Vec::new();
+
+    match infer_columns(expression, &mut errors) {
+        Some(columns) if errors.is_empty() => Ok(columns),
+        _ => Err(EngineError::Invalid(errors))
+    }
+}
+
+/**
+ * Compute the columns of an expression, in order, adding the problems found to errors.
+ * Returns None when they cannot be known, the cause having already been reported.
+ */
+fn infer_columns(expression: &Expression, errors: &mut Vec<EngineError>) -> Option<Vec<String>> {
+    match expression {
+        Expression::Select(expression_from, condition) => {
+            let columns = infer_columns(expression_from, errors)?;
+            check_condition(&columns, condition, "selection", errors);
+
+            Some(columns)
+        },
+        Expression::Project(expression_from, project_on) => {
+            let columns = infer_columns(expression_from, errors)?;
+            check_known(&columns, project_on.iter(), "projection", errors);
+            check_duplicates(project_on, "projection", errors);
+
+            Some(project_on.clone())
+        },
+        Expression::Rename(expression_from, old_columns, new_columns) => {
+            let columns = infer_columns(expression_from, errors)?;
+
+            rename(columns, old_columns, new_columns, "renaming", errors)
+        },
+        Expression::Product(expr1, expr2) => {
+            // On continue sur le second côté même si le premier est invalide, pour tout signaler
+            let columns1 = infer_columns(expr1, errors);
+            let columns2 = infer_columns(expr2, errors);
+
+            product(columns1?, columns2?, "product", errors)
+        },
+        Expression::Except(expr1, expr2) => {
+            let columns1 = infer_columns(expr1, errors);
+            let columns2 = infer_columns(expr2, errors);
+            let columns1 = columns1?;
+            check_same_columns(&columns1, &columns2?, "minus", errors);
+
+            Some(columns1)
+        },
+        Expression::Union(expr1, expr2, matching) => {
+            let columns1 = infer_columns(expr1, errors);
+            let columns2 = infer_columns(expr2, errors);
+            let (columns1, columns2) = (columns1?, columns2?);
+
+            match matching {
+                ColumnMatching::ByName => check_same_columns(&columns1, &columns2, "union", errors),
+                ColumnMatching::ByPosition if columns1.len() != columns2.len() =>
+                    errors.push(EngineError::SchemaMismatch(format!("union by position: the left side has {} columns but the right side has {}", columns1.len(), columns2.len()))),
+                ColumnMatching::ByPosition => ()
+            }
+
+            Some(columns1)
+        },
+        Expression::Aggregate(expression_from, group_by, aggregates) => {
+            let columns = infer_columns(expression_from, errors)?;
+            check_known(&columns, group_by.iter(), "group by", errors);
+            check_known(&columns, aggregates.iter().map(|(_, column, _)| column).filter(|column| *column != "*"), "aggregate", errors);
+
+            let output = group_by.iter().cloned().chain(aggregates.iter().map(|(_, _, name)| name.clone())).collect::<Vec<_>>();
+            check_duplicates(&output, "aggregate", errors);
+
+            Some(output)
+        },
+        Expression::Sort(expression_from, keys) | Expression::TopN(expression_from, keys, _, _) => {
+            let columns = infer_columns(expression_from, errors)?;
+            check_known(&columns, keys.iter().map(|(column, _)| column), "sort", errors);
+
+            Some(columns)
+        },
+        Expression::Limit(expression_from, _, _) | Expression::Distinct(expression_from) => infer_columns(expression_from, errors),
+        Expression::Empty(columns) => {
+            check_duplicates(columns, "empty", errors);
+
+            Some(columns.clone())
+        },
+        Expression::Load(file, _) => load(file, errors),
+        Expression::ReadSelectProjectRename(file, condition, old_columns, new_columns) => {
+            let columns = load(file, errors)?;
+            check_condition(&columns, condition, "rspr condition", errors);
+            check_known(&columns, old_columns.iter(), "rspr", errors);
+
+            rename(old_columns.clone(), old_columns, new_columns, "rspr", errors)
+        },
+        Expression::JoinProjectRename(expr1, expr2, condition, old_columns, new_columns) => {
+            let columns1 = infer_columns(expr1, errors);
+            let columns2 = infer_columns(expr2, errors);
+            let columns = product(columns1?, columns2?, "jpr", errors)?;
+            check_condition(&columns, condition, "jpr condition", errors);
+            check_known(&columns, old_columns.iter(), "jpr", errors);
+
+            rename(old_columns.clone(), old_columns, new_columns, "jpr", errors)
+        }
+    }
+}
+
+fn load(file: &CsvFile, errors: &mut Vec<EngineError>) -> Option<Vec<String>> {
+    match read_headers(file) {
+        Ok(columns) => Some(columns),
+        Err(e) => {
+            errors.push(e);
+            None
+        }
+    }
+}
+
+fn check_known<'a>(columns: &[String], used: impl Iterator<Item = &'a String>, context: &str, errors: &mut Vec<EngineError>) {
+    for column in used {
+        if !columns.contains(column) {
+            errors.push(EngineError::unknown_column(column, context));
+        }
+    }
+}
+
+fn check_condition(columns: &[String], condition: &Condition, context: &str, errors: &mut Vec<EngineError>) {
+    let mut used = HashSet::new();
+    columns_used_in_condition(condition, &mut used);
+
+    let mut used = used.into_iter().collect::<Vec<_>>();
+    used.sort();
+    check_known(columns, used.iter(), context, errors);
+}
+
+fn check_duplicates(columns: &[String], context: &str, errors: &mut Vec<EngineError>) {
+    let mut seen = HashSet::new();
+    let mut duplicates = columns.iter().filter(|column| !seen.insert(*column)).cloned().collect::<Vec<_>>();
+    duplicates.dedup();
+
+    if !duplicates.is_empty() {
+        errors.push(EngineError::SchemaMismatch(format!("{}: duplicate output columns {:?}", context, duplicates)));
+    }
+}
+
+fn check_same_columns(columns1: &[String], columns2: &[String], operation: &str, errors: &mut Vec<EngineError>) {
+    let only_left = columns1.iter().filter(|column| !columns2.contains(column)).cloned().collect::<Vec<_>>();
+    let only_right = columns2.iter().filter(|column| !columns1.contains(column)).cloned().collect::<Vec<_>>();
+
+    if !only_left.is_empty() || !only_right.is_empty() {
+        errors.push(EngineError::SchemaMismatch(format!("{}: columns {:?} only appear on the left and columns {:?} only on the right", operation, only_left, only_right)));
+    }
+}
+
+fn product(mut columns1: Vec<String>, columns2: Vec<String>, context: &str, errors: &mut Vec<EngineError>) -> Option<Vec<String>> {
+    columns1.extend(columns2);
+    check_duplicates(&columns1, context, errors);
+
+    Some(columns1)
+}
+
+fn rename(columns: Vec<String>, old_columns: &[String], new_columns: &[String], context: &str, errors: &mut Vec<EngineError>) -> Option<Vec<String>> {
+    if old_columns.len() != new_columns.len() {
+        errors.push(EngineError::SchemaMismatch(format!("{}: renaming {} columns into {} names", context, old_columns.len(), new_columns.len())));
+        return None;
+    }
+    check_known(&columns, old_columns.iter(), context, errors);
+
+    let renamed = columns.into_iter().map(|column| match old_columns.iter().position(|old| *old == column) {
+        Some(i) => new_columns[i].clone(),
+        None => column
+    }).collect::<Vec<_>>();
+    check_duplicates(&renamed, context, errors);
+
+    Some(renamed)
+}